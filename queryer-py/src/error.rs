@@ -0,0 +1,62 @@
+// queryer 的错误映射成 Python 异常：
+// QueryerError 是所有异常的基类，ParseError / FetchError / UnsupportedError 从它继承
+
+use pyo3::{create_exception, exceptions::PyException, prelude::*};
+use queryer::ErrorKind;
+use std::fmt::Display;
+
+create_exception!(queryer_py, QueryerError, PyException);
+create_exception!(queryer_py, ParseError, QueryerError);
+create_exception!(queryer_py, FetchError, QueryerError);
+create_exception!(queryer_py, UnsupportedError, QueryerError);
+
+/// 按错误的分类生成异常，异常上带着出错的 sql
+/// ParseError 还有 fragment / line / column，找不到时是 None
+pub fn to_py_err(py: Python, sql: &str, e: anyhow::Error) -> PyErr {
+    let message = format!("{:#}", e);
+    let kind = ErrorKind::of(&e);
+    let err = match kind {
+        ErrorKind::Parse => ParseError::new_err(message),
+        ErrorKind::Fetch => FetchError::new_err(message),
+        ErrorKind::Unsupported => UnsupportedError::new_err(message),
+        ErrorKind::Forbidden | ErrorKind::Other => QueryerError::new_err(message),
+    };
+    let parse_error = match kind {
+        ErrorKind::Parse => e.downcast_ref::<queryer::ParseError>(),
+        _ => None,
+    };
+    match set_attrs(py, &err, sql, kind, parse_error) {
+        Ok(_) => err,
+        Err(attr_err) => attr_err,
+    }
+}
+
+fn set_attrs(
+    py: Python,
+    err: &PyErr,
+    sql: &str,
+    kind: ErrorKind,
+    parse_error: Option<&queryer::ParseError>,
+) -> PyResult<()> {
+    let instance = err.instance(py);
+    instance.setattr("sql", sql)?;
+    if kind == ErrorKind::Parse {
+        instance.setattr("fragment", parse_error.and_then(|e| e.fragment.clone()))?;
+        instance.setattr("line", parse_error.and_then(|e| e.line))?;
+        instance.setattr("column", parse_error.and_then(|e| e.column))?;
+    }
+    Ok(())
+}
+
+/// 和 sql 无关的错误，比如转换结果时出错
+pub fn queryer_err(e: impl Display) -> PyErr {
+    QueryerError::new_err(e.to_string())
+}
+
+pub fn add_exceptions(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("QueryerError", py.get_type::<QueryerError>())?;
+    m.add("ParseError", py.get_type::<ParseError>())?;
+    m.add("FetchError", py.get_type::<FetchError>())?;
+    m.add("UnsupportedError", py.get_type::<UnsupportedError>())?;
+    Ok(())
+}
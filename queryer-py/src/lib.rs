@@ -4,6 +4,11 @@
 // sql = queryer_py.example_sql()
 // print(sql)
 // print(queryer_py.query(sql, 'csv'))
+// output 也可以是 json / dict / arrow / pandas / polars，后三种需要安装对应的 Python 包
+// 出错时抛出 queryer_py.QueryerError，具体是 ParseError / FetchError / UnsupportedError 之一
+// asyncio 里用 await queryer_py.query_async(sql, 'pandas')，多个查询可以并发执行
+// queryer_py.register("people", df) 之后可以 SELECT * FROM people WHERE ...
+// 整个进程共用一个会话，queryer_py.query("SET null_values = 'NA'") 对之后的查询都生效
 // Output:
 // SELECT location name, total_cases, new_cases, total_deaths, new_deaths FROM https://raw.githubusercontent.com/owid/covid-19-data/master/public/data/latest/owid-covid-latest.csv WHERE new_deaths > 10 ORDER BY new_cases DESC LIMIT 2
 // name,total_cases,new_cases,total_deaths,new_deaths
@@ -11,7 +16,30 @@
 // Italy,25977012.0,4122.0,191370.0,21.0
 
 
-use pyo3::{exceptions, prelude::*};
+mod error;
+mod output;
+mod table;
+
+use anyhow::anyhow;
+use error::{add_exceptions, to_py_err};
+use lazy_static::lazy_static;
+use output::Output;
+use polars::prelude::*;
+use pyo3::{exceptions, prelude::*, types::PyTuple};
+use queryer::Session;
+
+lazy_static! {
+    // 所有查询共用的会话，http 配置在第一次查询时从环境变量读取，SET 修改的选项一直有效
+    static ref SESSION: anyhow::Result<Session> = Session::from_env();
+}
+
+/// 共用的会话，克隆出来的 Session 和它共享 SET 修改的选项
+fn session(py: Python, sql: &str) -> PyResult<Session> {
+    match &*SESSION {
+        Ok(session) => Ok(session.clone()),
+        Err(e) => Err(to_py_err(py, sql, anyhow!("{:#}", e))),
+    }
+}
 
 #[pyfunction]
 pub fn example_sql() -> PyResult<String> {
@@ -19,21 +47,172 @@ pub fn example_sql() -> PyResult<String> {
 }
 
 #[pyfunction]
-pub fn query(sql: &str, output: Option<&str>) -> PyResult<String> {
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let data = rt.block_on(async { queryer::query(sql).await.unwrap() });
-    match output {
-        Some("csv") | None => Ok(data.to_csv().unwrap()),
-        Some(v) => Err(exceptions::PyTypeError::new_err(format!(
-            "Output type {} not supported",
+pub fn query(py: Python, sql: &str, output: Option<&str>) -> PyResult<PyObject> {
+    // 先检查 output，不支持的格式不用跑查询
+    let output: Output = output.unwrap_or("csv").parse()?;
+    let session = session(py, sql)?;
+    // 所有查询共用 pyo3-asyncio 的 runtime，不用每次都创建
+    // 查询时释放 GIL，否则 polars 的线程调用 Python 自定义函数时会死锁，其它 Python 线程也能继续运行
+    let rt = pyo3_asyncio::tokio::get_runtime();
+    let data = py
+        .allow_threads(|| rt.block_on(session.query(sql)))
+        .map_err(|e| to_py_err(py, sql, e))?;
+    output.convert(py, &data)
+}
+
+// async def handler():
+//     df = await queryer_py.query_async(sql, "pandas")
+/// 和 query 一样，但返回 asyncio 可以 await 的对象，查询在共用的 runtime 里执行，不占用 GIL
+#[pyfunction]
+pub fn query_async<'py>(
+    py: Python<'py>,
+    sql: String,
+    output: Option<&str>,
+) -> PyResult<&'py PyAny> {
+    let output: Output = output.unwrap_or("csv").parse()?;
+    let session = session(py, &sql)?;
+    pyo3_asyncio::tokio::future_into_py(py, async move {
+        let result = session.query(&sql).await;
+        // 只在转换结果时拿 GIL
+        Python::with_gil(|py| match result {
+            Ok(data) => output.convert(py, &data),
+            Err(e) => Err(to_py_err(py, &sql, e)),
+        })
+    })
+}
+
+// import queryer_py
+// queryer_py.register_function("shout", lambda s: s.upper() if s else s, ["str"], "str")
+// queryer_py.query("SELECT shout(location) AS location FROM ...")
+/// 把 Python 函数注册成 SQL 里的标量函数，args 和 returns 是 "int" / "float" / "str" / "bool"
+/// 函数按行调用，参数和返回值中的 None 对应 null
+#[pyfunction]
+pub fn register_function(
+    name: &str,
+    func: PyObject,
+    args: Vec<&str>,
+    returns: &str,
+) -> PyResult<()> {
+    if args.is_empty() {
+        return Err(exceptions::PyValueError::new_err(
+            "function should have at least one argument",
+        ));
+    }
+    let arg_types = args
+        .iter()
+        .map(|v| to_dtype(v))
+        .collect::<PyResult<Vec<_>>>()?;
+    let return_type = to_dtype(returns)?;
+
+    let fn_name = name.to_owned();
+    let dtype = return_type.clone();
+    queryer::register_function(name, arg_types, return_type, move |series| {
+        Python::with_gil(|py| {
+            let columns = series
+                .iter()
+                .map(|s| to_py_values(py, s))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let len = columns.iter().map(|v| v.len()).max().unwrap_or(0);
+            let results = (0..len)
+                .map(|i| {
+                    let row = columns.iter().map(|c| c[i].clone_ref(py));
+                    func.call1(py, PyTuple::new(py, row))
+                })
+                .collect::<PyResult<Vec<_>>>()?;
+            from_py_values(py, &fn_name, &results, &dtype)
+        })
+    });
+    Ok(())
+}
+
+#[pyfunction]
+pub fn unregister_function(name: &str) {
+    queryer::unregister_function(name)
+}
+
+// import pandas as pd
+// queryer_py.register("people", pd.DataFrame({"name": ["Tyr", "Lindsey"], "age": [18, 30]}))
+// queryer_py.query("SELECT name FROM people WHERE age > 20", "pandas")
+/// 把 pandas / polars DataFrame 或者 pyarrow Table / RecordBatch 注册成表，需要安装 pyarrow
+/// 注册时就把数据转换好，之后的查询不用再转换。可以用 JOIN ... ON 和其它表或数据源做等值连接
+#[pyfunction]
+pub fn register(py: Python, name: &str, data: &PyAny) -> PyResult<()> {
+    let df = table::to_data_frame(py, data)?;
+    queryer::register_table(name, df);
+    Ok(())
+}
+
+#[pyfunction]
+pub fn unregister(name: &str) {
+    queryer::unregister_table(name)
+}
+
+fn to_dtype(name: &str) -> PyResult<DataType> {
+    match name {
+        "int" => Ok(DataType::Int64),
+        "float" => Ok(DataType::Float64),
+        "str" => Ok(DataType::Utf8),
+        "bool" => Ok(DataType::Boolean),
+        v => Err(exceptions::PyTypeError::new_err(format!(
+            "Type {} not supported, expect int / float / str / bool",
             v
         ))),
     }
 }
 
+fn to_py_values(py: Python, s: &Series) -> anyhow::Result<Vec<PyObject>> {
+    let values = match s.dtype() {
+        DataType::Int64 => s.i64()?.into_iter().map(|v| v.into_py(py)).collect(),
+        DataType::Float64 => s.f64()?.into_iter().map(|v| v.into_py(py)).collect(),
+        DataType::Utf8 => s.utf8()?.into_iter().map(|v| v.into_py(py)).collect(),
+        DataType::Boolean => s.bool()?.into_iter().map(|v| v.into_py(py)).collect(),
+        v => return Err(anyhow!("Type {:?} not supported", v)),
+    };
+    Ok(values)
+}
+
+fn from_py_values(
+    py: Python,
+    name: &str,
+    values: &[PyObject],
+    dtype: &DataType,
+) -> anyhow::Result<Series> {
+    let mut series = match dtype {
+        DataType::Int64 => values
+            .iter()
+            .map(|v| v.extract::<Option<i64>>(py))
+            .collect::<PyResult<Int64Chunked>>()?
+            .into_series(),
+        DataType::Float64 => values
+            .iter()
+            .map(|v| v.extract::<Option<f64>>(py))
+            .collect::<PyResult<Float64Chunked>>()?
+            .into_series(),
+        DataType::Utf8 => values
+            .iter()
+            .map(|v| v.extract::<Option<String>>(py))
+            .collect::<PyResult<Utf8Chunked>>()?
+            .into_series(),
+        DataType::Boolean => values
+            .iter()
+            .map(|v| v.extract::<Option<bool>>(py))
+            .collect::<PyResult<BooleanChunked>>()?
+            .into_series(),
+        v => return Err(anyhow!("Type {:?} not supported", v)),
+    };
+    series.rename(name);
+    Ok(series)
+}
+
 #[pymodule]
-fn queryer_py(_py: Python, m: &PyModule) -> PyResult<()> {
+fn queryer_py(py: Python, m: &PyModule) -> PyResult<()> {
+    add_exceptions(py, m)?;
     m.add_function(wrap_pyfunction!(query, m)?)?;
+    m.add_function(wrap_pyfunction!(query_async, m)?)?;
     m.add_function(wrap_pyfunction!(example_sql, m)?)?;
+    m.add_function(wrap_pyfunction!(register_function, m)?)?;
+    m.add_function(wrap_pyfunction!(unregister_function, m)?)?;
+    m.add_function(wrap_pyfunction!(register, m)?)?;
+    m.add_function(wrap_pyfunction!(unregister, m)?)?;
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,125 @@
+// 把查询结果转换成 Python 里常用的数据结构
+// arrow / pandas / polars 通过 Arrow C Data Interface 交给 pyarrow，列的内存不需要复制
+
+use crate::error::queryer_err;
+use anyhow::anyhow;
+use arrow::array::Array;
+use polars::prelude::*;
+use pyo3::{exceptions, prelude::*, types::PyDict};
+use queryer::DataSet;
+use std::str::FromStr;
+
+/// query 支持的输出格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    Csv,
+    Json,
+    Dict,
+    Arrow,
+    Pandas,
+    Polars,
+}
+
+impl FromStr for Output {
+    type Err = PyErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "dict" => Ok(Self::Dict),
+            "arrow" | "pyarrow" => Ok(Self::Arrow),
+            "pandas" => Ok(Self::Pandas),
+            "polars" => Ok(Self::Polars),
+            v => Err(exceptions::PyTypeError::new_err(format!(
+                "Output type {} not supported, expect csv / json / dict / arrow / pandas / polars",
+                v
+            ))),
+        }
+    }
+}
+
+impl Output {
+    pub fn convert(&self, py: Python, data: &DataSet) -> PyResult<PyObject> {
+        let result = match self {
+            Self::Csv => data.to_csv().map_err(queryer_err)?.into_py(py),
+            Self::Json => to_json(data)?.into_py(py),
+            Self::Dict => to_dict(py, data)?.into_py(py),
+            Self::Arrow => to_arrow(py, data)?.into_py(py),
+            Self::Pandas => to_arrow(py, data)?.call_method0("to_pandas")?.into_py(py),
+            Self::Polars => {
+                let table = to_arrow(py, data)?;
+                py.import("polars")?
+                    .call_method1("from_arrow", (table,))?
+                    .into_py(py)
+            }
+        };
+        Ok(result)
+    }
+}
+
+/// 行对象组成的 json 数组
+fn to_json(data: &DataSet) -> PyResult<String> {
+    let rows = data.to_ndjson().map_err(queryer_err)?;
+    Ok(format!("[{}]", rows.lines().collect::<Vec<_>>().join(",")))
+}
+
+/// 列名 -> 值的列表，null 对应 None
+fn to_dict<'py>(py: Python<'py>, data: &DataSet) -> PyResult<&'py PyDict> {
+    let dict = PyDict::new(py);
+    for s in data.get_columns() {
+        let values = super::to_py_values(py, &to_python_dtype(s)?).map_err(queryer_err)?;
+        dict.set_item(s.name(), values)?;
+    }
+    Ok(dict)
+}
+
+/// 转成 to_py_values 支持的类型：整数都用 i64，浮点数都用 f64，其它类型用字符串表示
+fn to_python_dtype(s: &Series) -> PyResult<Series> {
+    let dtype = match s.dtype() {
+        DataType::Int64 | DataType::Float64 | DataType::Utf8 | DataType::Boolean => {
+            return Ok(s.clone())
+        }
+        DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64 => DataType::Int64,
+        DataType::Float32 => DataType::Float64,
+        _ => DataType::Utf8,
+    };
+    s.cast_with_dtype(&dtype).map_err(queryer_err)
+}
+
+/// 生成 pyarrow.Table，每一列合并成一个 chunk 后通过 C Data Interface 导出
+fn to_arrow<'py>(py: Python<'py>, data: &DataSet) -> PyResult<&'py PyAny> {
+    let pa = py.import("pyarrow")?;
+    let array_type = pa.getattr("Array")?;
+    let mut names = Vec::with_capacity(data.width());
+    let mut arrays = Vec::with_capacity(data.width());
+    for s in data.get_columns() {
+        // pyarrow 不认识 polars 的 categorical，先转成字符串
+        let s = match s.dtype() {
+            DataType::Categorical => s.cast_with_dtype(&DataType::Utf8).map_err(queryer_err)?,
+            _ => s.clone(),
+        }
+        .rechunk();
+        let chunk = s
+            .chunks()
+            .first()
+            .ok_or_else(|| queryer_err(anyhow!("column {} has no data", s.name())))?;
+        let (array_ptr, schema_ptr) = chunk.to_raw().map_err(queryer_err)?;
+        let array =
+            array_type.call_method1("_import_from_c", (array_ptr as usize, schema_ptr as usize));
+        // pyarrow 导入时会把数据移走，这里只回收导出时分配的两个结构体
+        // 导入失败时数据还在，drop 时会调用 release 释放
+        unsafe { arrow::ffi::ArrowArray::try_from_raw(array_ptr, schema_ptr) }
+            .map_err(queryer_err)?;
+        names.push(s.name().to_owned());
+        arrays.push(array?);
+    }
+    pa.getattr("Table")?
+        .call_method1("from_arrays", (arrays, names))
+}
@@ -0,0 +1,90 @@
+// 把 Python 里的 pandas / polars DataFrame 或者 pyarrow Table 转换成 polars 的 DataFrame
+// 都先转成 pyarrow.Table，再通过 Arrow C Data Interface 导入，列的内存不需要复制
+
+use crate::error::queryer_err;
+use arrow::{
+    array::{make_array_from_raw, ArrayRef},
+    ffi::ArrowArray,
+};
+use polars::prelude::*;
+use pyo3::{
+    prelude::*,
+    types::{IntoPyDict, PyType},
+};
+use std::convert::TryFrom;
+
+pub fn to_data_frame(py: Python, data: &PyAny) -> PyResult<DataFrame> {
+    let table = to_arrow_table(py, data)?;
+    let names: Vec<String> = table.getattr("column_names")?.extract()?;
+    let columns = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| to_series(py, name, table.call_method1("column", (i,))?))
+        .collect::<PyResult<Vec<_>>>()?;
+    DataFrame::new(columns).map_err(queryer_err)
+}
+
+/// pyarrow.Table / RecordBatch 直接用，polars 用 to_arrow()，pandas 等其它对象交给 pyarrow.table()
+fn to_arrow_table<'py>(py: Python<'py>, data: &'py PyAny) -> PyResult<&'py PyAny> {
+    let pa = py.import("pyarrow")?;
+    let table: &PyType = pa.getattr("Table")?.downcast()?;
+    let batch: &PyType = pa.getattr("RecordBatch")?.downcast()?;
+    if table.is_instance(data)? {
+        Ok(data)
+    } else if batch.is_instance(data)? {
+        table.call_method1("from_batches", (vec![data],))
+    } else if data.hasattr("to_arrow")? {
+        data.call_method0("to_arrow")
+    } else {
+        pa.call_method1("table", (data,))
+    }
+}
+
+/// pyarrow.ChunkedArray 的每个 chunk 导入成 arrow 的数组，组成一个 Series
+fn to_series(py: Python, name: &str, column: &PyAny) -> PyResult<Series> {
+    let column = to_polars_type(py, column)?;
+    let mut chunks: Vec<&PyAny> = column.getattr("chunks")?.extract()?;
+    // 空表可能没有 chunk，polars 至少需要一个
+    if chunks.is_empty() {
+        let kwargs = [("type", column.getattr("type")?)].into_py_dict(py);
+        let empty =
+            py.import("pyarrow")?
+                .call_method("array", (Vec::<i64>::new(),), Some(kwargs))?;
+        chunks.push(empty);
+    }
+    let arrays = chunks
+        .into_iter()
+        .map(import_array)
+        .collect::<PyResult<Vec<_>>>()?;
+    Series::try_from((name, arrays)).map_err(queryer_err)
+}
+
+/// polars 不支持的类型先在 pyarrow 里转换：
+/// 时间戳统一成毫秒（pandas 默认是纳秒），字典编码的列（pandas 的 category）解码
+fn to_polars_type<'py>(py: Python<'py>, column: &'py PyAny) -> PyResult<&'py PyAny> {
+    let types = py.import("pyarrow.types")?;
+    let dtype = column.getattr("type")?;
+    if types.call_method1("is_timestamp", (dtype,))?.is_true()? {
+        let target = py.import("pyarrow")?.call_method1("timestamp", ("ms",))?;
+        let kwargs = [("safe", false)].into_py_dict(py);
+        return column.call_method("cast", (target,), Some(kwargs));
+    }
+    if types.call_method1("is_dictionary", (dtype,))?.is_true()? {
+        return column.call_method1("cast", (dtype.getattr("value_type")?,));
+    }
+    Ok(column)
+}
+
+fn import_array(array: &PyAny) -> PyResult<ArrayRef> {
+    // 先在 Rust 这边分配好两个结构体，让 pyarrow 把数组导出到里面
+    let (array_ptr, schema_ptr) = ArrowArray::into_raw(unsafe { ArrowArray::empty() });
+    let exported = array.call_method1("_export_to_c", (array_ptr as usize, schema_ptr as usize));
+    match exported {
+        Ok(_) => unsafe { make_array_from_raw(array_ptr, schema_ptr) }.map_err(queryer_err),
+        // 导出失败时两个结构体还是空的，收回后直接丢掉
+        Err(e) => {
+            unsafe { ArrowArray::try_from_raw(array_ptr, schema_ptr) }.map_err(queryer_err)?;
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,602 @@
+// 将queryer项目作为lib，通过 http 给非 Rust 的服务使用: RUST_LOG=info cargo run --quiet
+// Test:
+// curl -s localhost:3000/query -H 'Accept: text/csv' \
+//   -d '{"sql": "SELECT location, new_deaths FROM https://raw.githubusercontent.com/owid/covid-19-data/master/public/data/latest/owid-covid-latest.csv WHERE new_deaths > $1 LIMIT 2", "params": [10]}'
+// Output:
+// location,new_deaths
+// Finland,14.0
+// Italy,21.0
+//
+// Accept 决定返回格式：
+// - application/json(默认): 行对象组成的数组
+// - application/x-ndjson: 每行一个 json 对象
+// - text/csv
+// - application/vnd.apache.arrow.file: Arrow IPC 文件
+// - application/vnd.apache.arrow.stream: Arrow IPC 流，每一批是一个消息
+// 结果按 Session::query_stream 返回的批次流式返回，出错时返回 {"error": {"code": ..., "message": ...}}
+// 已经开始返回结果后才出错时，json 数组的最后一个元素、ndjson / csv 的最后一行是这个错误对象，
+// Arrow IPC 没法附带错误，直接中断连接
+//
+// 默认只允许 SELECT / DIFF 查询远程数据源，下面的环境变量设为 1 / true 后才允许：
+// - QUERYER_SERVER_ALLOW_WRITES: INSERT / COPY 写文件
+// - QUERYER_SERVER_ALLOW_LOCAL_FILES: 读取 file:// / sqlite:// 等服务器上的文件
+// - QUERYER_SERVER_ALLOW_SET: SET / SHOW
+// 远程数据源只能访问 QUERYER_SERVER_ALLOWED_HOSTS 里的主机，用逗号分隔，比如
+// `raw.githubusercontent.com,*.example.com`，`*` 表示不限制；没有设置时拒绝所有 http / postgres 主机
+
+use anyhow::{anyhow, Result};
+use arrow::{
+    datatypes::Schema,
+    ipc::writer::{FileWriter, StreamWriter},
+    record_batch::RecordBatch,
+};
+use axum::{handler::post, Router};
+use bytes::Bytes;
+use futures::stream::{self, Stream, StreamExt};
+use hyper::{
+    header::{HeaderValue, ACCEPT, CONTENT_TYPE},
+    Body, HeaderMap, Response, StatusCode,
+};
+use queryer::{DataSet, ErrorKind, Param, Permissions, Session};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    env,
+    io::{self, Write},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tracing::{info, warn};
+
+/// POST /query 的请求体
+/// params 可以是数组（对应 $1、$2 ...）或者对象（对应 $name）
+/// dialect 是 tyr（默认）/ ansi / postgres / mysql
+#[derive(Debug, Deserialize)]
+struct QueryRequest {
+    sql: String,
+    #[serde(default)]
+    params: Option<Value>,
+    #[serde(default)]
+    case_insensitive: bool,
+    #[serde(default)]
+    dialect: Option<String>,
+}
+
+/// 返回结果的格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    NdJson,
+    Csv,
+    Ipc,
+    IpcStream,
+}
+
+impl Format {
+    /// 按 Accept 里出现的顺序找第一个支持的格式，没有 Accept 时用 json
+    fn from_accept(accept: Option<&str>) -> Option<Self> {
+        let accept = match accept {
+            Some(v) if !v.trim().is_empty() => v,
+            _ => return Some(Self::Json),
+        };
+        accept.split(',').find_map(|v| {
+            let mime = v.split(';').next().unwrap_or_default().trim();
+            match mime.to_lowercase().as_str() {
+                "application/json" | "application/*" | "*/*" => Some(Self::Json),
+                "application/x-ndjson" | "application/jsonlines" => Some(Self::NdJson),
+                "text/csv" | "text/*" => Some(Self::Csv),
+                "application/vnd.apache.arrow.file" => Some(Self::Ipc),
+                "application/vnd.apache.arrow.stream" => Some(Self::IpcStream),
+                _ => None,
+            }
+        })
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::NdJson => "application/x-ndjson",
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ipc => "application/vnd.apache.arrow.file",
+            Self::IpcStream => "application/vnd.apache.arrow.stream",
+        }
+    }
+}
+
+/// 以 json 形式返回给调用方的错误
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, code: &'static str, message: impl ToString) -> Self {
+        Self {
+            status,
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    /// 按 queryer 的错误分类决定状态码
+    fn from_query(e: anyhow::Error) -> Self {
+        let (status, code) = match ErrorKind::of(&e) {
+            ErrorKind::Parse => (StatusCode::BAD_REQUEST, "parse_error"),
+            ErrorKind::Fetch => (StatusCode::BAD_GATEWAY, "fetch_failed"),
+            ErrorKind::Unsupported => (StatusCode::UNPROCESSABLE_ENTITY, "unsupported"),
+            ErrorKind::Forbidden => (StatusCode::FORBIDDEN, "forbidden"),
+            ErrorKind::Other => (StatusCode::BAD_REQUEST, "query_failed"),
+        };
+        Self::new(status, code, format!("{:#}", e))
+    }
+
+    fn to_json(&self) -> Value {
+        json!({ "error": { "code": self.code, "message": self.message } })
+    }
+
+    fn into_response(self) -> Response<Body> {
+        Response::builder()
+            .status(self.status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(self.to_json().to_string()))
+            .unwrap()
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // 初始化 tracing
+    tracing_subscriber::fmt::init();
+
+    // 构建路由
+    let app = Router::new().route("/query", post(query));
+
+    // 运行 web 服务器，地址可以用 QUERYER_SERVER_ADDR 修改
+    let addr: SocketAddr = env::var("QUERYER_SERVER_ADDR")
+        .unwrap_or_else(|_| "127.0.0.1:3000".into())
+        .parse()
+        .expect("invalid QUERYER_SERVER_ADDR");
+    info!("listening on {}", addr);
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await
+        .unwrap();
+}
+
+/// 请求体自己解析，这样请求格式不对时也能返回 json 错误
+async fn query(headers: HeaderMap, body: Bytes) -> Response<Body> {
+    match run(headers, body).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            warn!("query failed: {} {}", e.code, e.message);
+            e.into_response()
+        }
+    }
+}
+
+async fn run(headers: HeaderMap, body: Bytes) -> Result<Response<Body>, ApiError> {
+    let accept = headers.get(ACCEPT).and_then(|v| v.to_str().ok());
+    let format = Format::from_accept(accept).ok_or_else(|| {
+        ApiError::new(
+            StatusCode::NOT_ACCEPTABLE,
+            "not_acceptable",
+            format!("unsupported Accept: {}", accept.unwrap_or_default()),
+        )
+    })?;
+
+    let req: QueryRequest = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, "invalid_request", e))?;
+    let session =
+        session(&req).map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, "invalid_request", e))?;
+
+    info!("query: {}", req.sql);
+    let mut batches = Box::pin(session.query_stream(req.sql));
+    // 第一批出错时（SQL 不对、数据源读不到等）还能返回错误的状态码
+    let first = batches
+        .next()
+        .await
+        .unwrap_or_else(|| Err(anyhow!("query returned no result")))
+        .map_err(ApiError::from_query)?;
+
+    let batches = stream::once(async move { Ok(first) }).chain(batches);
+    let body = match format {
+        Format::Ipc | Format::IpcStream => Body::wrap_stream(encode_ipc(batches, format)),
+        _ => Body::wrap_stream(encode_batches(batches, format)),
+    };
+
+    let mut resp = Response::new(body);
+    resp.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    Ok(resp)
+}
+
+/// 用请求里的参数生成 Session
+fn session(req: &QueryRequest) -> Result<Session> {
+    let mut session = Session::from_env()?
+        .with_case_insensitive(req.case_insensitive)
+        .with_permissions(permissions(|name| env::var(name).ok()));
+    if let Some(dialect) = &req.dialect {
+        session = session.with_dialect(dialect.parse()?);
+    }
+    match &req.params {
+        None | Some(Value::Null) => {}
+        Some(Value::Array(values)) => {
+            let params = values.iter().map(to_param).collect::<Result<Vec<_>>>()?;
+            session = session.with_params(params);
+        }
+        Some(Value::Object(values)) => {
+            for (name, value) in values {
+                session = session.with_param(name.as_str(), to_param(value)?);
+            }
+        }
+        Some(v) => return Err(anyhow!("params must be an array or an object, got {}", v)),
+    }
+    Ok(session)
+}
+
+/// 服务默认只读，写文件、读本机文件和 SET 需要用环境变量打开，远程主机默认全部拒绝
+fn permissions(var: impl Fn(&str) -> Option<String>) -> Permissions {
+    let enabled = |name| matches!(var(name).as_deref(), Some("1") | Some("true"));
+    let hosts = var("QUERYER_SERVER_ALLOWED_HOSTS").unwrap_or_default();
+    Permissions {
+        writes: enabled("QUERYER_SERVER_ALLOW_WRITES"),
+        local_files: enabled("QUERYER_SERVER_ALLOW_LOCAL_FILES"),
+        settings: enabled("QUERYER_SERVER_ALLOW_SET"),
+        hosts: Some(
+            hosts
+                .split(',')
+                .map(|v| v.trim().to_owned())
+                .filter(|v| !v.is_empty())
+                .collect(),
+        ),
+    }
+}
+
+fn to_param(value: &Value) -> Result<Param> {
+    match value {
+        Value::Null => Ok(Param::Null),
+        Value::Bool(v) => Ok(Param::Bool(*v)),
+        Value::Number(v) => match (v.as_i64(), v.as_f64()) {
+            (Some(n), _) => Ok(Param::Int(n)),
+            (None, Some(f)) => Ok(Param::Float(f)),
+            _ => Err(anyhow!("unsupported number parameter {}", v)),
+        },
+        Value::String(v) => Ok(Param::Str(v.clone())),
+        v => Err(anyhow!("unsupported parameter {}", v)),
+    }
+}
+
+/// 一批一批地写 IPC，每写完一批就把生成的字节作为 body 的一段返回，不用等整个结果编码完
+/// 中途出错时返回 Err，hyper 会中断连接，客户端能发现结果不完整
+fn encode_ipc(
+    batches: impl Stream<Item = Result<DataSet>> + Send + 'static,
+    format: Format,
+) -> impl Stream<Item = Result<Vec<u8>>> + Send + 'static {
+    let state = (Box::pin(batches), None::<IpcWriter>, false);
+    stream::unfold(state, |(mut batches, mut writer, done)| async move {
+        if done {
+            return None;
+        }
+        let chunk = match batches.next().await {
+            Some(batch) => batch.and_then(|ds| {
+                if writer.is_none() {
+                    writer = Some(IpcWriter::new(&ds, format)?);
+                }
+                writer.as_mut().unwrap().write(&ds)
+            }),
+            // 查询结果至少有一批，这里的 writer 一定已经创建
+            None => match writer.as_mut() {
+                Some(writer) => writer.finish(),
+                None => Ok(Vec::new()),
+            },
+        };
+        let done = chunk.is_err() || writer.as_ref().map_or(true, |w| w.finished);
+        Some((chunk, (batches, writer, done)))
+    })
+}
+
+/// 写 IPC 时用的缓冲区，writer 写进去之后再取出来
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuf {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum IpcKind {
+    File(FileWriter<SharedBuf>),
+    Stream(StreamWriter<SharedBuf>),
+}
+
+/// 用第一批的 schema 创建 IPC 文件或者流的 writer
+struct IpcWriter {
+    kind: IpcKind,
+    buf: SharedBuf,
+    finished: bool,
+}
+
+impl IpcWriter {
+    fn new(ds: &DataSet, format: Format) -> Result<Self> {
+        let buf = SharedBuf::default();
+        let schema: Schema = ds.schema().to_arrow();
+        let kind = match format {
+            Format::Ipc => IpcKind::File(FileWriter::try_new(buf.clone(), &schema)?),
+            _ => IpcKind::Stream(StreamWriter::try_new(buf.clone(), &schema)?),
+        };
+        Ok(Self {
+            kind,
+            buf,
+            finished: false,
+        })
+    }
+
+    fn write(&mut self, ds: &DataSet) -> Result<Vec<u8>> {
+        for batch in record_batches(ds)? {
+            match &mut self.kind {
+                IpcKind::File(w) => w.write(&batch)?,
+                IpcKind::Stream(w) => w.write(&batch)?,
+            }
+        }
+        Ok(self.buf.take())
+    }
+
+    fn finish(&mut self) -> Result<Vec<u8>> {
+        match &mut self.kind {
+            IpcKind::File(w) => w.finish()?,
+            IpcKind::Stream(w) => w.finish()?,
+        }
+        self.finished = true;
+        Ok(self.buf.take())
+    }
+}
+
+/// 每一列先合并成一个 chunk，这样 DataFrame 可以整齐地切成 RecordBatch
+fn record_batches(ds: &DataSet) -> Result<Vec<RecordBatch>> {
+    if ds.width() == 0 {
+        return Ok(Vec::new());
+    }
+    let mut df = (**ds).clone();
+    df.rechunk();
+    Ok(df.as_record_batches()?)
+}
+
+/// 查询结果的每一批编码后作为 body 的一段返回
+/// 中途出错时把错误对象作为最后一段，之后不再返回数据
+fn encode_batches(
+    batches: impl Stream<Item = Result<DataSet>> + Send + 'static,
+    format: Format,
+) -> impl Stream<Item = Result<Vec<u8>>> + Send + 'static {
+    let chunks = batches
+        .enumerate()
+        .map(move |(i, ds)| {
+            ds.and_then(|ds| encode_batch(&ds, i == 0, format))
+                .map_err(|e| encode_error(ApiError::from_query(e), i == 0, format))
+        })
+        // 出错之后的批次都不要了
+        .scan(false, |failed, chunk| {
+            let item = match *failed {
+                true => None,
+                false => {
+                    *failed = chunk.is_err();
+                    Some(chunk.unwrap_or_else(|error| error))
+                }
+            };
+            async move { item }
+        });
+
+    // json 数组需要在首尾补上括号
+    let (head, tail) = match format {
+        Format::Json => ("[", "]"),
+        _ => ("", ""),
+    };
+    stream::once(async move { Ok(head.as_bytes().to_vec()) })
+        .chain(chunks.map(Ok::<_, anyhow::Error>))
+        .chain(stream::once(async move { Ok(tail.as_bytes().to_vec()) }))
+}
+
+/// 已经返回过数据后才出错时的错误对象，json 数组里还要补上前面的逗号
+fn encode_error(e: ApiError, first: bool, format: Format) -> Vec<u8> {
+    warn!("query failed while streaming: {} {}", e.code, e.message);
+    let error = e.to_json().to_string();
+    let data = match format {
+        Format::Json if !first => format!(",{}", error),
+        Format::Json => error,
+        _ => format!("{}\n", error),
+    };
+    data.into_bytes()
+}
+
+/// query_stream 只在没有结果时返回空的批次，这时它也是第一批，csv 要靠它输出表头
+fn encode_batch(ds: &DataSet, first: bool, format: Format) -> Result<Vec<u8>> {
+    let data = match format {
+        Format::Csv if first => ds.to_csv()?,
+        // 只有第一批带表头
+        Format::Csv => ds
+            .to_csv()?
+            .splitn(2, '\n')
+            .nth(1)
+            .unwrap_or_default()
+            .to_owned(),
+        Format::NdJson => ds.to_ndjson()?,
+        Format::Json => {
+            let rows = ds.to_ndjson()?.lines().collect::<Vec<_>>().join(",");
+            match first {
+                true => rows,
+                false => format!(",{}", rows),
+            }
+        }
+        Format::Ipc | Format::IpcStream => unreachable!("ipc is encoded by encode_ipc"),
+    };
+    Ok(data.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::ipc::reader::{FileReader, StreamReader};
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+    use std::io::Cursor;
+
+    #[test]
+    fn format_from_accept_works() {
+        assert_eq!(Format::from_accept(None), Some(Format::Json));
+        assert_eq!(Format::from_accept(Some("text/csv")), Some(Format::Csv));
+        assert_eq!(
+            Format::from_accept(Some("image/png, application/vnd.apache.arrow.file;q=0.9")),
+            Some(Format::Ipc)
+        );
+        assert_eq!(
+            Format::from_accept(Some("application/vnd.apache.arrow.stream")),
+            Some(Format::IpcStream)
+        );
+        assert_eq!(Format::from_accept(Some("image/png")), None);
+    }
+
+    #[test]
+    fn permissions_works() {
+        assert_eq!(
+            permissions(|_| None),
+            Permissions::read_only().with_hosts(Vec::<String>::new())
+        );
+        let p = permissions(|name| match name {
+            "QUERYER_SERVER_ALLOW_WRITES" => Some("true".into()),
+            "QUERYER_SERVER_ALLOW_LOCAL_FILES" => Some("1".into()),
+            "QUERYER_SERVER_ALLOWED_HOSTS" => Some(" example.com, *.internal.net ,".into()),
+            _ => Some("no".into()),
+        });
+        assert!(p.writes && p.local_files && !p.settings);
+        assert_eq!(
+            p.hosts,
+            Some(vec!["example.com".into(), "*.internal.net".into()])
+        );
+    }
+
+    #[tokio::test]
+    async fn error_status_works() {
+        let session = Session::default().with_permissions(Permissions::read_only());
+        let cases = [
+            ("SELECT a FROM", StatusCode::BAD_REQUEST),
+            ("SELECT 1; SELECT 2", StatusCode::UNPROCESSABLE_ENTITY),
+            ("SELECT a FROM file:///etc/passwd", StatusCode::FORBIDDEN),
+            (
+                "COPY (SELECT a FROM https://example.com/a.csv) TO 'file:///tmp/a.csv'",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "SELECT a FROM http://127.0.0.1:1/a.csv",
+                StatusCode::BAD_GATEWAY,
+            ),
+        ];
+        for (sql, status) in cases {
+            let e = session.query(sql).await.unwrap_err();
+            assert_eq!(ApiError::from_query(e).status, status, "{}", sql);
+        }
+
+        // 没有设置 QUERYER_SERVER_ALLOWED_HOSTS 时不能访问任何远程主机
+        let session = Session::default().with_permissions(permissions(|_| None));
+        let e = session
+            .query("SELECT a FROM http://169.254.169.254/latest/meta-data")
+            .await
+            .unwrap_err();
+        assert_eq!(ApiError::from_query(e).status, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn session_params_works() {
+        let req: QueryRequest =
+            serde_json::from_str(r#"{"sql": "SELECT 1", "params": [1, 1.5, "a", null, true]}"#)
+                .unwrap();
+        assert!(session(&req).is_ok());
+        assert_eq!(to_param(&json!(1)).unwrap(), Param::Int(1));
+        assert_eq!(to_param(&json!(1.5)).unwrap(), Param::Float(1.5));
+        assert!(to_param(&json!([1])).is_err());
+
+        let req: QueryRequest =
+            serde_json::from_str(r#"{"sql": "SELECT 1", "params": "a"}"#).unwrap();
+        assert!(session(&req).is_err());
+
+        let req: QueryRequest =
+            serde_json::from_str(r#"{"sql": "SELECT 1", "dialect": "oracle"}"#).unwrap();
+        assert!(session(&req).is_err());
+    }
+
+    #[tokio::test]
+    async fn encode_batches_works() {
+        let path = env::temp_dir().join("queryer_server_people.csv");
+        std::fs::write(&path, "name,age\nTyr,1\nLindsey,2\n").unwrap();
+        let sql = format!("SELECT name, age FROM file://{}", path.display());
+        let batches = Session::default().query_stream(sql);
+
+        let chunks: Vec<Vec<u8>> = encode_batches(batches, Format::Json)
+            .map(|v| v.unwrap())
+            .collect()
+            .await;
+        let body = String::from_utf8(chunks.concat()).unwrap();
+        let rows: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            rows,
+            json!([{"name": "Tyr", "age": 1}, {"name": "Lindsey", "age": 2}])
+        );
+    }
+
+    #[tokio::test]
+    async fn encode_batches_error_works() {
+        let ds = || -> Result<DataSet> {
+            Ok(DataFrame::new(vec![Series::new("name", &["Tyr"])])?.into())
+        };
+        let batches = stream::iter(vec![ds(), Err(anyhow!("boom")), ds()]);
+
+        let chunks: Vec<Vec<u8>> = encode_batches(batches, Format::Json)
+            .map(|v| v.unwrap())
+            .collect()
+            .await;
+        let rows: Value = serde_json::from_slice(&chunks.concat()).unwrap();
+        assert_eq!(
+            rows,
+            json!([{"name": "Tyr"}, {"error": {"code": "query_failed", "message": "boom"}}])
+        );
+    }
+
+    #[tokio::test]
+    async fn encode_ipc_works() {
+        let path = env::temp_dir().join("queryer_server_ipc.csv");
+        std::fs::write(&path, "name,age\nTyr,1\nLindsey,2\n").unwrap();
+        let sql = format!("SELECT name, age FROM file://{}", path.display());
+
+        let chunks: Vec<Vec<u8>> = encode_ipc(
+            Session::default().query_stream(sql.clone()),
+            Format::IpcStream,
+        )
+        .map(|v| v.unwrap())
+        .collect()
+        .await;
+        let reader = StreamReader::try_new(Cursor::new(chunks.concat())).unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 2);
+
+        let chunks: Vec<Vec<u8>> = encode_ipc(Session::default().query_stream(sql), Format::Ipc)
+            .map(|v| v.unwrap())
+            .collect()
+            .await;
+        let reader = FileReader::try_new(Cursor::new(chunks.concat())).unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 2);
+    }
+}
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use sqlparser::{dialect::GenericDialect, parser::Parser}; 
+use sqlparser::{dialect::GenericDialect, parser::Parser};
 
 fn main() {
     tracing_subscriber::fmt::init();
@@ -22,6 +22,6 @@ fn main() {
                WHERE a > b AND b < 100 and c BETWEEN 10 and 20 \
                ORDER BY a DESC, b \
                LIMIT 10 OFFSET 20";
-    let ast = Parser::parse_sql(&GenericDialect::default(), sql);
+    let ast = Parser::parse_sql(&GenericDialect {}, sql);
     println!("{:#?}", ast);
-}
\ No newline at end of file
+}
@@ -0,0 +1,698 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    convert::const_int,
+    error::{unsupported, ParseError},
+    params::bind_params,
+    reshape::{extract_reshape, Reshape},
+    sample::{extract_sample, Sample},
+    DialectMode, Param,
+};
+use anyhow::{anyhow, Result};
+use sqlparser::{
+    ast::{Query, Statement},
+    dialect::Dialect,
+    parser::{Parser, ParserError},
+    tokenizer::{Token, Tokenizer},
+};
+use std::{iter::Peekable, vec::IntoIter};
+
+/// queryer 能执行的命令
+/// 大部分是 sqlparser 能直接解析的 Statement，sqlparser 不支持的语法在这里单独解析
+#[derive(Debug)]
+pub(crate) enum Command {
+    // Statement 比其它命令大得多，放在 Box 里
+    Statement(Box<Statement>),
+    /// COPY (SELECT ...) TO 'file:///out.parquet' [WITH] (FORMAT parquet)
+    Copy {
+        query: Box<Query>,
+        target: String,
+        format: Option<String>,
+    },
+    /// DIFF (SELECT ...) AGAINST (SELECT ...) KEY (col, ...)
+    /// 两边的查询各自带着自己的 PIVOT / SAMPLE 子句
+    Diff {
+        current: Box<Query>,
+        current_clauses: Box<Clauses>,
+        baseline: Box<Query>,
+        baseline_clauses: Box<Clauses>,
+        key: Vec<String>,
+    },
+    /// SET name = value，修改会话选项
+    Set {
+        name: String,
+        value: String,
+    },
+    /// SHOW name / SHOW ALL，查看会话选项，name 为 None 时列出所有选项
+    Show {
+        name: Option<String>,
+    },
+}
+
+impl Command {
+    /// SELECT 查询，其它命令返回 None
+    pub(crate) fn into_query(self) -> Option<Box<Query>> {
+        match self {
+            Command::Statement(statement) => match *statement {
+                Statement::Query(q) => Some(q),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+// LIMIT / OFFSET 后面的表达式遇到这些关键字就结束了
+const LIMIT_END: [&str; 5] = ["LIMIT", "OFFSET", "FETCH", "ROW", "ROWS"];
+
+/// sqlparser 不支持的子句，在解析前从 SQL 里摘出来，执行查询时再用
+#[derive(Debug, Default)]
+pub(crate) struct Clauses {
+    pub(crate) reshape: Option<Reshape>,
+    pub(crate) sample: Option<Sample>,
+}
+
+/// 把 SQL 解析成 Command，目前只支持单条语句
+/// SQL 里的 `$1` / `$name` 参数会先替换成 params 里对应的值
+/// 出错时返回 [ParseError]，不支持的语法返回 [Unsupported](crate::error::Unsupported)
+pub(crate) fn parse_command(
+    mode: DialectMode,
+    sql: &str,
+    params: &[(String, Param)],
+) -> Result<(Command, Clauses)> {
+    try_parse_command(mode, sql, params).map_err(|e| ParseError::wrap(sql, e))
+}
+
+fn try_parse_command(
+    mode: DialectMode,
+    sql: &str,
+    params: &[(String, Param)],
+) -> Result<(Command, Clauses)> {
+    let dialect = mode.dialect();
+    let dialect = dialect.as_ref();
+    let source = Tokenizer::new(dialect, sql)
+        .tokenize()
+        .map_err(|e| ParseError::from_tokenizer(sql, e))?;
+    let tokens: Vec<Token> = source
+        .iter()
+        .filter(|t| !matches!(t, Token::Whitespace(_)))
+        .cloned()
+        .collect();
+    let tokens = split_qualifiers(join_sheet_ranges(tokens));
+    let tokens = apply_mode(mode, bind_params(tokens, params)?)?;
+    let tokens = fold_limits(dialect, tokens)?;
+    match tokens.first() {
+        Some(first) if is_keyword(first, "SET") => {
+            return Ok((parse_set(&tokens[1..])?, Clauses::default()))
+        }
+        Some(first) if is_keyword(first, "SHOW") => {
+            return Ok((parse_show(&tokens[1..])?, Clauses::default()))
+        }
+        _ => {}
+    }
+    if matches!(tokens.as_slice(), [first, Token::LParen, ..] if is_keyword(first, "DIFF")) {
+        return Ok((parse_diff(dialect, &tokens[1..])?, Clauses::default()));
+    }
+    let (tokens, clauses) = extract_clauses(tokens)?;
+
+    let command = match tokens.as_slice() {
+        [first, Token::LParen, ..] if is_keyword(first, "COPY") => {
+            parse_copy(dialect, &tokens[1..])?
+        }
+        _ => {
+            let mut parser = Parser::new(tokens.clone(), dialect);
+            let statement = match parser.parse_statement() {
+                Ok(statement) => statement,
+                Err(ParserError::ParserError(message)) => {
+                    // 把剩下的 token 读完，算出 parser 是在第几个 token 出错的
+                    let mut rest = 0;
+                    while parser.next_token() != Token::EOF {
+                        rest += 1;
+                    }
+                    let index = tokens.len() - rest;
+                    let e = ParseError::from_parser(sql, &message, &source, &tokens, index);
+                    return Err(e.into());
+                }
+                Err(e) => return Err(e.into()),
+            };
+            while parser.consume_token(&Token::SemiColon) {}
+            match parser.next_token() {
+                Token::EOF => Command::Statement(Box::new(statement)),
+                _ => return Err(unsupported!("Only support single sql at the moment")),
+            }
+        }
+    };
+    Ok((command, clauses))
+}
+
+/// 把 sqlparser 不支持的子句摘出来
+fn extract_clauses(tokens: Vec<Token>) -> Result<(Vec<Token>, Clauses)> {
+    let (tokens, reshape) = extract_reshape(tokens)?;
+    let (tokens, sample) = extract_sample(tokens)?;
+    Ok((tokens, Clauses { reshape, sample }))
+}
+
+/// TyrDialect 里 '.' 是标识符的一部分，t."Total Cases" 会被切成 `t.` 和 `"Total Cases"` 两个 token，
+/// 这里把它还原成 t . "Total Cases"，让 sqlparser 解析成 CompoundIdentifier
+fn split_qualifiers(tokens: Vec<Token>) -> Vec<Token> {
+    let mut result = Vec::with_capacity(tokens.len());
+    let mut iter = tokens.into_iter().peekable();
+    while let Some(token) = iter.next() {
+        match (token, iter.peek()) {
+            (Token::Word(w), Some(Token::Word(next)))
+                if w.quote_style.is_none()
+                    && w.value.ends_with('.')
+                    && next.quote_style.is_some() =>
+            {
+                result.push(Token::make_word(w.value.trim_end_matches('.'), None));
+                result.push(Token::Period);
+            }
+            (token, _) => result.push(token),
+        }
+    }
+    result
+}
+
+/// 电子表格数据源 file:///x.xlsx#Sheet2!A1:C10 里的 `!` 会被切成单独的 token，
+/// 这里把它和前后两个标识符拼回一个数据源
+fn join_sheet_ranges(tokens: Vec<Token>) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::with_capacity(tokens.len());
+    let mut iter = tokens.into_iter().peekable();
+    while let Some(token) = iter.next() {
+        let is_sheet = match (result.last(), &token) {
+            (Some(Token::Word(w)), Token::ExclamationMark) => {
+                w.quote_style.is_none() && w.value.contains('#')
+            }
+            _ => false,
+        };
+        match (is_sheet, iter.peek()) {
+            (true, Some(Token::Word(range))) if range.quote_style.is_none() => {
+                let range = range.value.clone();
+                iter.next();
+                if let Some(Token::Word(w)) = result.last_mut() {
+                    w.value = format!("{}!{}", w.value, range);
+                }
+            }
+            _ => result.push(token),
+        }
+    }
+    result
+}
+
+/// 按方言改写 token，改写之后都是 sqlparser 和后面的处理认识的写法
+fn apply_mode(mode: DialectMode, tokens: Vec<Token>) -> Result<Vec<Token>> {
+    match mode {
+        DialectMode::Tyr => Ok(tokens),
+        DialectMode::Ansi => match tokens.iter().find(|t| is_keyword(t, "LIMIT")) {
+            Some(_) => Err(anyhow!(
+                "LIMIT is not supported in ANSI mode, use OFFSET n ROWS FETCH FIRST n ROWS ONLY"
+            )),
+            None => Ok(tokens),
+        },
+        // 没加引号的标识符（包括关键字）都转成小写，关键字的判断不受影响
+        DialectMode::Postgres => Ok(tokens
+            .into_iter()
+            .map(|t| match t {
+                Token::Word(mut w) if w.quote_style.is_none() => {
+                    w.value = w.value.to_lowercase();
+                    Token::Word(w)
+                }
+                t => t,
+            })
+            .collect()),
+        DialectMode::MySql => Ok(mysql_limits(
+            tokens
+                .into_iter()
+                .map(|t| match t {
+                    Token::StringConcat => Token::make_keyword("OR"),
+                    t => t,
+                })
+                .collect(),
+        )),
+    }
+}
+
+/// MySQL 的 LIMIT offset, count 改写成 LIMIT count OFFSET offset
+fn mysql_limits(tokens: Vec<Token>) -> Vec<Token> {
+    let mut result = Vec::with_capacity(tokens.len());
+    let mut iter = tokens.into_iter().peekable();
+    while let Some(token) = iter.next() {
+        let is_limit = is_keyword(&token, "LIMIT");
+        result.push(token);
+        if !is_limit {
+            continue;
+        }
+
+        let mut expr = limit_expr(&mut iter);
+        let mut depth = 0;
+        let comma = expr.iter().position(|t| {
+            match t {
+                Token::LParen => depth += 1,
+                Token::RParen => depth -= 1,
+                _ => {}
+            }
+            depth == 0 && *t == Token::Comma
+        });
+        match comma {
+            Some(i) => {
+                let count = expr.split_off(i + 1);
+                expr.pop();
+                result.extend(count);
+                result.push(Token::make_keyword("OFFSET"));
+                result.extend(expr);
+            }
+            None => result.extend(expr),
+        }
+    }
+    result
+}
+
+/// 取出 LIMIT / OFFSET 后面的表达式
+fn limit_expr(iter: &mut Peekable<IntoIter<Token>>) -> Vec<Token> {
+    let mut expr = Vec::new();
+    let mut depth = 0;
+    while let Some(t) = iter.peek() {
+        let end = match t {
+            Token::LParen => {
+                depth += 1;
+                false
+            }
+            // COPY (SELECT ... LIMIT 10) 里的右括号
+            Token::RParen if depth == 0 => true,
+            Token::RParen => {
+                depth -= 1;
+                false
+            }
+            Token::SemiColon | Token::EOF => true,
+            t => depth == 0 && LIMIT_END.iter().any(|k| is_keyword(t, k)),
+        };
+        if end {
+            break;
+        }
+        expr.extend(iter.next());
+    }
+    expr
+}
+
+/// sqlparser 的 LIMIT / OFFSET 只接受数字，这里先把常量表达式算成数字
+fn fold_limits(dialect: &dyn Dialect, tokens: Vec<Token>) -> Result<Vec<Token>> {
+    let mut result = Vec::with_capacity(tokens.len());
+    let mut iter = tokens.into_iter().peekable();
+    while let Some(token) = iter.next() {
+        let is_limit = is_keyword(&token, "LIMIT") || is_keyword(&token, "OFFSET");
+        result.push(token);
+        if !is_limit {
+            continue;
+        }
+
+        let expr = limit_expr(&mut iter);
+        match expr.as_slice() {
+            [] | [Token::Number(..)] => result.extend(expr),
+            [t] if is_keyword(t, "ALL") => result.extend(expr),
+            _ => {
+                let mut parser = Parser::new(expr, dialect);
+                let value = const_int(&parser.parse_expr()?)?;
+                if value < 0 {
+                    return Err(anyhow!(
+                        "LIMIT / OFFSET must not be negative, got {}",
+                        value
+                    ));
+                }
+                result.push(Token::Number(value.to_string(), false));
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// 解析 COPY 后面的部分：(query) TO target [WITH] (FORMAT x)
+fn parse_copy(dialect: &dyn Dialect, tokens: &[Token]) -> Result<Command> {
+    let end = matching_paren(tokens)?;
+    let query = parse_query(dialect, &tokens[1..end])?;
+
+    let mut rest = tokens[end + 1..].iter().filter(|t| **t != Token::SemiColon);
+    match rest.next() {
+        Some(t) if is_keyword(t, "TO") => {}
+        t => return Err(anyhow!("Expected TO after COPY (...), found {:?}", t)),
+    }
+    let target = match rest.next() {
+        Some(Token::SingleQuotedString(s)) => s.clone(),
+        Some(Token::Word(w)) => w.value.clone(),
+        t => return Err(anyhow!("Expected target after TO, found {:?}", t)),
+    };
+
+    // 可选的 [WITH] (FORMAT x)
+    let options: Vec<&Token> = rest.collect();
+    let options = match options.as_slice() {
+        [w, rest @ ..] if is_keyword(w, "WITH") => rest,
+        rest => rest,
+    };
+    let format = match options {
+        [] => None,
+        [Token::LParen, key, value, Token::RParen] if is_keyword(key, "FORMAT") => match value {
+            Token::Word(w) => Some(w.value.to_lowercase()),
+            Token::SingleQuotedString(s) => Some(s.to_lowercase()),
+            t => return Err(anyhow!("Expected format name, found {}", t)),
+        },
+        _ => {
+            return Err(unsupported!(
+                "We only support (FORMAT <name>) option for COPY"
+            ))
+        }
+    };
+
+    Ok(Command::Copy {
+        query: Box::new(query),
+        target,
+        format,
+    })
+}
+
+/// 解析 DIFF 后面的部分：(query) AGAINST (query) KEY (col, ...)
+fn parse_diff(dialect: &dyn Dialect, tokens: &[Token]) -> Result<Command> {
+    let end = matching_paren(tokens)?;
+    let (current, current_clauses) = extract_clauses(tokens[1..end].to_vec())?;
+    let current = parse_query(dialect, &current)?;
+
+    let rest = match &tokens[end + 1..] {
+        [t, rest @ ..] if is_keyword(t, "AGAINST") && rest.first() == Some(&Token::LParen) => rest,
+        _ => return Err(anyhow!("Expected AGAINST (...) after DIFF (...)")),
+    };
+    let end = matching_paren(rest)?;
+    let (baseline, baseline_clauses) = extract_clauses(rest[1..end].to_vec())?;
+    let baseline = parse_query(dialect, &baseline)?;
+
+    let rest: Vec<&Token> = rest[end + 1..]
+        .iter()
+        .filter(|t| **t != Token::SemiColon)
+        .collect();
+    let columns = match rest.as_slice() {
+        [k, Token::LParen, columns @ .., Token::RParen] if is_keyword(k, "KEY") => columns,
+        _ => return Err(anyhow!("Expected KEY (<column>, ...) after AGAINST (...)")),
+    };
+    let mut key = Vec::new();
+    for column in columns.split(|t| **t == Token::Comma) {
+        match column {
+            [Token::Word(w)] => key.push(w.value.clone()),
+            _ => return Err(anyhow!("Expected column name in KEY (...)")),
+        }
+    }
+
+    Ok(Command::Diff {
+        current: Box::new(current),
+        current_clauses: Box::new(current_clauses),
+        baseline: Box::new(baseline),
+        baseline_clauses: Box::new(baseline_clauses),
+        key,
+    })
+}
+
+/// 解析 SET 后面的部分：name = value 或者 name TO value
+/// 值只能是一个数字、字符串或者单词（true / false / DEFAULT）
+fn parse_set(tokens: &[Token]) -> Result<Command> {
+    let tokens: Vec<&Token> = tokens.iter().filter(|t| **t != Token::SemiColon).collect();
+    let pos = tokens
+        .iter()
+        .position(|t| **t == Token::Eq || is_keyword(t, "TO"))
+        .ok_or_else(|| anyhow!("Expected = or TO after SET <name>"))?;
+    let name = option_name(&tokens[..pos])?;
+    let value = match &tokens[pos + 1..] {
+        [Token::Number(v, _)] | [Token::SingleQuotedString(v)] => v.clone(),
+        [Token::Word(w)] => w.value.clone(),
+        _ => return Err(anyhow!("Expected a single value after SET {} =", name)),
+    };
+    Ok(Command::Set { name, value })
+}
+
+/// 解析 SHOW 后面的部分：选项名或者 ALL，什么都没有时也列出所有选项
+fn parse_show(tokens: &[Token]) -> Result<Command> {
+    let tokens: Vec<&Token> = tokens.iter().filter(|t| **t != Token::SemiColon).collect();
+    match tokens.as_slice() {
+        [] => Ok(Command::Show { name: None }),
+        [t] if is_keyword(t, "ALL") => Ok(Command::Show { name: None }),
+        name => Ok(Command::Show {
+            name: Some(option_name(name)?),
+        }),
+    }
+}
+
+/// 选项名可以带点，比如 http.timeout，TyrDialect 以外的方言会把它切成几个 token
+fn option_name(tokens: &[&Token]) -> Result<String> {
+    let mut name = String::new();
+    for t in tokens {
+        match t {
+            Token::Word(w) => name.push_str(&w.value),
+            Token::Period => name.push('.'),
+            t => return Err(anyhow!("Unexpected {} in option name", t)),
+        }
+    }
+    match name.is_empty() {
+        true => Err(anyhow!("Expected option name")),
+        false => Ok(name),
+    }
+}
+
+/// 用 sqlparser 解析一段 token 为 Query，要求把 token 全部用完
+pub(crate) fn parse_query(dialect: &dyn Dialect, tokens: &[Token]) -> Result<Query> {
+    let mut parser = Parser::new(tokens.to_vec(), dialect);
+    let query = parser.parse_query()?;
+    match parser.next_token() {
+        Token::EOF => Ok(query),
+        t => Err(anyhow!("Expected end of query, found {}", t)),
+    }
+}
+
+/// tokens[0] 是左括号，找到和它配对的右括号的位置
+pub(crate) fn matching_paren(tokens: &[Token]) -> Result<usize> {
+    let mut depth = 0;
+    for (i, t) in tokens.iter().enumerate() {
+        match t {
+            Token::LParen => depth += 1,
+            Token::RParen => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(anyhow!("Unmatched parenthesis"))
+}
+
+pub(crate) fn is_keyword(token: &Token, keyword: &str) -> bool {
+    matches!(token, Token::Word(w) if w.quote_style.is_none() && w.value.eq_ignore_ascii_case(keyword))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DialectMode;
+
+    #[test]
+    fn parse_copy_works() {
+        let sql = "COPY (SELECT a, b FROM file:///tmp/in.csv WHERE (a > 1)) TO 'file:///tmp/out.parquet' (FORMAT parquet)";
+        match parse_command(DialectMode::Tyr, sql, &[]).unwrap().0 {
+            Command::Copy {
+                query,
+                target,
+                format,
+            } => {
+                assert_eq!(
+                    query.to_string(),
+                    "SELECT a, b FROM file:///tmp/in.csv WHERE (a > 1)"
+                );
+                assert_eq!(target, "file:///tmp/out.parquet");
+                assert_eq!(format, Some("parquet".into()));
+            }
+            v => panic!("expect copy, got {:?}", v),
+        }
+
+        let sql = "COPY (SELECT a FROM file:///tmp/in.csv) TO file:///tmp/out.csv";
+        assert!(matches!(
+            parse_command(DialectMode::Tyr, sql, &[]).unwrap().0,
+            Command::Copy { format: None, .. }
+        ));
+
+        let sql = "COPY (SELECT a FROM file:///tmp/in.csv) file:///tmp/out.csv";
+        assert!(parse_command(DialectMode::Tyr, sql, &[]).is_err());
+    }
+
+    #[test]
+    fn parse_diff_works() {
+        let sql = "DIFF (SELECT location, new_deaths FROM file:///tmp/today.csv SAMPLE 10 ROWS) \
+                   AGAINST (SELECT location, new_deaths FROM file:///tmp/yesterday.csv) \
+                   KEY (location, \"date\")";
+        match parse_command(DialectMode::Tyr, sql, &[]).unwrap().0 {
+            Command::Diff {
+                current,
+                current_clauses,
+                baseline,
+                baseline_clauses,
+                key,
+            } => {
+                assert_eq!(
+                    current.to_string(),
+                    "SELECT location, new_deaths FROM file:///tmp/today.csv"
+                );
+                assert!(current_clauses.sample.is_some());
+                assert_eq!(
+                    baseline.to_string(),
+                    "SELECT location, new_deaths FROM file:///tmp/yesterday.csv"
+                );
+                assert!(baseline_clauses.sample.is_none());
+                assert_eq!(key, vec!["location", "date"]);
+            }
+            v => panic!("expect diff, got {:?}", v),
+        }
+
+        let sql =
+            "DIFF (SELECT a FROM file:///tmp/a.csv) AGAINST (SELECT a FROM file:///tmp/b.csv)";
+        assert!(parse_command(DialectMode::Tyr, sql, &[]).is_err());
+        let sql = "DIFF (SELECT a FROM file:///tmp/a.csv) AGAINST (SELECT a FROM file:///tmp/b.csv) KEY ()";
+        assert!(parse_command(DialectMode::Tyr, sql, &[]).is_err());
+    }
+
+    #[test]
+    fn limit_expression_works() {
+        let sql = "SELECT a FROM file:///tmp/in.csv WHERE a > $min LIMIT $1 * 2 OFFSET (3 + 2)";
+        let params = vec![
+            ("1".to_owned(), Param::from(10)),
+            ("min".to_owned(), Param::from(1.5)),
+        ];
+        match parse_command(DialectMode::Tyr, sql, &params)
+            .unwrap()
+            .0
+            .into_query()
+        {
+            Some(q) => assert_eq!(
+                q.to_string(),
+                "SELECT a FROM file:///tmp/in.csv WHERE a > 1.5 LIMIT 20 OFFSET 5"
+            ),
+            None => panic!("expect query"),
+        }
+
+        let sql = "SELECT a FROM file:///tmp/in.csv LIMIT a + 1";
+        assert!(parse_command(DialectMode::Tyr, sql, &[]).is_err());
+        let sql = "SELECT a FROM file:///tmp/in.csv LIMIT 1 - 2";
+        assert!(parse_command(DialectMode::Tyr, sql, &[]).is_err());
+    }
+
+    #[test]
+    fn split_qualifiers_works() {
+        let sql = r#"SELECT t."Total Cases" FROM file:///tmp/in.csv AS t"#;
+        match parse_command(DialectMode::Tyr, sql, &[])
+            .unwrap()
+            .0
+            .into_query()
+        {
+            Some(q) => assert_eq!(
+                q.to_string(),
+                r#"SELECT t."Total Cases" FROM file:///tmp/in.csv AS t"#
+            ),
+            None => panic!("expect query"),
+        }
+    }
+
+    #[test]
+    fn join_sheet_ranges_works() {
+        let sql = "SELECT a FROM file:///tmp/in.xlsx#Sheet2!A1:C10 WHERE a != 1";
+        match parse_command(DialectMode::Tyr, sql, &[])
+            .unwrap()
+            .0
+            .into_query()
+        {
+            Some(q) => assert_eq!(
+                q.to_string(),
+                "SELECT a FROM file:///tmp/in.xlsx#Sheet2!A1:C10 WHERE a <> 1"
+            ),
+            None => panic!("expect query"),
+        }
+    }
+
+    #[test]
+    fn dialect_mode_works() {
+        let query = |mode, sql| match parse_command(mode, sql, &[]).unwrap().0.into_query() {
+            Some(q) => q.to_string(),
+            None => panic!("expect query"),
+        };
+
+        let sql = "SELECT `Total Cases`, a || b FROM 'file:///tmp/in.csv' LIMIT 5, 10";
+        assert_eq!(
+            query(DialectMode::MySql, sql),
+            "SELECT `Total Cases`, a OR b FROM 'file:///tmp/in.csv' LIMIT 10 OFFSET 5"
+        );
+
+        let sql = r#"SELECT Location, "Total Cases" FROM 'file:///tmp/in.csv' LIMIT 1"#;
+        assert_eq!(
+            query(DialectMode::Postgres, sql),
+            r#"SELECT location, "Total Cases" FROM 'file:///tmp/in.csv' LIMIT 1"#
+        );
+
+        let sql = "SELECT a FROM 'file:///tmp/in.csv' OFFSET 1 ROWS FETCH FIRST 2 ROWS ONLY";
+        assert!(parse_command(DialectMode::Ansi, sql, &[]).is_ok());
+        let sql = "SELECT a FROM 'file:///tmp/in.csv' LIMIT 2";
+        assert!(parse_command(DialectMode::Ansi, sql, &[]).is_err());
+    }
+
+    #[test]
+    fn parse_insert_works() {
+        let sql = "INSERT INTO 'file:///tmp/out.csv' SELECT a FROM file:///tmp/in.csv";
+        match parse_command(DialectMode::Tyr, sql, &[]).unwrap().0 {
+            Command::Statement(statement) => assert!(matches!(
+                *statement,
+                Statement::Insert {
+                    overwrite: false,
+                    ..
+                }
+            )),
+            v => panic!("expect insert, got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn parse_set_works() {
+        let cases = [
+            ("SET inference_rows = 1000", "inference_rows", "1000"),
+            ("SET http.timeout = '30s';", "http.timeout", "30s"),
+            ("SET case_sensitive TO false", "case_sensitive", "false"),
+        ];
+        for (sql, name, value) in cases {
+            match parse_command(DialectMode::Tyr, sql, &[]).unwrap().0 {
+                Command::Set { name: n, value: v } => {
+                    assert_eq!((n.as_str(), v.as_str()), (name, value))
+                }
+                v => panic!("expect set, got {:?}", v),
+            }
+        }
+
+        // 其它方言会把 http.timeout 切成几个 token
+        let sql = "SET http.timeout = '30s'";
+        assert!(matches!(
+            parse_command(DialectMode::Ansi, sql, &[]).unwrap().0,
+            Command::Set { name, .. } if name == "http.timeout"
+        ));
+        assert!(parse_command(DialectMode::Tyr, "SET inference_rows", &[]).is_err());
+
+        assert!(matches!(
+            parse_command(DialectMode::Tyr, "SHOW ALL", &[]).unwrap().0,
+            Command::Show { name: None }
+        ));
+        assert!(matches!(
+            parse_command(DialectMode::Tyr, "SHOW null_values", &[]).unwrap().0,
+            Command::Show { name: Some(name) } if name == "null_values"
+        ));
+    }
+}
@@ -12,23 +12,82 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::error::unsupported;
+use crate::join::Join;
+use crate::partition::{predicates, Predicate};
+use crate::registry::get_function;
+use crate::temporal::{date_trunc, extract, parse_date, parse_timestamp, Interval};
+use crate::text::{self, concat};
 use anyhow::{anyhow, Result};
+use chrono::Local;
 use polars::prelude::*;
 use sqlparser::ast::{
-    BinaryOperator as SqlBinaryOperator, Expr as SqlExpr, Offset as SqlOffset, OrderByExpr, Select,
-    SelectItem, SetExpr, Statement, TableFactor, TableWithJoins, Value as SqlValue,
+    BinaryOperator as SqlBinaryOperator, DataType as SqlDataType, Expr as SqlExpr, Function,
+    FunctionArg, Ident, Join as SqlJoin, JoinConstraint, JoinOperator, Offset as SqlOffset,
+    OrderByExpr, Query, Select, SelectItem, SetExpr, Statement, TableFactor, TableWithJoins,
+    UnaryOperator, Value as SqlValue,
 };
 
 /// 解析出来的 SQL
 pub struct Sql<'a> {
     pub(crate) selection: Vec<Expr>,
     pub(crate) condition: Option<Expr>,
+    // 可以下推到远端数据库执行的 where 子句
+    pub(crate) pushdown: Option<String>,
+    // 可以用来跳过分区目录和 parquet row group 的简单条件
+    pub(crate) predicates: Vec<Predicate>,
     pub(crate) source: &'a str,
+    // FROM source AS alias 里的表别名
+    pub(crate) alias: Option<String>,
+    // JOIN 进来的其它数据源
+    pub(crate) joins: Vec<Join<'a>>,
     pub(crate) order_by: Vec<(String, bool)>,
     pub(crate) offset: Option<i64>,
     pub(crate) limit: Option<usize>,
 }
 
+impl<'a> Sql<'a> {
+    /// 查询里引用到的所有列名
+    pub(crate) fn columns(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .selection
+            .iter()
+            .chain(self.condition.iter())
+            .flat_map(|expr| expr.into_iter())
+            .filter_map(|expr| match expr {
+                Expr::Column(name) => Some(name.as_ref().clone()),
+                _ => None,
+            })
+            .chain(self.order_by.iter().map(|(name, _)| name.clone()))
+            .chain(
+                self.joins
+                    .iter()
+                    .flat_map(|j| j.on.iter().flat_map(|(a, b)| [a.clone(), b.clone()])),
+            )
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// FROM 的数据源在列名前面的表名，有 JOIN 时没有别名的数据源用它本身，比如注册的表名
+    pub(crate) fn qualifier(&self) -> Option<&str> {
+        match self.joins.is_empty() {
+            true => self.alias.as_deref(),
+            false => Some(self.alias.as_deref().unwrap_or(self.source)),
+        }
+    }
+
+    /// 结果的列名里要去掉的表名
+    pub(crate) fn qualifiers(&self) -> Vec<String> {
+        self.qualifier()
+            .into_iter()
+            .chain(self.joins.iter().map(|j| j.qualifier()))
+            .map(String::from)
+            .collect()
+    }
+}
+
 // 因为 Rust trait 的孤儿规则，我们如果要想对已有的类型实现已有的 trait，
 // 需要简单包装一下
 
@@ -36,10 +95,13 @@ pub struct Expression(pub(crate) Box<SqlExpr>);
 pub struct Operation(pub(crate) SqlBinaryOperator);
 pub struct Projection<'a>(pub(crate) &'a SelectItem);
 pub struct Source<'a>(pub(crate) &'a [TableWithJoins]);
+pub struct JoinClause<'a>(pub(crate) &'a SqlJoin);
 pub struct Order<'a>(pub(crate) &'a OrderByExpr);
 pub struct Offset<'a>(pub(crate) &'a SqlOffset);
 pub struct Limit<'a>(pub(crate) &'a SqlExpr);
 pub struct Value(pub(crate) SqlValue);
+pub struct Pushdown<'a>(pub(crate) &'a SqlExpr);
+pub struct Call(pub(crate) Function);
 
 /// 把 SqlParser 解析出来的 Statement 转换成我们需要的结构
 impl<'a> TryFrom<&'a Statement> for Sql<'a> {
@@ -48,54 +110,89 @@ impl<'a> TryFrom<&'a Statement> for Sql<'a> {
     fn try_from(sql: &'a Statement) -> Result<Self, Self::Error> {
         match sql {
             // 目前我们只关心 query (select ... from ... where ...)
-            Statement::Query(q) => {
-                let offset = q.offset.as_ref();
-                let limit = q.limit.as_ref();
-                let orders = &q.order_by;
-                let Select {
-                    from: table_with_joins,
-                    selection: where_clause,
-                    projection,
-
-                    group_by: _,
-                    ..
-                } = match &q.body {
-                    SetExpr::Select(statement) => statement.as_ref(),
-                    _ => return Err(anyhow!("We only support Select Query at the moment")),
-                };
+            Statement::Query(q) => q.as_ref().try_into(),
+            _ => Err(unsupported!("We only support Query at the moment")),
+        }
+    }
+}
 
-                let source = Source(table_with_joins).try_into()?;
+/// 把 SqlParser 解析出来的 Query 转换成我们需要的结构
+/// INSERT / COPY 里的子查询也是 Query
+impl<'a> TryFrom<&'a Query> for Sql<'a> {
+    type Error = anyhow::Error;
 
-                let condition = match where_clause {
-                    Some(expr) => Some(Expression(Box::new(expr.to_owned())).try_into()?),
-                    None => None,
-                };
+    fn try_from(q: &'a Query) -> Result<Self, Self::Error> {
+        let offset = q.offset.as_ref();
+        let limit = q.limit.as_ref();
+        let orders = &q.order_by;
+        let Select {
+            from: table_with_joins,
+            selection: where_clause,
+            projection,
 
-                let mut selection = Vec::with_capacity(8);
-                for p in projection {
-                    let expr = Projection(p).try_into()?;
-                    selection.push(expr);
-                }
+            group_by: _,
+            ..
+        } = match &q.body {
+            SetExpr::Select(statement) => statement.as_ref(),
+            _ => return Err(unsupported!("We only support Select Query at the moment")),
+        };
 
-                let mut order_by = Vec::new();
-                for expr in orders {
-                    order_by.push(Order(expr).try_into()?);
-                }
+        let source = Source(table_with_joins).try_into()?;
+        let alias = table_with_joins
+            .first()
+            .and_then(|t| table_alias(&t.relation));
+        let joins: Vec<Join> = match table_with_joins.first() {
+            Some(t) => t
+                .joins
+                .iter()
+                .map(|j| JoinClause(j).try_into())
+                .collect::<Result<Vec<_>>>()?,
+            None => vec![],
+        };
+
+        let condition = match where_clause {
+            Some(expr) => Some(Expression(Box::new(expr.to_owned())).try_into()?),
+            None => None,
+        };
+        let pushdown: Option<String> = where_clause.as_ref().and_then(|expr| Pushdown(expr).into());
+        let predicates = where_clause.as_ref().map(predicates).unwrap_or_default();
+
+        let mut selection = Vec::with_capacity(8);
+        for p in projection {
+            let expr = Projection(p).try_into()?;
+            selection.push(expr);
+        }
+
+        let mut order_by = Vec::new();
+        for expr in orders {
+            order_by.push(Order(expr).try_into()?);
+        }
 
-                let offset = offset.map(|v| Offset(v).into());
-                let limit = limit.map(|v| Limit(v).into());
-
-                Ok(Sql {
-                    selection,
-                    condition,
-                    source,
-                    order_by,
-                    offset,
-                    limit,
-                })
+        let offset = offset.map(|v| Offset(v).try_into()).transpose()?;
+        let mut limit = limit.map(|v| Limit(v).try_into()).transpose()?;
+        // FETCH FIRST n ROWS ONLY 和 LIMIT n 是一回事，没有 n 时是一行
+        if let Some(fetch) = &q.fetch {
+            if limit.is_some() || fetch.with_ties || fetch.percent {
+                return Err(unsupported!("{} is not supported", fetch));
             }
-            _ => Err(anyhow!("We only support Query at the moment")),
+            limit = match &fetch.quantity {
+                Some(v) => Some(Limit(v).try_into()?),
+                None => Some(1),
+            };
         }
+
+        Ok(Sql {
+            selection,
+            condition,
+            pushdown,
+            predicates,
+            source,
+            alias,
+            joins,
+            order_by,
+            offset,
+            limit,
+        })
     }
 }
 
@@ -105,17 +202,108 @@ impl TryFrom<Expression> for Expr {
 
     fn try_from(expr: Expression) -> Result<Self, Self::Error> {
         match *expr.0 {
-            SqlExpr::BinaryOp { left, op, right } => Ok(Expr::BinaryExpr {
-                left: Box::new(Expression(left).try_into()?),
-                op: Operation(op).try_into()?,
-                right: Box::new(Expression(right).try_into()?),
-            }),
+            SqlExpr::BinaryOp { left, op, right } => match (op, *right) {
+                // date + INTERVAL '1' DAY
+                (
+                    op @ (SqlBinaryOperator::Plus | SqlBinaryOperator::Minus),
+                    SqlExpr::Value(SqlValue::Interval {
+                        value,
+                        leading_field,
+                        ..
+                    }),
+                ) => {
+                    let interval = Interval::parse(&value, leading_field.as_ref())?;
+                    let interval = match op {
+                        SqlBinaryOperator::Minus => interval.negate()?,
+                        _ => interval,
+                    };
+                    Ok(interval.apply(Expression(left).try_into()?))
+                }
+                // a || b
+                (SqlBinaryOperator::StringConcat, right) => concat(vec![
+                    Expression(left).try_into()?,
+                    Expression(Box::new(right)).try_into()?,
+                ]),
+                (op, right) => Ok(Expr::BinaryExpr {
+                    left: Box::new(Expression(left).try_into()?),
+                    op: Operation(op).try_into()?,
+                    right: Box::new(Expression(Box::new(right)).try_into()?),
+                }),
+            },
             SqlExpr::Wildcard => Ok(Self::Wildcard),
             SqlExpr::IsNull(expr) => Ok(Self::IsNull(Box::new(Expression(expr).try_into()?))),
             SqlExpr::IsNotNull(expr) => Ok(Self::IsNotNull(Box::new(Expression(expr).try_into()?))),
             SqlExpr::Identifier(id) => Ok(Self::Column(Arc::new(id.value))),
+            // "t"."col" 这种写法，先保留限定名，加载数据后再对应到真正的列上
+            SqlExpr::CompoundIdentifier(ids) => Ok(Self::Column(Arc::new(qualified_name(&ids)))),
             SqlExpr::Value(v) => Ok(Self::Literal(Value(v).try_into()?)),
-            v => Err(anyhow!("expr {:#?} is not supported", v)),
+            SqlExpr::TypedString { data_type, value } => match data_type {
+                SqlDataType::Date => Ok(lit(parse_date(&value)?)),
+                SqlDataType::Timestamp => Ok(lit(parse_timestamp(&value)?)),
+                v => Err(unsupported!("typed string {} is not supported", v)),
+            },
+            SqlExpr::Extract { field, expr } => Ok(extract(&field, Expression(expr).try_into()?)),
+            SqlExpr::Nested(expr) => Expression(expr).try_into(),
+            SqlExpr::Function(f) => Call(f).try_into(),
+            v => Err(unsupported!("expr {:#?} is not supported", v)),
+        }
+    }
+}
+
+/// 把 SqlParser 的函数调用转换成 DataFrame 的 Expr
+impl TryFrom<Call> for Expr {
+    type Error = anyhow::Error;
+
+    fn try_from(call: Call) -> Result<Self, Self::Error> {
+        let name = call.0.name.to_string().to_lowercase();
+        let args: Vec<SqlExpr> = call
+            .0
+            .args
+            .into_iter()
+            .map(|arg| match arg {
+                FunctionArg::Named { arg, .. } | FunctionArg::Unnamed(arg) => arg,
+            })
+            .collect();
+
+        match (name.as_str(), args.as_slice()) {
+            ("now", []) => Ok(lit(Local::now().naive_local())),
+            ("date_trunc", [SqlExpr::Value(SqlValue::SingleQuotedString(unit)), expr]) => {
+                date_trunc(unit, Expression(Box::new(expr.clone())).try_into()?)
+            }
+            // DATE_ADD(date, INTERVAL '1' DAY) 或者 DATE_ADD(date, '1 day')
+            ("date_add", [expr, SqlExpr::Value(interval)]) => {
+                let interval = match interval {
+                    SqlValue::Interval {
+                        value,
+                        leading_field,
+                        ..
+                    } => Interval::parse(value, leading_field.as_ref())?,
+                    SqlValue::SingleQuotedString(value) => Interval::parse(value, None)?,
+                    v => return Err(anyhow!("DATE_ADD expects an interval, got {}", v)),
+                };
+                Ok(interval.apply(Expression(Box::new(expr.clone())).try_into()?))
+            }
+            ("concat", args) => concat(
+                args.iter()
+                    .map(|arg| Expression(Box::new(arg.clone())).try_into())
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            // REGEXP_LIKE(location, '^it', 'i')，第一个参数之后的都是字面量
+            (
+                "regexp_like" | "regexp_extract" | "regexp_replace" | "contains",
+                [expr, rest @ ..],
+            ) => text::call(&name, Expression(Box::new(expr.clone())).try_into()?, rest),
+            // 用户通过 register_function 注册的函数
+            _ => match get_function(&name) {
+                Some(f) => {
+                    let input = args
+                        .iter()
+                        .map(|arg| Expression(Box::new(arg.clone())).try_into())
+                        .collect::<Result<Vec<_>>>()?;
+                    f.to_expr(input)
+                }
+                None => Err(unsupported!("function {} is not supported", name)),
+            },
         }
     }
 }
@@ -139,7 +327,7 @@ impl TryFrom<Operation> for Operator {
             SqlBinaryOperator::NotEq => Ok(Self::NotEq),
             SqlBinaryOperator::And => Ok(Self::And),
             SqlBinaryOperator::Or => Ok(Self::Or),
-            v => Err(anyhow!("Operator {} is not supported", v)),
+            v => Err(unsupported!("Operator {} is not supported", v)),
         }
     }
 }
@@ -150,17 +338,22 @@ impl<'a> TryFrom<Projection<'a>> for Expr {
 
     fn try_from(p: Projection<'a>) -> Result<Self, Self::Error> {
         match p.0 {
-            SelectItem::UnnamedExpr(SqlExpr::Identifier(id)) => Ok(col(&id.to_string())),
+            SelectItem::UnnamedExpr(SqlExpr::Identifier(id)) => Ok(col(&id.value)),
             SelectItem::ExprWithAlias {
                 expr: SqlExpr::Identifier(id),
                 alias,
             } => Ok(Expr::Alias(
-                Box::new(Expr::Column(Arc::new(id.to_string()))),
-                Arc::new(alias.to_string()),
+                Box::new(Expr::Column(Arc::new(id.value.clone()))),
+                Arc::new(alias.value.clone()),
             )),
-            SelectItem::QualifiedWildcard(v) => Ok(col(&v.to_string())),
+            // 只有一个数据源，t.* 就是 *
+            SelectItem::QualifiedWildcard(_) => Ok(col("*")),
             SelectItem::Wildcard => Ok(col("*")),
-            item => Err(anyhow!("projection {} not supported", item)),
+            SelectItem::UnnamedExpr(expr) => Expression(Box::new(expr.clone())).try_into(),
+            SelectItem::ExprWithAlias { expr, alias } => Ok(Expr::Alias(
+                Box::new(Expression(Box::new(expr.clone())).try_into()?),
+                Arc::new(alias.value.clone()),
+            )),
         }
     }
 }
@@ -170,18 +363,101 @@ impl<'a> TryFrom<Source<'a>> for &'a str {
 
     fn try_from(source: Source<'a>) -> Result<Self, Self::Error> {
         if source.0.len() != 1 {
-            return Err(anyhow!("We only support single data source at the moment"));
+            return Err(unsupported!(
+                "We only support single data source at the moment"
+            ));
         }
 
-        let table = &source.0[0];
-        if !table.joins.is_empty() {
-            return Err(anyhow!("We do not support joint data source at the moment"));
-        }
+        // JOIN 的数据源由 JoinClause 转换
+        table_name(&source.0[0].relation)
+    }
+}
+
+fn table_name(relation: &TableFactor) -> Result<&str> {
+    match relation {
+        TableFactor::Table { name, .. } => Ok(&name.0.first().unwrap().value),
+        _ => Err(unsupported!("We only support table")),
+    }
+}
+
+fn table_alias(relation: &TableFactor) -> Option<String> {
+    match relation {
+        TableFactor::Table {
+            alias: Some(alias), ..
+        } => Some(alias.name.value.clone()),
+        _ => None,
+    }
+}
+
+/// 把 SqlParser 的 JOIN 转换成 Join，只支持 INNER / LEFT JOIN 和 ON 里的列相等条件
+impl<'a> TryFrom<JoinClause<'a>> for Join<'a> {
+    type Error = anyhow::Error;
 
-        match &table.relation {
-            TableFactor::Table { name, .. } => Ok(&name.0.first().unwrap().value),
-            _ => Err(anyhow!("We only support table")),
+    fn try_from(join: JoinClause<'a>) -> Result<Self, Self::Error> {
+        let (how, constraint) = match &join.0.join_operator {
+            JoinOperator::Inner(c) => (JoinType::Inner, c),
+            JoinOperator::LeftOuter(c) => (JoinType::Left, c),
+            op => {
+                return Err(unsupported!(
+                    "We only support INNER and LEFT JOIN, got {:?}",
+                    op
+                ))
+            }
+        };
+        let on = match constraint {
+            JoinConstraint::On(expr) => equi_keys(expr)?,
+            // USING (k) 就是 ON 左边的 k = 右边的 k
+            JoinConstraint::Using(ids) => ids
+                .iter()
+                .map(|id| (id.value.clone(), id.value.clone()))
+                .collect(),
+            _ => return Err(unsupported!("JOIN needs an ON or USING condition")),
+        };
+        Ok(Join {
+            source: table_name(&join.0.relation)?,
+            alias: table_alias(&join.0.relation),
+            on,
+            how,
+        })
+    }
+}
+
+/// ON 条件里用 AND 连接的 `列 = 列`
+fn equi_keys(expr: &SqlExpr) -> Result<Vec<(String, String)>> {
+    match expr {
+        SqlExpr::BinaryOp {
+            left,
+            op: SqlBinaryOperator::And,
+            right,
+        } => {
+            let mut keys = equi_keys(left)?;
+            keys.extend(equi_keys(right)?);
+            Ok(keys)
         }
+        SqlExpr::Nested(expr) => equi_keys(expr),
+        SqlExpr::BinaryOp {
+            left,
+            op: SqlBinaryOperator::Eq,
+            right,
+        } => match (column_name(left), column_name(right)) {
+            (Some(left), Some(right)) => Ok(vec![(left, right)]),
+            _ => Err(unsupported!(
+                "We only support equality between columns in JOIN ON, got {}",
+                expr
+            )),
+        },
+        expr => Err(unsupported!(
+            "We only support equality between columns in JOIN ON, got {}",
+            expr
+        )),
+    }
+}
+
+fn column_name(expr: &SqlExpr) -> Option<String> {
+    match expr {
+        SqlExpr::Identifier(id) => Some(id.value.clone()),
+        SqlExpr::CompoundIdentifier(ids) => Some(qualified_name(ids)),
+        _ => None,
     }
 }
 
@@ -191,7 +467,8 @@ impl<'a> TryFrom<Order<'a>> for (String, bool) {
 
     fn try_from(o: Order) -> Result<Self, Self::Error> {
         let name = match &o.0.expr {
-            SqlExpr::Identifier(id) => id.to_string(),
+            SqlExpr::Identifier(id) => id.value.clone(),
+            SqlExpr::CompoundIdentifier(ids) => qualified_name(ids),
             expr => {
                 return Err(anyhow!(
                     "We only support identifier for order by, got {}",
@@ -205,24 +482,113 @@ impl<'a> TryFrom<Order<'a>> for (String, bool) {
 }
 
 /// 把 SqlParser 的 offset expr 转换成 i64
-impl<'a> From<Offset<'a>> for i64 {
-    fn from(offset: Offset) -> Self {
-        match offset.0 {
-            SqlOffset {
-                value: SqlExpr::Value(SqlValue::Number(v, _b)),
-                ..
-            } => v.parse().unwrap_or(0),
-            _ => 0,
+impl<'a> TryFrom<Offset<'a>> for i64 {
+    type Error = anyhow::Error;
+
+    fn try_from(offset: Offset) -> Result<Self, Self::Error> {
+        match const_int(&offset.0.value)? {
+            v if v < 0 => Err(anyhow!("OFFSET must not be negative, got {}", v)),
+            v => Ok(v),
         }
     }
 }
 
 /// 把 SqlParser 的 Limit expr 转换成 usize
-impl<'a> From<Limit<'a>> for usize {
-    fn from(l: Limit<'a>) -> Self {
-        match l.0 {
-            SqlExpr::Value(SqlValue::Number(v, _b)) => v.parse().unwrap_or(usize::MAX),
-            _ => usize::MAX,
+impl<'a> TryFrom<Limit<'a>> for usize {
+    type Error = anyhow::Error;
+
+    fn try_from(l: Limit<'a>) -> Result<Self, Self::Error> {
+        match const_int(l.0)? {
+            v if v < 0 => Err(anyhow!("LIMIT must not be negative, got {}", v)),
+            v => Ok(v as usize),
+        }
+    }
+}
+
+/// 计算只包含整数常量的表达式，LIMIT / OFFSET 要用
+pub(crate) fn const_int(expr: &SqlExpr) -> Result<i64> {
+    let overflow = || anyhow!("integer overflow in {}", expr);
+    match expr {
+        SqlExpr::Value(SqlValue::Number(v, _)) => v
+            .parse()
+            .map_err(|_| anyhow!("expect an integer, got {}", v)),
+        SqlExpr::Nested(expr) => const_int(expr),
+        SqlExpr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => const_int(expr)?.checked_neg().ok_or_else(overflow),
+        SqlExpr::UnaryOp {
+            op: UnaryOperator::Plus,
+            expr,
+        } => const_int(expr),
+        SqlExpr::BinaryOp { left, op, right } => {
+            let (l, r) = (const_int(left)?, const_int(right)?);
+            let v = match op {
+                SqlBinaryOperator::Plus => l.checked_add(r),
+                SqlBinaryOperator::Minus => l.checked_sub(r),
+                SqlBinaryOperator::Multiply => l.checked_mul(r),
+                SqlBinaryOperator::Divide => l.checked_div(r),
+                SqlBinaryOperator::Modulo => l.checked_rem(r),
+                op => return Err(unsupported!("Operator {} is not supported in constant", op)),
+            };
+            v.ok_or_else(overflow)
+        }
+        expr => Err(anyhow!("expect an integer constant, got {}", expr)),
+    }
+}
+
+/// 把 where 子句中远端数据库也能执行的部分转换成 SQL 字符串
+/// 只下推 `列 比较 常量`、`IS [NOT] NULL` 以及它们的 AND / OR 组合，
+/// 下推只是为了少传数据，本地仍然会用完整的 condition 再过滤一遍
+impl<'a> From<Pushdown<'a>> for Option<String> {
+    fn from(p: Pushdown<'a>) -> Self {
+        match p.0 {
+            SqlExpr::BinaryOp {
+                left,
+                op: SqlBinaryOperator::And,
+                right,
+            } => {
+                // AND 的两边可以分别下推，只推能推的那一半也不影响结果
+                let left: Option<String> = Pushdown(left).into();
+                let right: Option<String> = Pushdown(right).into();
+                match (left, right) {
+                    (Some(l), Some(r)) => Some(format!("({}) AND ({})", l, r)),
+                    (l, r) => l.or(r),
+                }
+            }
+            SqlExpr::BinaryOp {
+                left,
+                op: SqlBinaryOperator::Or,
+                right,
+            } => {
+                // OR 必须两边都能下推
+                let left: Option<String> = Pushdown(left).into();
+                let right: Option<String> = Pushdown(right).into();
+                Some(format!("({}) OR ({})", left?, right?))
+            }
+            SqlExpr::BinaryOp { left, op, right } => match (left.as_ref(), op, right.as_ref()) {
+                (
+                    SqlExpr::Identifier(id),
+                    SqlBinaryOperator::Eq
+                    | SqlBinaryOperator::NotEq
+                    | SqlBinaryOperator::Gt
+                    | SqlBinaryOperator::Lt
+                    | SqlBinaryOperator::GtEq
+                    | SqlBinaryOperator::LtEq,
+                    SqlExpr::Value(
+                        SqlValue::Number(..)
+                        | SqlValue::SingleQuotedString(_)
+                        | SqlValue::Boolean(_),
+                    ),
+                ) if is_plain_column(id) => Some(p.0.to_string()),
+                _ => None,
+            },
+            SqlExpr::IsNull(expr) | SqlExpr::IsNotNull(expr) => match expr.as_ref() {
+                SqlExpr::Identifier(id) if is_plain_column(id) => Some(p.0.to_string()),
+                _ => None,
+            },
+            SqlExpr::Nested(expr) => Pushdown(expr).into(),
+            _ => None,
         }
     }
 }
@@ -232,14 +598,48 @@ impl TryFrom<Value> for LiteralValue {
     type Error = anyhow::Error;
     fn try_from(v: Value) -> Result<Self, Self::Error> {
         match v.0 {
-            SqlValue::Number(v, _) => Ok(LiteralValue::Float64(v.parse().unwrap())),
+            SqlValue::Number(v, _) => number(&v),
+            SqlValue::SingleQuotedString(v) => Ok(LiteralValue::Utf8(v)),
             SqlValue::Boolean(v) => Ok(LiteralValue::Boolean(v)),
             SqlValue::Null => Ok(LiteralValue::Null),
-            v => Err(anyhow!("Value {} is not supported", v)),
+            v => Err(unsupported!("Value {} is not supported", v)),
         }
     }
 }
 
+/// 没有引号的 t.col 会被 TyrDialect 解析成一个标识符，远端数据库不认识表别名，不能下推
+fn is_plain_column(id: &Ident) -> bool {
+    id.quote_style.is_some() || !id.value.contains('.')
+}
+
+/// 把 ["t", "col"] 拼成 t.col
+fn qualified_name(ids: &[Ident]) -> String {
+    ids.iter()
+        .map(|id| id.value.as_str())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// 整数用 Int64，其它用 Float64
+/// Float64 只能精确表示 15 位有效数字，超出的话报错，而不是悄悄丢掉精度
+fn number(v: &str) -> Result<LiteralValue> {
+    if let Ok(n) = v.parse::<i64>() {
+        return Ok(LiteralValue::Int64(n));
+    }
+
+    let f: f64 = v.parse().map_err(|_| anyhow!("invalid number {}", v))?;
+    let mantissa = v.split(['e', 'E']).next().unwrap_or_default();
+    let digits: String = mantissa.chars().filter(|c| c.is_ascii_digit()).collect();
+    let digits = digits.trim_start_matches('0').trim_end_matches('0');
+    if digits.len() > 15 || f.is_infinite() {
+        return Err(anyhow!(
+            "number {} can not be represented without losing precision",
+            v
+        ));
+    }
+    Ok(LiteralValue::Float64(f))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -250,15 +650,129 @@ mod tests {
     fn parse_sql_works() {
         let url = "http://abc.xyz/abc?a=1&b=2";
         let sql = format!(
-            "select a, b, c from {} where a=1 order by c desc limit 5 offset 10",
+            "select a, b, c from {} where a = 1 order by c desc limit 5 offset 10",
             url
         );
-        let statement = &Parser::parse_sql(&TyrDialect::default(), sql.as_ref()).unwrap()[0];
+        let statement = &Parser::parse_sql(&TyrDialect, sql.as_ref()).unwrap()[0];
         let sql: Sql = statement.try_into().unwrap();
         assert_eq!(sql.source, url);
         assert_eq!(sql.limit, Some(5));
         assert_eq!(sql.offset, Some(10));
         assert_eq!(sql.order_by, vec![("c".into(), true)]);
         assert_eq!(sql.selection, vec![col("a"), col("b"), col("c")]);
+        assert_eq!(sql.pushdown, Some("a = 1".into()));
+    }
+
+    #[test]
+    fn pushdown_only_keeps_simple_predicates() {
+        let sql = "select a from t where a > 1 and b + 1 = 2 and (c = 'x' or d is null)";
+        let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+        assert_eq!(
+            sql.pushdown,
+            Some("(a > 1) AND ((c = 'x') OR (d IS NULL))".into())
+        );
+
+        let sql = "select a from t where a > 1 or b + 1 = 2";
+        let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+        assert_eq!(sql.pushdown, None);
+    }
+
+    #[test]
+    fn fetch_first_works() {
+        let sql = "select a from 'file:///tmp/in.csv' offset 1 rows fetch first 2 rows only";
+        let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+        assert_eq!(sql.source, "file:///tmp/in.csv");
+        assert_eq!(sql.limit, Some(2));
+        assert_eq!(sql.offset, Some(1));
+
+        let sql = "select a from 'file:///tmp/in.csv' fetch first 10 percent rows only";
+        let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+        assert!(Sql::try_from(statement).is_err());
+    }
+
+    #[test]
+    fn quoted_and_qualified_identifier_works() {
+        let sql = r#"select "t"."Total Cases", t.location as 国家 from file:///tmp/a.csv as t where "Total Cases" > 1 and t.new_deaths > 10 order by "t"."location""#;
+        let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+        assert_eq!(sql.alias, Some("t".into()));
+        assert_eq!(sql.order_by, vec![("t.location".into(), false)]);
+        assert_eq!(sql.pushdown, Some(r#""Total Cases" > 1"#.into()));
+        assert_eq!(
+            sql.columns(),
+            vec!["Total Cases", "t.Total Cases", "t.location", "t.new_deaths"]
+        );
+    }
+
+    #[test]
+    fn join_works() {
+        let sql = "select p.name, o.amount from people as p join file:///tmp/orders.csv as o on o.person_id = p.id and (p.region = o.region) where o.amount > 10";
+        let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+        assert_eq!(sql.source, "people");
+        assert_eq!(sql.joins.len(), 1);
+        assert_eq!(sql.joins[0].source, "file:///tmp/orders.csv");
+        assert_eq!(sql.joins[0].how, JoinType::Inner);
+        assert_eq!(
+            sql.joins[0].on,
+            vec![
+                ("o.person_id".into(), "p.id".into()),
+                ("p.region".into(), "o.region".into())
+            ]
+        );
+        assert_eq!(sql.qualifiers(), vec!["p", "o"]);
+        assert!(sql.columns().contains(&"o.person_id".to_string()));
+
+        let sql = "select a from t left join u using (k)";
+        let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+        assert_eq!(sql.joins[0].how, JoinType::Left);
+        assert_eq!(sql.qualifiers(), vec!["t", "u"]);
+
+        for sql in [
+            "select a from t join u on t.k > u.k",
+            "select a from t cross join u",
+            "select a from t full outer join u on t.k = u.k",
+        ] {
+            let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+            assert!(Sql::try_from(statement).is_err());
+        }
+    }
+
+    #[test]
+    fn number_literal_works() {
+        assert_eq!(number("42").unwrap(), LiteralValue::Int64(42));
+        assert_eq!(number("-3").unwrap(), LiteralValue::Int64(-3));
+        assert_eq!(number("1.5").unwrap(), LiteralValue::Float64(1.5));
+        assert_eq!(
+            number("100000000000000000000").unwrap(),
+            LiteralValue::Float64(1e20)
+        );
+        assert!(number("12345678901234567890").is_err());
+        assert!(number("0.12345678901234567").is_err());
+        assert!(number("1.2.3").is_err());
+    }
+
+    #[test]
+    fn date_functions_works() {
+        let sql = "select extract(year from d), date_trunc('month', d) as m, d + interval '1' day \
+                   from t where d >= DATE '2023-01-01' and d < now()";
+        let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+        assert_eq!(sql.selection.len(), 3);
+        assert_eq!(sql.pushdown, None);
+
+        let sql = "select a from t where d >= DATE '2023-01-01'";
+        let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+        let date = chrono::NaiveDate::from_ymd_opt(2023, 1, 1).unwrap();
+        assert_eq!(sql.condition, Some(col("d").gt_eq(lit(date))));
+
+        let sql = "select date_trunc('decade', d) from t";
+        let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+        assert!(Sql::try_from(statement).is_err());
     }
 }
@@ -12,23 +12,72 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use sqlparser::dialect::Dialect;
+use anyhow::{anyhow, Result};
+use sqlparser::dialect::{AnsiDialect, Dialect, MySqlDialect, PostgreSqlDialect};
+use std::str::FromStr;
 
 #[derive(Debug, Default)]
 pub struct TyrDialect;
 
 // 实现Dialect , 创建自己的sql方言
 impl Dialect for TyrDialect {
+    // 支持中文等非 ASCII 的列名
     fn is_identifier_start(&self, ch: char) -> bool {
-        ('a'..='z').contains(&ch) || ('A'..='Z').contains(&ch) || ch == '_'
+        ch.is_alphabetic() || ch == '_'
     }
 
-    // identifier可以有 ':', '/', '?', '&', '='
+    // identifier可以有 ':', '/', '?', '&', '=', '@'(数据库连接串里的 user@host), '#'(电子表格的工作表)
     fn is_identifier_part(&self, ch: char) -> bool {
-        ('a'..='z').contains(&ch)
-            || ('A'..='Z').contains(&ch)
-            || ('0'..='9').contains(&ch)
-            || [':', '/', '?', '&', '=', '-', '_', '.'].contains(&ch)
+        ch.is_alphanumeric() || [':', '/', '?', '&', '=', '-', '_', '.', '@', '#'].contains(&ch)
+    }
+}
+
+/// 可以选择的 SQL 方言，决定标识符的引号、字符串拼接、LIMIT 的写法和列名的大小写
+/// 除了 Tyr 之外，数据源都要写成字符串：`FROM 'https://...'`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DialectMode {
+    /// 默认方言，数据源的 url 可以直接写在 FROM 后面，列名区分大小写
+    #[default]
+    Tyr,
+    /// 标准 SQL：只支持 OFFSET n ROWS FETCH FIRST n ROWS ONLY，列名不区分大小写
+    Ansi,
+    /// 类似 PostgreSQL：没加引号的标识符转成小写，加了双引号的保持原样
+    Postgres,
+    /// 类似 MySQL：标识符用反引号，|| 是 OR，支持 LIMIT offset, count，列名不区分大小写
+    MySql,
+}
+
+impl FromStr for DialectMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "tyr" => Ok(Self::Tyr),
+            "ansi" => Ok(Self::Ansi),
+            "postgres" | "postgresql" => Ok(Self::Postgres),
+            "mysql" => Ok(Self::MySql),
+            v => Err(anyhow!(
+                "dialect {} is not supported, expect tyr / ansi / postgres / mysql",
+                v
+            )),
+        }
+    }
+}
+
+impl DialectMode {
+    /// 分词和解析用的 sqlparser 方言
+    pub(crate) fn dialect(&self) -> Box<dyn Dialect> {
+        match self {
+            Self::Tyr => Box::new(TyrDialect),
+            Self::Ansi => Box::new(AnsiDialect {}),
+            Self::Postgres => Box::new(PostgreSqlDialect {}),
+            Self::MySql => Box::new(MySqlDialect {}),
+        }
+    }
+
+    /// 这个方言下列名是否不区分大小写
+    pub(crate) fn case_insensitive(&self) -> bool {
+        matches!(self, Self::Ansi | Self::MySql)
     }
 }
 
@@ -53,6 +102,22 @@ mod tests {
 
     #[test]
     fn it_works() {
-        assert!(Parser::parse_sql(&TyrDialect::default(), &example_sql()).is_ok());
+        assert!(Parser::parse_sql(&TyrDialect, &example_sql()).is_ok());
+    }
+
+    #[test]
+    fn dialect_mode_from_str_works() {
+        assert_eq!(
+            "PostgreSQL".parse::<DialectMode>().unwrap(),
+            DialectMode::Postgres
+        );
+        assert_eq!("mysql".parse::<DialectMode>().unwrap(), DialectMode::MySql);
+        assert!("oracle".parse::<DialectMode>().is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unicode_identifier_works() {
+        let sql = "SELECT 国家, \"Total Cases\" FROM file:///tmp/数据.csv";
+        assert!(Parser::parse_sql(&TyrDialect, sql).is_ok());
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, Result};
+use polars::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// diff 结果里标记每一行变化类型的列：added / removed / changed
+pub(crate) const CHANGE_COLUMN: &str = "change";
+
+/// 按 key 对比 current 和 baseline 两份数据
+/// 结果里每行是一个变化：key 列、change 列，以及每个其它列的 `<列名>_before` / `<列名>_after`
+/// 先按 current 的顺序输出新增和修改的行，再按 baseline 的顺序输出删除的行
+pub(crate) fn diff(current: &DataFrame, baseline: &DataFrame, key: &[&str]) -> Result<DataFrame> {
+    if key.is_empty() {
+        return Err(anyhow!("DIFF needs at least one KEY column"));
+    }
+    let baseline = align(current, baseline)?;
+
+    let index = unique_keys(&baseline, key)?;
+    let current_keys = row_keys(current, key)?;
+    unique_keys(current, key)?;
+
+    // key 以外的列，只在一边出现的列另一边的值当作 null
+    let mut values = Vec::new();
+    for name in current
+        .get_column_names()
+        .into_iter()
+        .chain(baseline.get_column_names())
+    {
+        if !key.contains(&name) && !values.contains(&name) {
+            values.push(name);
+        }
+    }
+
+    // (变化类型, baseline 里的行, current 里的行)
+    let mut changes = Vec::new();
+    let mut matched = HashSet::new();
+    for (i, k) in current_keys.iter().enumerate() {
+        match index.get(k) {
+            None => changes.push(("added", None, Some(i as u32))),
+            Some(&j) => {
+                matched.insert(j);
+                let changed = values
+                    .iter()
+                    .any(|name| value(&baseline, name, j) != value(current, name, i));
+                if changed {
+                    changes.push(("changed", Some(j as u32), Some(i as u32)));
+                }
+            }
+        }
+    }
+    for j in (0..baseline.height()).filter(|j| !matched.contains(j)) {
+        changes.push(("removed", Some(j as u32), None));
+    }
+
+    let before: UInt32Chunked = changes.iter().map(|v| v.1).collect();
+    let after: UInt32Chunked = changes.iter().map(|v| v.2).collect();
+    let has_after: BooleanChunked = changes.iter().map(|v| v.2.is_some()).collect();
+
+    let mut columns = Vec::with_capacity(key.len() + 1 + values.len() * 2);
+    for name in key {
+        let current_key = current.column(name)?.take(&after)?;
+        let baseline_key = baseline.column(name)?.take(&before)?;
+        columns.push(current_key.zip_with(&has_after, &baseline_key)?);
+    }
+    let kinds: Vec<&str> = changes.iter().map(|v| v.0).collect();
+    columns.push(Series::new(CHANGE_COLUMN, kinds));
+    for name in values {
+        columns.push(take_column(&baseline, current, name, &before, "before")?);
+        columns.push(take_column(current, &baseline, name, &after, "after")?);
+    }
+    Ok(DataFrame::new(columns)?)
+}
+
+/// 两边同名列的类型不一致时（比如 i64 和 f64），把 baseline 的列转换成 current 的类型再比较
+fn align(current: &DataFrame, baseline: &DataFrame) -> Result<DataFrame> {
+    let mut result = baseline.clone();
+    for s in baseline.get_columns() {
+        if let Ok(target) = current.column(s.name()) {
+            if target.dtype() != s.dtype() {
+                result.with_column(s.cast_with_dtype(target.dtype())?)?;
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// 每一行 key 列的值，用 Debug 格式区分 null 和字符串 "null"
+fn row_keys(df: &DataFrame, key: &[&str]) -> Result<Vec<Vec<String>>> {
+    let columns = key
+        .iter()
+        .map(|name| df.column(name))
+        .collect::<polars::prelude::Result<Vec<_>>>()?;
+    Ok((0..df.height())
+        .map(|i| columns.iter().map(|s| format!("{:?}", s.get(i))).collect())
+        .collect())
+}
+
+/// key 到行号的映射，key 重复时没法一一对应，直接报错
+fn unique_keys(df: &DataFrame, key: &[&str]) -> Result<HashMap<Vec<String>, usize>> {
+    let mut index = HashMap::new();
+    for (i, k) in row_keys(df, key)?.into_iter().enumerate() {
+        if let Some(v) = index.insert(k, i) {
+            return Err(anyhow!(
+                "KEY ({}) is not unique, found duplicates at row {} and {}",
+                key.join(", "),
+                v,
+                i
+            ));
+        }
+    }
+    Ok(index)
+}
+
+fn value<'a>(df: &'a DataFrame, name: &str, i: usize) -> AnyValue<'a> {
+    df.column(name).map(|s| s.get(i)).unwrap_or(AnyValue::Null)
+}
+
+/// 按行号取出一列并加上后缀，df 里没有这一列时取 other 里的同名列，值全部是 null
+fn take_column(
+    df: &DataFrame,
+    other: &DataFrame,
+    name: &str,
+    idx: &UInt32Chunked,
+    suffix: &str,
+) -> Result<Series> {
+    let mut s = match df.column(name) {
+        Ok(s) => s.take(idx)?,
+        Err(_) => {
+            let nulls: UInt32Chunked = (0..idx.len()).map(|_| None).collect();
+            other.column(name)?.take(&nulls)?
+        }
+    };
+    s.rename(&format!("{}_{}", name, suffix));
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_works() {
+        let baseline = DataFrame::new(vec![
+            Series::new("location", &["Italy", "Romania", "Finland"]),
+            Series::new("new_deaths", &[21i64, 14, 14]),
+        ])
+        .unwrap();
+        let current = DataFrame::new(vec![
+            Series::new("location", &["Italy", "Finland", "France"]),
+            Series::new("new_deaths", &[30.0, 14.0, 3.0]),
+        ])
+        .unwrap();
+
+        let df = diff(&current, &baseline, &["location"]).unwrap();
+        assert_eq!(
+            df.get_column_names(),
+            vec![
+                "location",
+                "change",
+                "new_deaths_before",
+                "new_deaths_after"
+            ]
+        );
+        let location: Vec<_> = df
+            .column("location")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(
+            location,
+            vec![Some("Italy"), Some("France"), Some("Romania")]
+        );
+        let change: Vec<_> = df
+            .column("change")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(
+            change,
+            vec![Some("changed"), Some("added"), Some("removed")]
+        );
+        let before: Vec<_> = df
+            .column("new_deaths_before")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(before, vec![Some(21.0), None, Some(14.0)]);
+
+        let duplicated =
+            DataFrame::new(vec![Series::new("location", &["Italy", "Italy"])]).unwrap();
+        assert!(diff(&duplicated, &baseline, &["location"]).is_err());
+        assert!(diff(&current, &baseline, &["name"]).is_err());
+    }
+}
@@ -0,0 +1,319 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// 内部还是用 anyhow 传递错误，这里的几种错误类型放在错误链里，
+// queryer-py 这样的调用方可以用 ErrorKind::of 区分错误，映射成各自的异常
+
+use sqlparser::{
+    parser::ParserError,
+    tokenizer::{Token, TokenizerError},
+};
+use std::fmt;
+
+/// 错误的分类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// SQL 写得不对：词法、语法错误或者参数缺失
+    Parse,
+    /// 读取数据源失败：文件不存在、网络错误、数据库连不上等
+    Fetch,
+    /// SQL 合法，但 queryer 还不支持
+    Unsupported,
+    /// Session 的 [Permissions](crate::Permissions) 不允许这个操作
+    Forbidden,
+    /// 其它错误，比如执行查询时列不存在
+    Other,
+}
+
+impl ErrorKind {
+    /// 按错误链里的错误类型分类，不支持优先，比如不支持的 scheme 也发生在读取数据源时
+    /// downcast_ref 会检查 context 和被它包起来的错误
+    pub fn of(e: &anyhow::Error) -> Self {
+        if e.downcast_ref::<Forbidden>().is_some() {
+            Self::Forbidden
+        } else if e.downcast_ref::<Unsupported>().is_some() {
+            Self::Unsupported
+        } else if e.downcast_ref::<ParseError>().is_some() {
+            Self::Parse
+        } else if e.downcast_ref::<FetchError>().is_some() {
+            Self::Fetch
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// SQL 解析错误，带上出错的 SQL 片段和位置（行列都从 1 开始）
+/// sqlparser 的 token 没有位置，语法错误的位置按出错的 token 是 SQL 里第几个同样的 token 来找
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub fragment: Option<String>,
+    pub line: Option<u64>,
+    pub column: Option<u64>,
+}
+
+impl ParseError {
+    /// 包装 parse_command 里的错误，不支持的语法保持原样
+    pub(crate) fn wrap(sql: &str, e: anyhow::Error) -> anyhow::Error {
+        if e.is::<Unsupported>() || e.is::<ParseError>() {
+            return e;
+        }
+        let error = match e.downcast_ref::<ParserError>() {
+            Some(ParserError::ParserError(message)) => Self::from_parser(sql, message, &[], &[], 0),
+            Some(ParserError::TokenizerError(message)) => Self::new(message),
+            None => Self::new(format!("{:#}", e)),
+        };
+        error.into()
+    }
+
+    pub(crate) fn from_tokenizer(sql: &str, e: TokenizerError) -> Self {
+        // 出错的位置到这一行结束，最多取 20 个字符
+        let fragment = sql
+            .lines()
+            .nth(e.line.saturating_sub(1) as usize)
+            .map(|line| {
+                line.chars()
+                    .skip(e.col.saturating_sub(1) as usize)
+                    .take(20)
+                    .collect::<String>()
+            })
+            .filter(|v| !v.trim().is_empty());
+        Self {
+            message: e.message,
+            fragment,
+            line: Some(e.line),
+            column: Some(e.col),
+        }
+    }
+
+    /// sqlparser 的错误信息是 `Expected ..., found: <token>`，按 token 在 SQL 里找位置
+    /// source 是 SQL 原始的 token（包括空白），parsed 是交给 parser 的 token，parser 读到第 index 个时出错，
+    /// 不知道这些时（传空的 token）就用 token 在 SQL 里第一次出现的位置
+    pub(crate) fn from_parser(
+        sql: &str,
+        message: &str,
+        source: &[Token],
+        parsed: &[Token],
+        index: usize,
+    ) -> Self {
+        let found = message.rsplit_once("found: ").map(|(_, v)| v.trim());
+        let (fragment, offset) = match found {
+            Some("EOF") => (None, Some(sql.trim_end().len())),
+            Some(token) => {
+                // 出错的 token 可能是 parser 看了一眼的下一个，也可能是刚读过的那个
+                let at = match parsed.get(index) {
+                    Some(t) if t.to_string() == token => index,
+                    _ => index.saturating_sub(1).min(parsed.len()),
+                };
+                let nth = parsed[..at]
+                    .iter()
+                    .filter(|t| t.to_string() == token)
+                    .count();
+                let offset = token_offset(sql, source, token, nth).or_else(|| sql.find(token));
+                (Some(token.to_owned()), offset)
+            }
+            None => (None, None),
+        };
+        let (line, column) = match offset {
+            Some(offset) => {
+                let (line, column) = position(sql, offset);
+                (Some(line), Some(column))
+            }
+            None => (None, None),
+        };
+        Self {
+            message: message.to_owned(),
+            fragment,
+            line,
+            column,
+        }
+    }
+
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            fragment: None,
+            line: None,
+            column: None,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, " at Line: {}, Column {}", line, column)?;
+        }
+        if let Some(fragment) = &self.fragment {
+            write!(f, " near {:?}", fragment)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// SQL 里用到了还不支持的功能，用 [unsupported!] 生成
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unsupported(pub String);
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// Session 不允许执行的操作
+#[derive(Debug, Clone, PartialEq)]
+pub struct Forbidden(pub String);
+
+impl fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Forbidden {}
+
+/// 读取数据源失败，作为 context 加在底层的错误上
+#[derive(Debug, Clone, PartialEq)]
+pub struct FetchError(pub String);
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to fetch {}", self.0)
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+/// 和 anyhow! 一样生成错误，但会被识别成 ErrorKind::Unsupported
+macro_rules! unsupported {
+    ($($arg:tt)*) => {
+        anyhow::Error::new($crate::error::Unsupported(format!($($arg)*)))
+    };
+}
+pub(crate) use unsupported;
+
+/// SQL 里第 nth 个（从 0 开始）文本是 token 的 token 的字节偏移
+/// token 按顺序在 SQL 里往后找，转义过的字符串之类和原文对不上的 token 直接跳过
+fn token_offset(sql: &str, source: &[Token], token: &str, nth: usize) -> Option<usize> {
+    let mut cursor = 0;
+    let mut seen = 0;
+    for t in source {
+        let text = t.to_string();
+        let offset = match sql[cursor..].find(&text) {
+            Some(v) => cursor + v,
+            None => continue,
+        };
+        cursor = offset + text.len();
+        if text == token {
+            if seen == nth {
+                return Some(offset);
+            }
+            seen += 1;
+        }
+    }
+    None
+}
+
+/// 字节偏移转换成行列，都从 1 开始
+fn position(sql: &str, offset: usize) -> (u64, u64) {
+    let before = &sql[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+    (line as u64, column as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::parse_command;
+    use crate::dialect::DialectMode;
+
+    fn parse_error(sql: &str) -> anyhow::Error {
+        parse_command(DialectMode::Tyr, sql, &[]).unwrap_err()
+    }
+
+    #[test]
+    fn parse_error_works() {
+        let e = parse_error("SELECT a FROM t WHERE\n  a > > 1");
+        assert_eq!(ErrorKind::of(&e), ErrorKind::Parse);
+        let e = e.downcast::<ParseError>().unwrap();
+        assert_eq!(e.fragment.as_deref(), Some(">"));
+        assert_eq!((e.line, e.column), (Some(2), Some(7)));
+
+        // 出错的 token 前面出现过同样的文本
+        let e = parse_error("SELECT b FROM t WHERE a IN b")
+            .downcast::<ParseError>()
+            .unwrap();
+        assert_eq!(e.fragment.as_deref(), Some("b"));
+        assert_eq!((e.line, e.column), (Some(1), Some(28)));
+
+        // 多字节字符不会让位置错乱
+        let e = parse_error("SELECT İ FROM t WHERE a IN 国家")
+            .downcast::<ParseError>()
+            .unwrap();
+        assert_eq!(e.fragment.as_deref(), Some("国家"));
+        assert_eq!((e.line, e.column), (Some(1), Some(28)));
+
+        let e = parse_error("SELECT a FROM t WHERE")
+            .downcast::<ParseError>()
+            .unwrap();
+        assert_eq!(e.fragment, None);
+        assert_eq!((e.line, e.column), (Some(1), Some(22)));
+
+        let e = parse_error("SELECT 'abc FROM t");
+        assert_eq!(ErrorKind::of(&e), ErrorKind::Parse);
+        assert_eq!(e.downcast::<ParseError>().unwrap().line, Some(1));
+    }
+
+    #[test]
+    fn error_kind_works() {
+        let e = parse_error("SELECT 1; SELECT 2");
+        assert_eq!(ErrorKind::of(&e), ErrorKind::Unsupported);
+
+        let e = unsupported!("scheme {} is not supported", "ftp")
+            .context(FetchError("ftp://abc".into()));
+        assert_eq!(ErrorKind::of(&e), ErrorKind::Unsupported);
+        assert_eq!(
+            format!("{:#}", e),
+            "failed to fetch ftp://abc: scheme ftp is not supported"
+        );
+
+        let e = anyhow::anyhow!("connection refused").context(FetchError("http://abc".into()));
+        assert_eq!(ErrorKind::of(&e), ErrorKind::Fetch);
+        assert_eq!(ErrorKind::of(&anyhow::anyhow!("oops")), ErrorKind::Other);
+    }
+
+    #[tokio::test]
+    async fn unknown_column_is_other() {
+        let path = std::env::temp_dir().join("queryer_error_unknown_column.csv");
+        std::fs::write(&path, "name,age\nTyr,18\n").unwrap();
+        let sql = format!("SELECT salary FROM file://{}", path.display());
+        let e = crate::query(sql).await.unwrap_err();
+        assert_eq!(ErrorKind::of(&e), ErrorKind::Other);
+        assert!(e.to_string().starts_with("column salary not found"));
+    }
+}
@@ -0,0 +1,285 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, Result};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    redirect::Policy,
+    Certificate, Client, Proxy, RequestBuilder,
+};
+use std::{env, path::PathBuf, time::Duration};
+
+/// url 数据源的 http 请求配置，所有字段都是可选的，没设置的用 reqwest 的默认行为
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HttpConfig {
+    pub headers: Vec<(String, String)>,
+    pub bearer_token: Option<String>,
+    pub basic_auth: Option<(String, Option<String>)>,
+    pub user_agent: Option<String>,
+    pub timeout: Option<Duration>,
+    pub proxy: Option<String>,
+    // PEM 格式的 CA 证书，用于自签名证书的内部服务
+    pub ca_bundle: Option<PathBuf>,
+    // 失败后的重试次数，每次重试的等待时间翻倍
+    pub retries: Option<u32>,
+    pub backoff: Option<Duration>,
+}
+
+impl HttpConfig {
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_token = Some(token.into());
+        self
+    }
+
+    pub fn basic_auth(mut self, user: impl Into<String>, password: Option<String>) -> Self {
+        self.basic_auth = Some((user.into(), password));
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    pub fn ca_bundle(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ca_bundle = Some(path.into());
+        self
+    }
+
+    pub fn retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = Some(retries);
+        self.backoff = Some(backoff);
+        self
+    }
+
+    /// 从环境变量读取配置：
+    /// - QUERYER_HTTP_HEADERS: `Name: value; Name2: value2`
+    /// - QUERYER_HTTP_BEARER_TOKEN
+    /// - QUERYER_HTTP_BASIC_AUTH: `user:password`
+    /// - QUERYER_HTTP_USER_AGENT
+    /// - QUERYER_HTTP_TIMEOUT: `30s` / `500ms` / `2m`
+    /// - QUERYER_HTTP_PROXY
+    /// - QUERYER_HTTP_CA_BUNDLE
+    /// - QUERYER_HTTP_RETRIES
+    /// - QUERYER_HTTP_BACKOFF
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut config = Self::default();
+        if let Some(v) = var("QUERYER_HTTP_HEADERS") {
+            for pair in v.split(';').filter(|p| !p.trim().is_empty()) {
+                let (name, value) = pair
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("invalid header {}, expect `Name: value`", pair))?;
+                config = config.header(name.trim(), value.trim());
+            }
+        }
+        config.bearer_token = var("QUERYER_HTTP_BEARER_TOKEN");
+        config.basic_auth = var("QUERYER_HTTP_BASIC_AUTH").map(|v| match v.split_once(':') {
+            Some((user, password)) => (user.to_owned(), Some(password.to_owned())),
+            None => (v, None),
+        });
+        config.user_agent = var("QUERYER_HTTP_USER_AGENT");
+        config.timeout = var("QUERYER_HTTP_TIMEOUT")
+            .map(|v| parse_duration(&v))
+            .transpose()?;
+        config.proxy = var("QUERYER_HTTP_PROXY");
+        config.ca_bundle = var("QUERYER_HTTP_CA_BUNDLE").map(PathBuf::from);
+        config.retries = var("QUERYER_HTTP_RETRIES").map(|v| v.parse()).transpose()?;
+        config.backoff = var("QUERYER_HTTP_BACKOFF")
+            .map(|v| parse_duration(&v))
+            .transpose()?;
+        Ok(config)
+    }
+
+    /// 用 other 里设置过的字段覆盖自己
+    /// other 里出现的 header 替换掉自己同名（不区分大小写）的 header，认证信息整体以 other 为准
+    pub fn merge(&self, other: &HttpConfig) -> Self {
+        let mut headers: Vec<_> = self
+            .headers
+            .iter()
+            .filter(|(name, _)| {
+                !other
+                    .headers
+                    .iter()
+                    .any(|(v, _)| v.eq_ignore_ascii_case(name))
+            })
+            .cloned()
+            .collect();
+        headers.extend(other.headers.iter().cloned());
+        let auth = match other.bearer_token.is_some() || other.basic_auth.is_some() {
+            true => other,
+            false => self,
+        };
+        Self {
+            headers,
+            bearer_token: auth.bearer_token.clone(),
+            basic_auth: auth.basic_auth.clone(),
+            user_agent: other.user_agent.clone().or_else(|| self.user_agent.clone()),
+            timeout: other.timeout.or(self.timeout),
+            proxy: other.proxy.clone().or_else(|| self.proxy.clone()),
+            ca_bundle: other.ca_bundle.clone().or_else(|| self.ca_bundle.clone()),
+            retries: other.retries.or(self.retries),
+            backoff: other.backoff.or(self.backoff),
+        }
+    }
+
+    /// 根据配置生成 reqwest client，redirect 决定跟随哪些重定向
+    pub(crate) async fn client(&self, redirect: Policy) -> Result<Client> {
+        let mut builder = Client::builder().redirect(redirect);
+        if let Some(v) = &self.user_agent {
+            builder = builder.user_agent(v.as_str());
+        }
+        if let Some(v) = self.timeout {
+            builder = builder.timeout(v);
+        }
+        if let Some(v) = &self.proxy {
+            builder = builder.proxy(Proxy::all(v.as_str())?);
+        }
+        if let Some(v) = &self.ca_bundle {
+            let pem = tokio::fs::read(v).await?;
+            builder = builder.add_root_certificate(Certificate::from_pem(&pem)?);
+        }
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+        Ok(builder.default_headers(headers).build()?)
+    }
+
+    /// 给请求加上认证信息
+    pub(crate) fn authorize(&self, req: RequestBuilder) -> RequestBuilder {
+        match (&self.bearer_token, &self.basic_auth) {
+            (Some(token), _) => req.bearer_auth(token),
+            (None, Some((user, password))) => req.basic_auth(user, password.as_ref()),
+            (None, None) => req,
+        }
+    }
+}
+
+/// 解析 `30s` / `500ms` / `2m` / `1h` 这样的时间，纯数字当作秒
+pub(crate) fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let pos = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(pos);
+    let value: f64 = value
+        .parse()
+        .map_err(|_| anyhow!("invalid duration {}", s))?;
+    let secs = match unit.trim() {
+        "" | "s" => value,
+        "ms" => value / 1000.0,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(anyhow!("invalid duration {}", s)),
+    };
+    // from_secs_f64 在溢出时会 panic，值可能来自 SET http.timeout，需要先检查
+    if !secs.is_finite() || secs >= u64::MAX as f64 {
+        return Err(anyhow!("duration {} is too large", s));
+    }
+    Ok(Duration::from_secs_f64(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn parse_duration_works() {
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert_eq!(parse_duration("5").unwrap(), Duration::from_secs(5));
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("99999999999999999999").is_err());
+        assert!(parse_duration("99999999999999999999h").is_err());
+    }
+
+    #[test]
+    fn from_vars_works() {
+        let vars: HashMap<&str, &str> = [
+            ("QUERYER_HTTP_HEADERS", "X-Api-Key: abc; X-Team: data"),
+            ("QUERYER_HTTP_BASIC_AUTH", "tyr:secret"),
+            ("QUERYER_HTTP_TIMEOUT", "10s"),
+            ("QUERYER_HTTP_RETRIES", "3"),
+        ]
+        .into_iter()
+        .collect();
+        let config = HttpConfig::from_vars(|k| vars.get(k).map(|v| v.to_string())).unwrap();
+        let expected = HttpConfig {
+            retries: Some(3),
+            ..HttpConfig::default()
+                .header("X-Api-Key", "abc")
+                .header("X-Team", "data")
+                .basic_auth("tyr", Some("secret".into()))
+                .timeout(Duration::from_secs(10))
+        };
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn merge_works() {
+        let base = HttpConfig::default()
+            .header("X-Team", "data")
+            .user_agent("queryer")
+            .timeout(Duration::from_secs(10));
+        let source = HttpConfig::default()
+            .bearer_token("token")
+            .timeout(Duration::from_secs(30));
+        let config = base.merge(&source);
+        assert_eq!(config.headers, vec![("X-Team".into(), "data".into())]);
+        assert_eq!(config.bearer_token, Some("token".into()));
+        assert_eq!(config.user_agent, Some("queryer".into()));
+        assert_eq!(config.timeout, Some(Duration::from_secs(30)));
+
+        // 同名的 header 和认证信息以更具体的配置为准
+        let base = base.header("X-Api-Key", "a").bearer_token("token");
+        let source = HttpConfig::default()
+            .header("x-api-key", "b")
+            .basic_auth("tyr", None);
+        let config = base.merge(&source);
+        assert_eq!(
+            config.headers,
+            vec![
+                ("X-Team".into(), "data".into()),
+                ("x-api-key".into(), "b".into())
+            ]
+        );
+        assert_eq!(config.bearer_token, None);
+        assert_eq!(config.basic_auth, Some(("tyr".into(), None)));
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{fetcher::retrieve_appended, settings::first_record_end, Session};
+use anyhow::Result;
+use polars::prelude::*;
+use std::collections::HashMap;
+
+/// 增量查询记住的每个数据源的读取位置，反复传给 [Session::query_incremental]
+/// 每次只处理数据源上次之后追加的完整行，适合只会追加的日志 csv
+#[derive(Debug, Default, Clone)]
+pub struct Watermark {
+    sources: HashMap<String, Position>,
+}
+
+#[derive(Debug, Clone)]
+struct Position {
+    // 已经处理过的字节数，总是停在某一行的末尾
+    offset: u64,
+    // 第一次读到的表结构（没有行），后面追加的行按它解析，类型才能前后一致
+    empty: DataFrame,
+}
+
+impl Watermark {
+    /// 数据源已经处理到的字节位置，还没读过时返回 None
+    pub fn offset(&self, source: &str) -> Option<u64> {
+        self.sources.get(source).map(|p| p.offset)
+    }
+
+    /// 忘掉所有数据源的位置，下次查询从头读
+    pub fn reset(&mut self) {
+        self.sources.clear();
+    }
+
+    /// 读取数据源上次之后追加的完整行，还没有任何数据行时返回 None
+    pub(crate) async fn load(
+        &mut self,
+        source: &str,
+        session: &Session,
+    ) -> Result<Option<DataFrame>> {
+        let offset = self.offset(source).unwrap_or(0);
+        let data = match retrieve_appended(source, offset, session).await? {
+            Some(data) => data,
+            // 数据源变短了，说明被截断或者轮转过，从头开始读
+            None => {
+                self.sources.remove(source);
+                retrieve_appended(source, 0, session)
+                    .await?
+                    .unwrap_or_default()
+            }
+        };
+
+        // 只处理完整的行，还没写完的最后一行留到下次
+        let end = last_record_end(&data);
+        let lines = data[..end].to_vec();
+        let options = session.settings.options();
+
+        match self.sources.get_mut(source) {
+            Some(position) => {
+                let df = match end {
+                    0 => position.empty.clone(),
+                    _ => options
+                        .csv_reader(lines, false)
+                        .with_schema(&position.empty.schema())
+                        .finish()?,
+                };
+                position.offset += end as u64;
+                Ok(Some(df))
+            }
+            // 只有表头时推断不出列的类型，等有数据行了再记录位置
+            // 只有表头的数据交给 polars 会 panic，所以在解析之前判断
+            None if first_record_end(&lines) == end => Ok(None),
+            None => {
+                let df = options.csv_reader(lines, true).finish()?;
+                let position = Position {
+                    offset: end as u64,
+                    empty: df.slice(0, 0),
+                };
+                self.sources.insert(source.to_owned(), position);
+                Ok(Some(df))
+            }
+        }
+    }
+}
+
+/// 最后一个完整记录结束的位置，引号里的换行属于字段内容，不算记录结束
+/// data 总是从某个记录的开头读起，所以开头不在引号里
+fn last_record_end(data: &[u8]) -> usize {
+    let mut end = 0;
+    let mut quoted = false;
+    for (i, c) in data.iter().enumerate() {
+        match c {
+            b'"' => quoted = !quoted,
+            b'\n' if !quoted => end = i + 1,
+            _ => {}
+        }
+    }
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::OpenOptions, io::Write};
+
+    #[tokio::test]
+    async fn query_incremental_works() {
+        let path = std::env::temp_dir().join("queryer_incremental.csv");
+        std::fs::write(&path, "name,age\nTyr,18\nLindsey,30\nJo").unwrap();
+        let source = format!("file://{}", path.display());
+        let sql = format!("SELECT name, age FROM {} WHERE age > 10", source);
+        let session = Session::default();
+        let mut watermark = Watermark::default();
+
+        // 没写完的最后一行不处理
+        let ds = session
+            .query_incremental(&sql, &mut watermark)
+            .await
+            .unwrap();
+        assert_eq!(ds.height(), 2);
+        assert_eq!(watermark.offset(&source), Some(27));
+
+        // 没有新数据时返回空结果，列还在
+        let ds = session
+            .query_incremental(&sql, &mut watermark)
+            .await
+            .unwrap();
+        assert_eq!(ds.height(), 0);
+        assert_eq!(ds.get_column_names(), vec!["name", "age"]);
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"e,40\nAlice,5\n").unwrap();
+        let ds = session
+            .query_incremental(&sql, &mut watermark)
+            .await
+            .unwrap();
+        assert_eq!(
+            ds.column("name").unwrap().utf8().unwrap().get(0),
+            Some("Joe")
+        );
+        assert_eq!(ds.height(), 1);
+
+        // 文件被截断后从头读
+        std::fs::write(&path, "name,age\nBob,20\n").unwrap();
+        let ds = session
+            .query_incremental(&sql, &mut watermark)
+            .await
+            .unwrap();
+        assert_eq!(
+            ds.column("name").unwrap().utf8().unwrap().get(0),
+            Some("Bob")
+        );
+        assert_eq!(watermark.offset(&source), Some(16));
+    }
+
+    #[tokio::test]
+    async fn quoted_newline_works() {
+        let path = std::env::temp_dir().join("queryer_incremental_quoted.csv");
+        std::fs::write(&path, "name,note\nTyr,\"first\nline").unwrap();
+        let source = format!("file://{}", path.display());
+        let sql = format!("SELECT name, note FROM {}", source);
+        let session = Session::default();
+        let mut watermark = Watermark::default();
+
+        // 引号里的换行不算记录结束，只有表头时还不记录位置
+        let ds = session
+            .query_incremental(&sql, &mut watermark)
+            .await
+            .unwrap();
+        assert_eq!(ds.height(), 0);
+        assert_eq!(watermark.offset(&source), None);
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b" end\"\nLindsey,\"a\nb\"\n").unwrap();
+        let ds = session
+            .query_incremental(&sql, &mut watermark)
+            .await
+            .unwrap();
+        assert_eq!(ds.height(), 2);
+        assert_eq!(
+            ds.column("note").unwrap().utf8().unwrap().get(0),
+            Some("first\nline end")
+        );
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// FROM a JOIN b ON a.k = b.k：每个数据源先各自读进来，再用 polars 按连接列做 hash join
+
+use anyhow::{anyhow, Result};
+use polars::prelude::*;
+
+/// JOIN 进来的数据源，只支持用 AND 连接的列相等条件（等值连接），INNER 和 LEFT 两种
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Join<'a> {
+    pub(crate) source: &'a str,
+    pub(crate) alias: Option<String>,
+    // ON 里相等的两列，写的时候可以不分左右，连接时按哪一边有这一列来确定
+    pub(crate) on: Vec<(String, String)>,
+    pub(crate) how: JoinType,
+}
+
+impl<'a> Join<'a> {
+    /// 列名前面的表名：有别名时是别名，否则是数据源本身，比如注册的表名
+    pub(crate) fn qualifier(&self) -> &str {
+        self.alias.as_deref().unwrap_or(self.source)
+    }
+
+    /// 把 right 按连接条件接到 left 上，两边的列已经按查询里的写法改过名
+    /// 两边有同名的列时，右边的列在结果里会加上 `_right` 后缀
+    pub(crate) fn apply(&self, mut left: DataFrame, mut right: DataFrame) -> Result<DataFrame> {
+        let mut keys = Vec::with_capacity(self.on.len());
+        for (i, (a, b)) in self.on.iter().enumerate() {
+            let (l, r) = match (left.column(a), right.column(b)) {
+                (Ok(_), Ok(_)) => (a, b),
+                _ if left.column(b).is_ok() && right.column(a).is_ok() => (b, a),
+                _ => {
+                    return Err(anyhow!(
+                        "cannot find join columns {} and {} in {}",
+                        a,
+                        b,
+                        self.source
+                    ))
+                }
+            };
+            // polars 会去掉右边的连接列，两边各复制一份来连接，查询里还能引用原来的列
+            // 两边类型不一样时（比如 Int64 和 Float64）都转成能放下两边的值的类型再比较
+            let ltype = left.column(l)?.dtype().clone();
+            let rtype = right.column(r)?.dtype().clone();
+            let dtype = key_type(&ltype, &rtype).ok_or_else(|| {
+                anyhow!("cannot join {} ({:?}) with {} ({:?})", l, ltype, r, rtype)
+            })?;
+            let key = format!("__join_key_{}", i);
+            for (df, name) in [(&mut left, l), (&mut right, r)] {
+                let mut s = df.column(name)?.cast_with_dtype(&dtype)?;
+                s.rename(&key);
+                df.with_column(s)?;
+            }
+            keys.push(key);
+        }
+        // SQL 里 NULL 和什么都不相等，右边连接列是 NULL 的行永远匹配不上
+        let right = right.drop_nulls(Some(&keys))?;
+        let on: Vec<&str> = keys.iter().map(|v| v.as_str()).collect();
+        let mut df = left.join(&right, on.clone(), on, self.how)?;
+        for key in &keys {
+            df.drop_in_place(key)?;
+        }
+        Ok(df)
+    }
+}
+
+/// 两边连接列都能转换过去的类型：类型相同时不用转换，整数和浮点数混在一起时用 Float64，
+/// 其它不同的类型（比如数字和字符串）没法比较
+fn key_type(left: &DataType, right: &DataType) -> Option<DataType> {
+    use DataType::*;
+    let integer = |v: &DataType| {
+        matches!(
+            v,
+            Int8 | Int16 | Int32 | Int64 | UInt8 | UInt16 | UInt32 | UInt64
+        )
+    };
+    let float = |v: &DataType| matches!(v, Float32 | Float64);
+    match (left, right) {
+        _ if left == right => Some(left.clone()),
+        _ if integer(left) && integer(right) => Some(Int64),
+        _ if (integer(left) || float(left)) && (integer(right) || float(right)) => Some(Float64),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_works() {
+        let people = DataFrame::new(vec![
+            Series::new("p.id", &[1i64, 2, 3]),
+            Series::new("name", &["Tyr", "Lindsey", "Tom"]),
+        ])
+        .unwrap();
+        let orders = DataFrame::new(vec![
+            Series::new("o.person_id", &[Some(1.0), Some(1.0), Some(3.0), None]),
+            Series::new("amount", &[10i64, 20, 30, 40]),
+        ])
+        .unwrap();
+        let join = Join {
+            source: "orders",
+            alias: Some("o".into()),
+            // ON 两边的列顺序写反了也可以
+            on: vec![("o.person_id".into(), "p.id".into())],
+            how: JoinType::Inner,
+        };
+        let df = join.apply(people.clone(), orders.clone()).unwrap();
+        assert_eq!(df.height(), 3);
+        assert_eq!(
+            df.get_column_names(),
+            vec!["p.id", "name", "o.person_id", "amount"]
+        );
+
+        // LEFT JOIN 保留没有匹配上的 Lindsey
+        let join = Join {
+            how: JoinType::Left,
+            ..join
+        };
+        let df = join.apply(people, orders).unwrap();
+        assert_eq!(df.height(), 4);
+        assert_eq!(df.column("amount").unwrap().null_count(), 1);
+    }
+
+    #[test]
+    fn join_keys_use_common_type() {
+        let people = DataFrame::new(vec![Series::new("p.id", &[1i64, 2])]).unwrap();
+        // 1.5 不能截断成 1 去匹配
+        let orders = DataFrame::new(vec![
+            Series::new("o.person_id", &[1.5, 2.0]),
+            Series::new("amount", &[10i64, 20]),
+        ])
+        .unwrap();
+        let join = Join {
+            source: "orders",
+            alias: Some("o".into()),
+            on: vec![("p.id".into(), "o.person_id".into())],
+            how: JoinType::Inner,
+        };
+        let df = join.apply(people.clone(), orders).unwrap();
+        assert_eq!(df.height(), 1);
+        assert_eq!(df.column("amount").unwrap().i64().unwrap().get(0), Some(20));
+        assert_eq!(df.column("p.id").unwrap().dtype(), &DataType::Int64);
+
+        // 数字和字符串不能连接
+        let orders = DataFrame::new(vec![Series::new("o.person_id", &["1", "2"])]).unwrap();
+        assert!(join.apply(people, orders).is_err());
+    }
+}
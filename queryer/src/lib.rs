@@ -49,25 +49,70 @@
 // | "Finland" | 1.484646e6  | 315       | 1.0185e4     | 14         |
 // +-----------+-------------+-----------+--------------+------------+
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use futures::{future::Either, Stream};
 use polars::prelude::*;
-use sqlparser::parser::Parser;
+use sqlparser::ast::{Query, Statement};
 use std::convert::TryInto;
 use std::ops::{Deref, DerefMut};
-use tracing::info;
+use std::time::Instant;
+use tracing::{info, info_span, Instrument};
 
 // 调用自己的其他包
+mod command;
 mod convert;
 mod dialect;
+mod diff;
+mod error;
 mod loader;
 mod fetcher;
+mod http;
+mod incremental;
+mod join;
+mod params;
+mod partition;
+mod permissions;
+mod registry;
+mod reshape;
+mod resolve;
+mod sample;
+mod session;
+mod settings;
+mod stats;
+mod stream;
+mod temporal;
+mod text;
+mod udf;
+mod writer;
+use command::{parse_command, Clauses, Command};
 use convert::Sql;
-use loader::detect_content;
-use fetcher::retrieve_data;
+use error::unsupported;
+use loader::{detect_bytes, detect_content, split_sheet, Loader};
+use fetcher::{retrieve_bytes, retrieve_data, retrieve_table};
+use partition::{lake_path, LakeLoader, Predicate};
+use resolve::{rename_columns, resolve_columns, strip_alias};
+use temporal::parse_dates;
+use writer::write_data;
 
 // pub use 可以把其他包的内容暴露给外部(queryer-py)使用
 pub use dialect::example_sql;
-pub use dialect::TyrDialect;
+pub use dialect::{DialectMode, TyrDialect};
+pub use error::{ErrorKind, FetchError, Forbidden, ParseError, Unsupported};
+pub use fetcher::FetchHandler;
+pub use http::HttpConfig;
+pub use incremental::Watermark;
+pub use loader::LoadHandler;
+pub use params::Param;
+pub use permissions::Permissions;
+pub use registry::{
+    register_fetcher, register_function, register_loader, register_table, unregister_fetcher,
+    unregister_function, unregister_loader, unregister_table,
+};
+pub use session::Session;
+pub use stats::QueryStats;
+pub use temporal::DEFAULT_DATE_FORMATS;
+pub use udf::ScalarFn;
+pub use writer::OutputFormat;
 
 #[derive(Debug)]
 pub struct DataSet(DataFrame);
@@ -88,6 +133,13 @@ impl DerefMut for DataSet {
     }
 }
 
+/// 自定义的 loader 用它把 DataFrame 包装成 DataSet
+impl From<DataFrame> for DataSet {
+    fn from(df: DataFrame) -> Self {
+        Self(df)
+    }
+}
+
 impl DataSet {
     /// 从 DataSet 转换成 csv
     pub fn to_csv(&self) -> Result<String> {
@@ -96,39 +148,333 @@ impl DataSet {
         writer.finish(self)?;
         Ok(String::from_utf8(buf)?)
     }
+
+    /// 从 DataSet 转换成每行一个 json 对象的格式
+    pub fn to_ndjson(&self) -> Result<String> {
+        let mut buf = Vec::new();
+        let writer = JsonWriter::new(&mut buf);
+        writer.finish(self)?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    /// 从 DataSet 转换成 Arrow IPC 文件格式
+    pub fn to_ipc(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        let writer = IpcWriter::new(&mut buf);
+        writer.finish(self)?;
+        Ok(buf)
+    }
+
+    /// 按 key 列和 baseline 对比，返回新增（added）、删除（removed）和修改（changed）的行
+    /// 结果包含 key 列、change 列，以及其它每一列的 `<列名>_before` 和 `<列名>_after`
+    pub fn diff(&self, baseline: &DataSet, key: &[&str]) -> Result<DataSet> {
+        Ok(DataSet(diff::diff(self, baseline, key)?))
+    }
 }
 
 /// 从 from 中获取数据，从 where 中过滤，最后选取需要返回的列
+/// http 相关的配置从环境变量中读取，需要在代码里配置的话使用 [Session]
+/// 每次调用都用一个新的 Session，SET 不会影响之后的查询，所以 SET / SHOW 要在 [Session] 里执行
 pub async fn query<T: AsRef<str>>(sql: T) -> Result<DataSet> {
-    let ast = Parser::parse_sql(&TyrDialect::default(), sql.as_ref())?;
+    transient_session()?.query(sql).await
+}
+
+/// 和 [query] 一样，同时返回各个阶段的耗时和数据量
+pub async fn query_with_stats<T: AsRef<str>>(sql: T) -> Result<(DataSet, QueryStats)> {
+    transient_session()?.query_with_stats(sql).await
+}
+
+/// 和 [query] 一样，但结果按批返回，详见 [Session::query_stream]
+pub fn query_stream<T: Into<String>>(sql: T) -> impl Stream<Item = Result<DataSet>> + Send {
+    match transient_session() {
+        Ok(session) => Either::Left(session.query_stream(sql)),
+        Err(e) => Either::Right(futures::stream::once(async move { Err(e) })),
+    }
+}
+
+/// 增量查询：只处理 file / http 数据源上次查询之后追加的行，位置记录在 watermark 里
+pub async fn query_incremental<T: AsRef<str>>(
+    sql: T,
+    watermark: &mut Watermark,
+) -> Result<DataSet> {
+    transient_session()?.query_incremental(sql, watermark).await
+}
+
+/// 顶层函数每次查询用的 Session
+fn transient_session() -> Result<Session> {
+    Ok(Session {
+        transient: true,
+        ..Session::from_env()?
+    })
+}
+
+/// 执行一条 SQL：查询返回结果，INSERT / COPY 返回写入的行数
+/// 每个阶段的耗时和数据量记录在 stats 里
+pub(crate) async fn execute(
+    session: &Session,
+    sql: &str,
+    stats: &mut QueryStats,
+) -> Result<DataSet> {
+    let start = Instant::now();
+    let (command, clauses) = info_span!("parse")
+        .in_scope(|| parse_command(session.dialect, sql, &session.params))?;
+    stats.parse += start.elapsed();
+    session.permissions.check_command(&command)?;
+    if session.transient && matches!(command, Command::Set { .. } | Command::Show { .. }) {
+        return Err(unsupported!(
+            "SET and SHOW need a Session, the top-level query() does not keep options between queries"
+        ));
+    }
+
+    let ds = match command {
+        Command::Statement(statement) => match *statement {
+            Statement::Query(q) => run_query(session, &q, &clauses, stats).await,
+            // INSERT INTO 'file:///out.csv' SELECT ... 追加，INSERT OVERWRITE 覆盖
+            Statement::Insert {
+                table_name,
+                columns,
+                overwrite,
+                source,
+                ..
+            } => {
+                if !columns.is_empty() {
+                    return Err(unsupported!(
+                        "We do not support column list in INSERT at the moment"
+                    ));
+                }
+                let target = table_name
+                    .0
+                    .iter()
+                    .map(|v| v.value.as_str())
+                    .collect::<Vec<_>>()
+                    .join(".");
+                let ds = run_query(session, &source, &clauses, stats).await?;
+                let rows = write_data(ds, &target, None, !overwrite)
+                    .instrument(info_span!("write", target = target.as_str()))
+                    .await?;
+                rows_written(rows)
+            }
+            _ => Err(unsupported!(
+                "We only support Query, INSERT, COPY, DIFF, SET and SHOW at the moment"
+            )),
+        },
+        // COPY (SELECT ...) TO 'file:///out.parquet' (FORMAT parquet)，总是覆盖
+        Command::Copy {
+            query,
+            target,
+            format,
+        } => {
+            let ds = run_query(session, &query, &clauses, stats).await?;
+            let rows = write_data(ds, &target, format.as_deref(), false)
+                .instrument(info_span!("write", target = target.as_str()))
+                .await?;
+            rows_written(rows)
+        }
+        // DIFF (SELECT ...) AGAINST (SELECT ...) KEY (location)
+        Command::Diff {
+            current,
+            current_clauses,
+            baseline,
+            baseline_clauses,
+            key,
+        } => {
+            let current = run_query(session, &current, &current_clauses, stats).await?;
+            let baseline = run_query(session, &baseline, &baseline_clauses, stats).await?;
+            let key: Vec<&str> = key.iter().map(|v| v.as_str()).collect();
+            current.diff(&baseline, &key)
+        }
+        // SET inference_rows = 1000，返回修改后的值
+        Command::Set { name, value } => {
+            session.settings.set(&name, &value)?;
+            Ok(DataSet(session.settings.options().show(Some(&name))?))
+        }
+        // SHOW http.timeout / SHOW ALL
+        Command::Show { name } => Ok(DataSet(session.settings.options().show(name.as_deref())?)),
+    }?;
+    stats.rows_returned = ds.height();
+    Ok(ds)
+}
+
+/// 写操作返回只有一列 rows 的 DataSet
+fn rows_written(rows: usize) -> Result<DataSet> {
+    let df = DataFrame::new(vec![Series::new("rows", &[rows as i64])])?;
+    Ok(DataSet(df))
+}
 
-    if ast.len() != 1 {
-        return Err(anyhow!("Only support single sql at the moment"));
+/// 执行一条增量查询，只支持 SELECT
+pub(crate) async fn execute_incremental(
+    session: &Session,
+    sql: &str,
+    watermark: &mut Watermark,
+) -> Result<DataSet> {
+    let (command, clauses) = parse_command(session.dialect, sql, &session.params)?;
+    let q = command
+        .into_query()
+        .ok_or_else(|| unsupported!("Incremental query only supports SELECT"))?;
+    let sql: Sql = q.as_ref().try_into()?;
+    if !sql.joins.is_empty() {
+        return Err(unsupported!("Incremental query does not support JOIN"));
     }
+    session.permissions.check_source(sql.source)?;
 
-    let sql = &ast[0];
+    info!("retrieving appended data from source: {}", sql.source);
+    let appended = watermark
+        .load(sql.source, session)
+        .await
+        .context(FetchError(sql.source.to_owned()))?;
+    match appended {
+        Some(df) => transform(session, sql, &clauses, df),
+        // 数据源里还没有数据行
+        None => Ok(DataSet(DataFrame::default())),
+    }
+}
 
+async fn run_query(
+    session: &Session,
+    sql: &Query,
+    clauses: &Clauses,
+    stats: &mut QueryStats,
+) -> Result<DataSet> {
     // 整个 SQL AST 转换成我们定义的 Sql 结构的细节都埋藏在 try_into() 中
     // 我们只需关注数据结构的使用，怎么转换可以之后需要的时候才关注，这是
     // 关注点分离，是我们控制软件复杂度的法宝。
+    let sql: Sql = sql.try_into()?;
+    let df = load_source(session, &sql, clauses, stats).await?;
+
+    let start = Instant::now();
+    let ds = info_span!("execute").in_scope(|| transform(session, sql, clauses, df))?;
+    stats.execute += start.elapsed();
+    Ok(ds)
+}
+
+/// 从 sql 的数据源读入数据，有 JOIN 时把其它数据源也读进来连接好，还没有执行任何子句
+async fn load_source(
+    session: &Session,
+    sql: &Sql<'_>,
+    clauses: &Clauses,
+    stats: &mut QueryStats,
+) -> Result<DataFrame> {
+    // PIVOT / UNPIVOT 之后 where 里的列和远端数据库里的列对不上，不能下推
+    // 抽样要在过滤之前执行，下推到数据库的话就变成先过滤后抽样了，结果和文件数据源不一样
+    // 有 JOIN 时 where 里可能有其它数据源的列，也不能下推或者用来剪枝
+    let (pushdown, predicates) = match (&clauses.reshape, &clauses.sample) {
+        (None, None) if sql.joins.is_empty() => (sql.pushdown.as_deref(), sql.predicates.clone()),
+        _ => (None, vec![]),
+    };
+    let df = load_table(session, sql.source, pushdown, predicates, stats).await?;
+    if sql.joins.is_empty() {
+        return Ok(df);
+    }
+
+    // 连接之前先把两边的列都改成查询里的写法，`p.id`、`o.id` 这样带表名的列才能分清是哪边的
+    let columns = sql.columns();
+    let ignore_case = session.ignore_case();
+    let mut df = rename_columns(df, &columns, sql.qualifier(), ignore_case)?;
+    for join in &sql.joins {
+        let other = load_table(session, join.source, None, vec![], stats).await?;
+        let other = rename_columns(other, &columns, Some(join.qualifier()), ignore_case)?;
+        // 没有带表名的列两边都有时，不知道查询要的是哪一边
+        if let Some(name) = columns
+            .iter()
+            .find(|v| *v != "*" && df.column(v).is_ok() && other.column(v).is_ok())
+        {
+            return Err(anyhow!(
+                "column {} is ambiguous, it exists in {} and {}, qualify it with a table name",
+                name,
+                sql.qualifier().unwrap_or(sql.source),
+                join.qualifier()
+            ));
+        }
+        df = join.apply(df, other)?;
+    }
+    Ok(df)
+}
+
+/// 读入一个数据源，pushdown 是可以下推到数据库的 where，predicates 用来跳过分区和 row group
+async fn load_table(
+    session: &Session,
+    source: &str,
+    pushdown: Option<&str>,
+    predicates: Vec<Predicate>,
+    stats: &mut QueryStats,
+) -> Result<DataFrame> {
+    // register_table 注册的内存表，不需要读取和解析
+    if let Some(df) = registry::get_table(source) {
+        info!("using registered table: {}", source);
+        stats.rows_scanned += df.height();
+        return Ok(df);
+    }
+    session.permissions.check_source(source)?;
+    info!("retrieving data from source: {}", source);
+
+    // 从 source 读入一个 DataSet
+    // detect_content，怎么 detect 不重要，重要的是它能根据内容返回 DataSet
+    // SET 设置的推断行数、空值标记等
+    let options = session.settings.options();
+    let start = Instant::now();
+    let fetch = async {
+        // 本地目录和 parquet 文件按分区数据集读
+        if let Some(root) = lake_path(source) {
+            let lake = LakeLoader::discover(root, predicates, options.clone()).await?;
+            let bytes = lake.size();
+            return Ok((Loader::Lake(lake), bytes));
+        }
+        // 数据库里的列有类型，直接读成 data frame，where 里简单的条件下推到数据库里执行
+        if let Some(df) = retrieve_table(source, pushdown).await? {
+            // 没有文本可以计算长度，按读出来的数据在内存里的大小统计
+            let bytes = df
+                .get_columns()
+                .iter()
+                .flat_map(|s| s.chunks())
+                .map(|a| a.get_buffer_memory_size())
+                .sum();
+            return Ok((Loader::Table(DataSet(df)), bytes));
+        }
+        match split_sheet(source) {
+            // 电子表格是二进制格式，按字节读取，`#` 后面是工作表和范围
+            Some((source, sheet)) => {
+                let data = retrieve_bytes(source, session).await?;
+                let bytes = data.len();
+                Ok((detect_bytes(data, sheet, &options)?, bytes))
+            }
+            None => {
+                let data = retrieve_data(source, session).await?;
+                let bytes = data.len();
+                Ok::<_, anyhow::Error>((detect_content(data, &options), bytes))
+            }
+        }
+    };
+    let (loader, bytes) = fetch
+        .instrument(info_span!("fetch", source))
+        .await
+        .context(FetchError(source.to_owned()))?;
+    stats.fetch += start.elapsed();
+    stats.bytes_downloaded += bytes;
+
+    let start = Instant::now();
+    let ds = info_span!("load").in_scope(|| loader.load())?;
+    stats.load += start.elapsed();
+    stats.rows_scanned += ds.height();
+
+    Ok(ds.0)
+}
+
+/// 对读进来的数据执行 SQL 里的各个子句
+fn transform(session: &Session, sql: Sql, clauses: &Clauses, df: DataFrame) -> Result<DataSet> {
+    let df = prepare(session, &sql, clauses, df)?;
+    let qualifiers = sql.qualifiers();
     let Sql {
-        source,
         condition,
         selection,
         offset,
         limit,
         order_by,
-    } = sql.try_into()?;
-
-    info!("retrieving data from source: {}", source);
-
-    // 从 source 读入一个 DataSet
-    // detect_content，怎么 detect 不重要，重要的是它能根据内容返回 DataSet
-    let ds = detect_content(retrieve_data(source).await?).load()?;
+        ..
+    } = sql;
 
     let mut filtered = match condition {
-        Some(expr) => ds.0.lazy().filter(expr),
-        None => ds.0.lazy(),
+        Some(expr) => df.lazy().filter(expr),
+        None => df.lazy(),
     };
 
     filtered = order_by
@@ -139,5 +485,27 @@ pub async fn query<T: AsRef<str>>(sql: T) -> Result<DataSet> {
         filtered = filtered.slice(offset.unwrap_or(0), limit.unwrap_or(usize::MAX));
     }
 
-    Ok(DataSet(filtered.select(selection).collect()?))
+    let df = filtered.select(selection).collect()?;
+    Ok(DataSet(strip_alias(df, &qualifiers)?))
+}
+
+/// 过滤之前的准备工作：抽样、PIVOT / UNPIVOT，把查询里的列名对应到数据上，以及识别日期列
+fn prepare(
+    session: &Session,
+    sql: &Sql,
+    clauses: &Clauses,
+    mut df: DataFrame,
+) -> Result<DataFrame> {
+    // 先抽样，后面的处理都只针对样本
+    if let Some(sample) = &clauses.sample {
+        df = sample.apply(df)?;
+    }
+    if let Some(reshape) = &clauses.reshape {
+        df = reshape.apply(df)?;
+    }
+    // 查询里的列名可能带表别名或者大小写不一致，先对应到数据里的列上
+    let columns = sql.columns();
+    let df = resolve_columns(df, &columns, sql.qualifier(), session.ignore_case())?;
+    // 把查询里用到的、看起来像日期的字符串列转换成日期类型，这样才能和 DATE 字面量比较
+    parse_dates(df, &session.date_formats, &columns)
 }
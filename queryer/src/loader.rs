@@ -12,45 +12,332 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::DataSet;
-use anyhow::Result;
+use crate::{partition::LakeLoader, registry, settings::Options, DataSet};
+use anyhow::{anyhow, Result};
+use calamine::{open_workbook_auto_from_rs, DataType as Cell, Range, Reader};
+use chrono::Timelike;
 use polars::prelude::*;
-use std::io::Cursor;
+use std::{fmt, io::Cursor, sync::Arc};
+
+// 按电子表格读取的数据源扩展名
+const SHEET_EXTENSIONS: [&str; 5] = ["xlsx", "xlsm", "xlsb", "xls", "ods"];
 
 pub trait Load {
     type Error;
     fn load(self) -> Result<DataSet, Self::Error>;
 }
 
+/// 用户自定义的数据格式，通过 [register_loader](crate::register_loader) 注册
+pub trait LoadHandler: Send + Sync {
+    /// 判断 data 是不是这种格式
+    fn detect(&self, data: &str) -> bool;
+    fn load(&self, data: String) -> Result<DataSet>;
+}
+
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum Loader {
     Csv(CsvLoader),
+    Xlsx(XlsxLoader),
+    Lake(LakeLoader),
+    Custom(CustomLoader),
+    // 数据库这样本身有类型的数据源，读出来就是 data frame
+    Table(DataSet),
 }
 
+/// csv 文本，以及 SET 设置的推断行数和空值标记
 #[derive(Default, Debug)]
-pub struct CsvLoader(pub(crate) String);
+pub struct CsvLoader(pub(crate) String, pub(crate) Options);
+
+pub struct CustomLoader(pub(crate) Arc<dyn LoadHandler>, pub(crate) String);
+
+/// Excel（xlsx / xlsm / xlsb / xls）和 OpenDocument（ods）电子表格
+/// sheet 为空时读第一个工作表，range 是 `A1:C10` 这样的范围，为空时读整个工作表
+/// 范围的第一行是表头
+pub struct XlsxLoader {
+    pub(crate) data: Vec<u8>,
+    pub(crate) sheet: Option<String>,
+    pub(crate) range: Option<String>,
+}
+
+impl fmt::Debug for XlsxLoader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("XlsxLoader")
+            .field("len", &self.data.len())
+            .field("sheet", &self.sheet)
+            .field("range", &self.range)
+            .finish()
+    }
+}
+
+impl fmt::Debug for CustomLoader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CustomLoader").field(&self.1).finish()
+    }
+}
 
 impl Loader {
     pub fn load(self) -> Result<DataSet> {
         match self {
             Loader::Csv(csv) => csv.load(),
+            Loader::Xlsx(xlsx) => xlsx.load(),
+            Loader::Lake(lake) => lake.load(),
+            Loader::Custom(custom) => custom.load(),
+            Loader::Table(ds) => Ok(ds),
         }
     }
 }
 
-pub fn detect_content(data: String) -> Loader {
-    // TODO: 内容检测
-    Loader::Csv(CsvLoader(data))
+pub fn detect_content(data: String, options: &Options) -> Loader {
+    // 先问用户注册的格式，都不认识再当作 csv
+    match registry::get_loaders()
+        .into_iter()
+        .find(|l| l.detect(&data))
+    {
+        Some(handler) => Loader::Custom(CustomLoader(handler, data)),
+        None => Loader::Csv(CsvLoader(data, options.clone())),
+    }
+}
+
+/// 检测二进制的内容，zip（xlsx / ods）和 OLE（xls）格式当作电子表格，其它的按文本处理
+/// sheet 是数据源 `#` 后面的部分：`Sheet2`、`Sheet2!A1:C10` 或者 `!A1:C10`
+pub fn detect_bytes(data: Vec<u8>, sheet: Option<&str>, options: &Options) -> Result<Loader> {
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(&[0xD0, 0xCF, 0x11, 0xE0]) {
+        let (sheet, range) = match sheet {
+            Some(v) => match v.split_once('!') {
+                Some((sheet, range)) => (sheet, Some(range.to_owned())),
+                None => (v, None),
+            },
+            None => ("", None),
+        };
+        let sheet = match sheet.is_empty() {
+            true => None,
+            false => Some(sheet.to_owned()),
+        };
+        return Ok(Loader::Xlsx(XlsxLoader { data, sheet, range }));
+    }
+    Ok(detect_content(String::from_utf8(data)?, options))
+}
+
+/// 扩展名是电子表格的数据源，拆成真正的数据源和 `#` 后面的工作表和范围
+pub(crate) fn split_sheet(source: &str) -> Option<(&str, Option<&str>)> {
+    let (path, sheet) = match source.split_once('#') {
+        Some((path, sheet)) => (path, Some(sheet)),
+        None => (source, None),
+    };
+    let name = path.split('?').next().unwrap_or_default();
+    let ext = name.rsplit('.').next().unwrap_or_default().to_lowercase();
+    match SHEET_EXTENSIONS.contains(&ext.as_str()) {
+        true => Some((path, sheet)),
+        false => None,
+    }
 }
 
 impl Load for CsvLoader {
     type Error = anyhow::Error;
 
     fn load(self) -> Result<DataSet, Self::Error> {
-        let df = CsvReader::new(Cursor::new(self.0))
-            .infer_schema(Some(16))
-            .finish()?;
+        let df = self.1.csv_reader(self.0.into_bytes(), true).finish()?;
         Ok(DataSet(df))
     }
-}
\ No newline at end of file
+}
+
+impl Load for CustomLoader {
+    type Error = anyhow::Error;
+
+    fn load(self) -> Result<DataSet, Self::Error> {
+        self.0.load(self.1)
+    }
+}
+
+impl Load for XlsxLoader {
+    type Error = anyhow::Error;
+
+    fn load(self) -> Result<DataSet, Self::Error> {
+        let mut workbook = open_workbook_auto_from_rs(Cursor::new(self.data))?;
+        let sheet = match self.sheet {
+            Some(v) => v,
+            None => workbook
+                .sheet_names()
+                .first()
+                .cloned()
+                .ok_or_else(|| anyhow!("workbook has no sheet"))?,
+        };
+        let mut range = workbook
+            .worksheet_range(&sheet)
+            .ok_or_else(|| anyhow!("sheet {} not found", sheet))??;
+        if let Some(v) = &self.range {
+            range = sub_range(&range, v)?;
+        }
+
+        let mut rows = range.rows();
+        let header = match rows.next() {
+            Some(v) => v,
+            None => return Ok(DataSet(DataFrame::default())),
+        };
+        let rows: Vec<&[Cell]> = rows.collect();
+        let columns = header
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                let name = match cell {
+                    Cell::Empty => format!("column_{}", i + 1),
+                    v => v.to_string(),
+                };
+                let cells: Vec<&Cell> = rows.iter().map(|row| &row[i]).collect();
+                to_series(&name, &cells)
+            })
+            .collect();
+        Ok(DataSet(DataFrame::new(columns)?))
+    }
+}
+
+/// 按 `A1:C10` 或者 `B2`（从 B2 到最后）截取工作表的一部分
+fn sub_range(range: &Range<Cell>, spec: &str) -> Result<Range<Cell>> {
+    let (start, end) = match spec.split_once(':') {
+        Some((start, end)) => (cell_position(start)?, cell_position(end)?),
+        None => (cell_position(spec)?, range.end().unwrap_or_default()),
+    };
+    if start.0 > end.0 || start.1 > end.1 {
+        return Err(anyhow!("invalid range {}", spec));
+    }
+    Ok(range.range(start, end))
+}
+
+/// 把 `AB12` 这样的单元格转换成从 0 开始的 (行, 列)
+fn cell_position(cell: &str) -> Result<(u32, u32)> {
+    let cell = cell.trim().to_uppercase();
+    let split = cell
+        .find(|c: char| c.is_ascii_digit())
+        .ok_or_else(|| anyhow!("invalid cell {}", cell))?;
+    let (letters, digits) = cell.split_at(split);
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(anyhow!("invalid cell {}", cell));
+    }
+
+    let col = letters
+        .bytes()
+        .fold(0u32, |acc, c| acc * 26 + (c - b'A' + 1) as u32);
+    let row: u32 = digits.parse()?;
+    if row == 0 {
+        return Err(anyhow!("invalid cell {}", cell));
+    }
+    Ok((row - 1, col - 1))
+}
+
+fn to_series(name: &str, cells: &[&Cell]) -> Series {
+    let mut s = infer_series(cells);
+    s.rename(name);
+    s
+}
+
+/// 按一列里所有非空单元格的类型推断这一列的类型：
+/// 都是整数的数字是 i64，数字是 f64，布尔是 bool，日期时间是 Date32 / Date64，混在一起就是字符串
+fn infer_series(cells: &[&Cell]) -> Series {
+    let values: Vec<&Cell> = cells
+        .iter()
+        .copied()
+        .filter(|v| !matches!(v, Cell::Empty | Cell::Error(_)))
+        .collect();
+    let all = |f: fn(&Cell) -> bool| !values.is_empty() && values.iter().all(|v| f(v));
+
+    if all(|v| matches!(v, Cell::Int(_)) || matches!(v, Cell::Float(f) if f.fract() == 0.0)) {
+        let ca: Int64Chunked = cells
+            .iter()
+            .map(|v| v.get_float().map(|f| f as i64).or_else(|| v.get_int()))
+            .collect();
+        return ca.into_series();
+    }
+    if all(|v| matches!(v, Cell::Int(_) | Cell::Float(_))) {
+        let ca: Float64Chunked = cells
+            .iter()
+            .map(|v| v.get_float().or_else(|| v.get_int().map(|i| i as f64)))
+            .collect();
+        return ca.into_series();
+    }
+    if all(|v| matches!(v, Cell::Bool(_))) {
+        let ca: BooleanChunked = cells.iter().map(|v| v.get_bool()).collect();
+        return ca.into_series();
+    }
+    // as_datetime 也会把数字当作 Excel 的日期序号，所以要先排除数字
+    if all(|v| !matches!(v, Cell::Int(_) | Cell::Float(_)) && v.as_datetime().is_some()) {
+        let datetimes: Vec<_> = cells.iter().map(|v| v.as_datetime()).collect();
+        // 都是零点的当作日期，和 csv 里的日期列一致
+        if datetimes
+            .iter()
+            .flatten()
+            .all(|v| v.num_seconds_from_midnight() == 0)
+        {
+            let ca: Date32Chunked = datetimes
+                .iter()
+                .map(|v| v.map(|v| v.timestamp().div_euclid(86400) as i32))
+                .collect();
+            return ca.into_series();
+        }
+        let ca: Date64Chunked = datetimes
+            .iter()
+            .map(|v| v.map(|v| v.timestamp_millis()))
+            .collect();
+        return ca.into_series();
+    }
+
+    let ca: Utf8Chunked = cells
+        .iter()
+        .map(|v| match v {
+            Cell::Empty | Cell::Error(_) => None,
+            v => Some(v.to_string()),
+        })
+        .collect();
+    ca.into_series()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_sheet_works() {
+        assert_eq!(
+            split_sheet("file:///tmp/a.xlsx#Sheet2!A1:C10"),
+            Some(("file:///tmp/a.xlsx", Some("Sheet2!A1:C10")))
+        );
+        assert_eq!(
+            split_sheet("https://example.com/a.ODS?raw=true"),
+            Some(("https://example.com/a.ODS?raw=true", None))
+        );
+        assert_eq!(split_sheet("file:///tmp/a.csv"), None);
+    }
+
+    #[test]
+    fn sub_range_works() {
+        assert_eq!(cell_position("A1").unwrap(), (0, 0));
+        assert_eq!(cell_position("ab12").unwrap(), (11, 27));
+        assert!(cell_position("12").is_err());
+        assert!(cell_position("A0").is_err());
+
+        let mut range = Range::new((0, 0), (3, 2));
+        range.set_value((1, 1), Cell::Int(5));
+        let sub = sub_range(&range, "B2:C4").unwrap();
+        assert_eq!(sub.start(), Some((1, 1)));
+        assert_eq!(sub.height(), 3);
+        assert_eq!(sub.rows().next().unwrap()[0], Cell::Int(5));
+        assert!(sub_range(&range, "C4:B2").is_err());
+    }
+
+    #[test]
+    fn to_series_works() {
+        let cells = [Cell::Float(1.0), Cell::Empty, Cell::Int(3)];
+        let s = to_series("n", &cells.iter().collect::<Vec<_>>());
+        assert_eq!(s.dtype(), &DataType::Int64);
+        assert_eq!(s.null_count(), 1);
+
+        let cells = [Cell::Float(1.5), Cell::Int(3)];
+        let s = to_series("n", &cells.iter().collect::<Vec<_>>());
+        assert_eq!(s.dtype(), &DataType::Float64);
+
+        let cells = [Cell::Float(1.5), Cell::String("a".into()), Cell::Bool(true)];
+        let s = to_series("n", &cells.iter().collect::<Vec<_>>());
+        assert_eq!(s.dtype(), &DataType::Utf8);
+        assert_eq!(s.utf8().unwrap().get(2), Some("true"));
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, Result};
+use sqlparser::tokenizer::Token;
+
+/// SQL 里 `$1` / `$name` 参数的值
+/// 参数在解析 SQL 之前就替换成对应的字面量，所以可以出现在任何能写字面量的地方，包括 LIMIT / OFFSET
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl From<bool> for Param {
+    fn from(v: bool) -> Self {
+        Self::Bool(v)
+    }
+}
+
+impl From<i32> for Param {
+    fn from(v: i32) -> Self {
+        Self::Int(v as i64)
+    }
+}
+
+impl From<i64> for Param {
+    fn from(v: i64) -> Self {
+        Self::Int(v)
+    }
+}
+
+impl From<f64> for Param {
+    fn from(v: f64) -> Self {
+        Self::Float(v)
+    }
+}
+
+impl From<&str> for Param {
+    fn from(v: &str) -> Self {
+        Self::Str(v.to_owned())
+    }
+}
+
+impl From<String> for Param {
+    fn from(v: String) -> Self {
+        Self::Str(v)
+    }
+}
+
+impl<T: Into<Param>> From<Option<T>> for Param {
+    fn from(v: Option<T>) -> Self {
+        v.map(Into::into).unwrap_or(Self::Null)
+    }
+}
+
+impl Param {
+    fn to_token(&self) -> Token {
+        match self {
+            Self::Null => Token::make_keyword("NULL"),
+            Self::Bool(true) => Token::make_keyword("TRUE"),
+            Self::Bool(false) => Token::make_keyword("FALSE"),
+            Self::Int(v) => Token::Number(v.to_string(), false),
+            // 用 {:?} 保证整数值的浮点数也带小数点，不会被当成整数
+            Self::Float(v) => Token::Number(format!("{:?}", v), false),
+            Self::Str(v) => Token::SingleQuotedString(v.clone()),
+        }
+    }
+}
+
+/// 把 token 里的 `$1` / `$name` 替换成参数的值，没有绑定的参数报错
+pub(crate) fn bind_params(tokens: Vec<Token>, params: &[(String, Param)]) -> Result<Vec<Token>> {
+    let mut result = Vec::with_capacity(tokens.len());
+    let mut iter = tokens.into_iter().peekable();
+    while let Some(token) = iter.next() {
+        if token != Token::Char('$') {
+            result.push(token);
+            continue;
+        }
+
+        let name = match iter.next() {
+            Some(Token::Number(n, _)) => n,
+            Some(Token::Word(w)) if w.quote_style.is_none() => w.value,
+            t => return Err(anyhow!("Expected parameter name after $, found {:?}", t)),
+        };
+        // 同名参数以最后设置的为准
+        let param = params
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == name)
+            .ok_or_else(|| anyhow!("parameter ${} is not bound", name))?;
+        result.push(param.1.to_token());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TyrDialect;
+    use sqlparser::tokenizer::Tokenizer;
+
+    fn tokens(sql: &str) -> Vec<Token> {
+        Tokenizer::new(&TyrDialect, sql).tokenize().unwrap()
+    }
+
+    #[test]
+    fn bind_params_works() {
+        let params = vec![
+            ("1".to_owned(), Param::from(10)),
+            ("name".to_owned(), Param::from("Tyr")),
+            ("ratio".to_owned(), Param::from(1.0)),
+        ];
+        let result = bind_params(tokens("a > $1 AND b = $name OR c < $ratio"), &params).unwrap();
+        let sql: String = result.iter().map(|t| t.to_string()).collect();
+        assert_eq!(sql, "a > 10 AND b = 'Tyr' OR c < 1.0");
+
+        assert!(bind_params(tokens("a > $2"), &params).is_err());
+    }
+}
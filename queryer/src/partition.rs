@@ -0,0 +1,941 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    loader::Load,
+    registry,
+    settings::{csv_header, Options},
+    DataSet,
+};
+use anyhow::{anyhow, Result};
+use parquet::{
+    arrow::{ArrowReader, ParquetFileArrowReader},
+    basic::ConvertedType,
+    file::{
+        metadata::RowGroupMetaData,
+        reader::{FileReader, SerializedFileReader},
+        statistics::Statistics,
+    },
+};
+use polars::prelude::*;
+use sqlparser::ast::{
+    BinaryOperator as SqlBinaryOperator, Expr as SqlExpr, UnaryOperator, Value as SqlValue,
+};
+use std::{
+    cmp::Ordering,
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+use tracing::{debug, info};
+
+// 按 Hive 分区目录读取的数据文件扩展名
+const DATA_EXTENSIONS: [&str; 2] = ["csv", "parquet"];
+// Hive 用这个目录名表示分区值为 NULL
+const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// WHERE 里用 AND 连接的简单条件：`列 op 字面量` 或者 `列 IN (字面量, ...)`
+/// 只用来跳过肯定没有匹配行的文件和 row group，真正的过滤还是在读进来之后做
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Predicate {
+    column: String,
+    op: Comparison,
+    values: Vec<Scalar>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Eq,
+    NotEq,
+    Gt,
+    Lt,
+    GtEq,
+    LtEq,
+    In,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Scalar {
+    Number(Number),
+    Str(String),
+}
+
+/// 整数单独保存，大于 2^53 的整数转成 f64 会丢精度，和 Int64 的统计信息比较时会出错
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn parse(v: &str) -> Option<Self> {
+        match v.parse::<i64>() {
+            Ok(v) => Some(Self::Int(v)),
+            Err(_) => v.parse::<f64>().ok().map(Self::Float),
+        }
+    }
+
+    fn neg(self) -> Self {
+        match self {
+            Self::Int(v) => v.checked_neg().map_or(Self::Float(-(v as f64)), Self::Int),
+            Self::Float(v) => Self::Float(-v),
+        }
+    }
+
+    /// 比不出大小（NaN，或者整数太大转成 f64 不精确）时返回 None
+    fn compare(self, other: Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => Some(a.cmp(&b)),
+            (Self::Int(a), Self::Float(b)) => compare_int_float(a, b),
+            (Self::Float(a), Self::Int(b)) => compare_int_float(b, a).map(Ordering::reverse),
+            (Self::Float(a), Self::Float(b)) => a.partial_cmp(&b),
+        }
+    }
+}
+
+fn compare_int_float(a: i64, b: f64) -> Option<Ordering> {
+    match a.unsigned_abs() <= 1 << f64::MANTISSA_DIGITS {
+        true => (a as f64).partial_cmp(&b),
+        false => None,
+    }
+}
+
+/// 从 WHERE 子句里找出可以用来剪枝的条件，OR 之类的复杂条件直接忽略
+pub(crate) fn predicates(expr: &SqlExpr) -> Vec<Predicate> {
+    match expr {
+        SqlExpr::BinaryOp {
+            left,
+            op: SqlBinaryOperator::And,
+            right,
+        } => {
+            let mut result = predicates(left);
+            result.extend(predicates(right));
+            result
+        }
+        SqlExpr::Nested(expr) => predicates(expr),
+        SqlExpr::BinaryOp { left, op, right } => {
+            let op = match op {
+                SqlBinaryOperator::Eq => Comparison::Eq,
+                SqlBinaryOperator::NotEq => Comparison::NotEq,
+                SqlBinaryOperator::Gt => Comparison::Gt,
+                SqlBinaryOperator::Lt => Comparison::Lt,
+                SqlBinaryOperator::GtEq => Comparison::GtEq,
+                SqlBinaryOperator::LtEq => Comparison::LtEq,
+                _ => return vec![],
+            };
+            // `5 < a` 反过来就是 `a > 5`
+            let (column, op, value) = match (column(left), scalar(right)) {
+                (Some(column), Some(value)) => (column, op, value),
+                _ => match (scalar(left), column(right)) {
+                    (Some(value), Some(column)) => (column, op.flip(), value),
+                    _ => return vec![],
+                },
+            };
+            vec![Predicate {
+                column,
+                op,
+                values: vec![value],
+            }]
+        }
+        SqlExpr::InList {
+            expr,
+            list,
+            negated: false,
+        } => {
+            let values: Option<Vec<_>> = list.iter().map(scalar).collect();
+            match (column(expr), values) {
+                (Some(column), Some(values)) => vec![Predicate {
+                    column,
+                    op: Comparison::In,
+                    values,
+                }],
+                _ => vec![],
+            }
+        }
+        _ => vec![],
+    }
+}
+
+/// 没有引号的 t.col 会被解析成一个标识符，这时不知道它对应哪一列，不用它剪枝
+fn column(expr: &SqlExpr) -> Option<String> {
+    match expr {
+        SqlExpr::Identifier(id) if id.quote_style.is_some() || !id.value.contains('.') => {
+            Some(id.value.clone())
+        }
+        _ => None,
+    }
+}
+
+fn scalar(expr: &SqlExpr) -> Option<Scalar> {
+    match expr {
+        SqlExpr::Value(SqlValue::Number(v, _)) => Number::parse(v).map(Scalar::Number),
+        SqlExpr::Value(SqlValue::SingleQuotedString(v)) => Some(Scalar::Str(v.clone())),
+        SqlExpr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match scalar(expr)? {
+            Scalar::Number(v) => Some(Scalar::Number(v.neg())),
+            Scalar::Str(_) => None,
+        },
+        _ => None,
+    }
+}
+
+impl Comparison {
+    fn flip(self) -> Self {
+        match self {
+            Self::Gt => Self::Lt,
+            Self::Lt => Self::Gt,
+            Self::GtEq => Self::LtEq,
+            Self::LtEq => Self::GtEq,
+            op => op,
+        }
+    }
+
+    fn accepts(self, ord: Ordering) -> bool {
+        match self {
+            Self::Eq | Self::In => ord == Ordering::Equal,
+            Self::NotEq => ord != Ordering::Equal,
+            Self::Gt => ord == Ordering::Greater,
+            Self::Lt => ord == Ordering::Less,
+            Self::GtEq => ord != Ordering::Less,
+            Self::LtEq => ord != Ordering::Greater,
+        }
+    }
+}
+
+impl Predicate {
+    /// 分区值（目录名里 `=` 后面的部分）能不能满足条件
+    /// 拿不准的时候（类型对不上、字符串比大小）都当作满足，只是少剪掉一些文件
+    fn matches(&self, value: Option<&str>) -> bool {
+        // 和 NULL 比较的结果不会是 true
+        let value = match value {
+            Some(v) => v,
+            None => return false,
+        };
+        let number = Number::parse(value);
+        self.values.iter().any(|expected| match (expected, number) {
+            (Scalar::Number(expected), Some(v)) => match v.compare(*expected) {
+                Some(ord) => self.op.accepts(ord),
+                None => true,
+            },
+            // 分区列会被推断成数字，和字符串的比较交给真正的过滤
+            (Scalar::Number(_), None) | (Scalar::Str(_), Some(_)) => true,
+            (Scalar::Str(expected), None) => match self.op {
+                Comparison::Eq | Comparison::NotEq | Comparison::In => {
+                    self.op.accepts(value.cmp(expected.as_str()))
+                }
+                _ => true,
+            },
+        })
+    }
+
+    /// row group 里这一列的取值范围是 [min, max]，有没有可能满足条件
+    fn overlaps(&self, min: Number, max: Number) -> bool {
+        self.values.iter().any(|expected| match expected {
+            Scalar::Number(v) => match (min.compare(*v), max.compare(*v)) {
+                (Some(lo), Some(hi)) => match self.op {
+                    Comparison::Eq | Comparison::In => {
+                        lo != Ordering::Greater && hi != Ordering::Less
+                    }
+                    Comparison::NotEq => !(lo == Ordering::Equal && hi == Ordering::Equal),
+                    Comparison::Gt | Comparison::GtEq => self.op.accepts(hi),
+                    Comparison::Lt | Comparison::LtEq => self.op.accepts(lo),
+                },
+                _ => true,
+            },
+            Scalar::Str(_) => true,
+        })
+    }
+}
+
+/// `file://` 数据源是目录或者 parquet 文件时按分区数据集读取，返回本地路径
+/// 用户注册了 file 的 fetcher 时还是交给它处理
+pub(crate) fn lake_path(source: &str) -> Option<PathBuf> {
+    let path = source.strip_prefix("file://")?;
+    if registry::get_fetcher("file").is_some() {
+        return None;
+    }
+    let path = PathBuf::from(path);
+    match path.is_dir() || extension(&path).as_deref() == Some("parquet") {
+        true => Some(path),
+        false => None,
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|v| v.to_str())
+        .map(|v| v.to_lowercase())
+}
+
+/// 一个数据文件，以及从目录名 `key=value` 里解析出来的分区值
+#[derive(Debug, Clone)]
+struct DataFile {
+    path: PathBuf,
+    size: u64,
+    partitions: Vec<(String, Option<String>)>,
+}
+
+/// Hive 风格分区（`year=2023/month=09/part-0.parquet`）的目录，也可以是单个 parquet 文件
+/// 分区键作为列加到读出来的数据里，WHERE 里分区键上的条件在读文件前就用来跳过目录，
+/// 其它列上的条件用 parquet 的 row group 统计信息跳过 row group
+#[derive(Debug)]
+pub struct LakeLoader {
+    files: Vec<DataFile>,
+    // 所有文件里出现过的分区键，以及按所有分区值推断出来的类型
+    keys: Vec<(String, DataType)>,
+    predicates: Vec<Predicate>,
+    options: Options,
+}
+
+impl LakeLoader {
+    /// 列出 root 下面的数据文件，去掉分区值不满足 predicates 的
+    pub(crate) async fn discover(
+        root: PathBuf,
+        predicates: Vec<Predicate>,
+        options: Options,
+    ) -> Result<Self> {
+        // 遍历目录是同步的文件操作，放到 blocking 线程池里跑
+        tokio::task::spawn_blocking(move || {
+            let mut files = Vec::new();
+            match root.is_dir() {
+                true => walk(&root, &mut vec![], &mut files)?,
+                false => files.push(DataFile {
+                    size: fs::metadata(&root)?.len(),
+                    path: root.clone(),
+                    partitions: vec![],
+                }),
+            }
+            if files.is_empty() {
+                return Err(anyhow!("no data files found in {}", root.display()));
+            }
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            // 类型要按剪枝前的所有文件推断，结果的表结构才不会随着条件变化
+            let keys = partition_keys(&files);
+
+            let candidates = files.len();
+            let mut kept = Vec::new();
+            for file in &files {
+                if file.matches(&predicates)? {
+                    kept.push(file.clone());
+                }
+            }
+            // 全部被剪掉时留一个文件，读出表结构，结果才有正确的列
+            if kept.is_empty() {
+                kept.push(files[0].clone());
+            }
+            info!(
+                "partition pruning kept {} of {} files",
+                kept.len(),
+                candidates
+            );
+
+            Ok(Self {
+                files: kept,
+                keys,
+                predicates,
+                options,
+            })
+        })
+        .await?
+    }
+
+    /// 要读取的文件的总字节数
+    pub(crate) fn size(&self) -> usize {
+        self.files.iter().map(|f| f.size as usize).sum()
+    }
+
+    /// 一批一批地读，每批最多 rows 行，流式查询用，内存里只有当前读的这一批
+    /// 列的顺序和类型按所有文件的 schema 统一，和 load 的结果一样。
+    /// 只支持 parquet 文件，csv 不读完推断不出统一的类型，有 csv 文件时返回 None
+    pub(crate) fn batches(self, rows: usize) -> Result<Option<LakeBatches>> {
+        if self
+            .files
+            .iter()
+            .any(|f| extension(&f.path).as_deref() != Some("parquet"))
+        {
+            return Ok(None);
+        }
+        let mut schemas = Vec::with_capacity(self.files.len());
+        for file in &self.files {
+            // 分区键和 with_partitions 一样加在最后
+            let mut fields = parquet_schema(&file.path)?.fields().clone();
+            for (key, dtype) in &self.keys {
+                if !fields.iter().any(|f| f.name() == key) {
+                    fields.push(Field::new(key, dtype.clone()));
+                }
+            }
+            schemas.push(Schema::new(fields));
+        }
+        let (names, dtypes) = common_schema(&schemas)?;
+        Ok(Some(LakeBatches {
+            loader: self,
+            rows,
+            names,
+            dtypes,
+            file: 0,
+            groups: None,
+            group: 0,
+            pending: None,
+            emitted: false,
+        }))
+    }
+}
+
+/// LakeLoader 一批一批读出来的数据，每次读一个 row group，再按 rows 行切开返回
+pub(crate) struct LakeBatches {
+    loader: LakeLoader,
+    rows: usize,
+    // 统一之后的列名和类型
+    names: Vec<String>,
+    dtypes: Vec<DataType>,
+    // 正在读的文件，它有几个 row group，以及下一个要读的 row group
+    file: usize,
+    groups: Option<usize>,
+    group: usize,
+    // 读进来还没有返回完的 row group，以及下一批开始的行
+    pending: Option<(DataFrame, usize)>,
+    emitted: bool,
+}
+
+impl LakeBatches {
+    fn next_batch(&mut self) -> Result<Option<DataFrame>> {
+        loop {
+            if let Some((df, position)) = self.pending.as_mut() {
+                if *position < df.height() {
+                    let batch = df.slice(*position as i64, self.rows);
+                    *position += batch.height();
+                    return Ok(Some(batch));
+                }
+                self.pending = None;
+            }
+            let file = match self.loader.files.get(self.file) {
+                Some(file) => file,
+                None => return Ok(None),
+            };
+            let groups = match self.groups {
+                Some(groups) => groups,
+                None => *self.groups.insert(row_groups(&file.path)?),
+            };
+            if self.group >= groups {
+                self.file += 1;
+                self.groups = None;
+                self.group = 0;
+                continue;
+            }
+            let df = open_parquet(&file.path, &self.loader.predicates, Some(self.group))?;
+            self.group += 1;
+            if let Some(df) = df {
+                let df = with_partitions(df, file, &self.loader.keys)?;
+                self.pending = Some((conform(&df, &self.names, &self.dtypes)?, 0));
+            }
+        }
+    }
+
+    /// 所有 row group 都被跳过时，不带条件读第一个 row group，只留下表结构
+    fn empty(&self) -> Result<DataFrame> {
+        let file = &self.loader.files[0];
+        let df = open_parquet(&file.path, &[], Some(0))?
+            .ok_or_else(|| anyhow!("{} has no data", file.path.display()))?;
+        let df = with_partitions(df.head(Some(0)), file, &self.loader.keys)?;
+        conform(&df, &self.names, &self.dtypes)
+    }
+}
+
+impl Iterator for LakeBatches {
+    type Item = Result<DataFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = match self.next_batch() {
+            Ok(Some(df)) => Ok(df),
+            Ok(None) if !self.emitted => self.empty(),
+            Ok(None) => return None,
+            Err(e) => {
+                // 出错之后不再继续读
+                self.file = self.loader.files.len();
+                self.pending = None;
+                Err(e)
+            }
+        };
+        self.emitted = true;
+        Some(result)
+    }
+}
+
+impl DataFile {
+    /// 分区值能不能满足条件。文件里有和分区键同名的列时，读出来的是文件里的值，
+    /// 这个分区键不能用来剪枝
+    fn matches(&self, predicates: &[Predicate]) -> Result<bool> {
+        let keys: Vec<_> = self
+            .partitions
+            .iter()
+            .filter(|(key, _)| predicates.iter().any(|p| &p.column == key))
+            .collect();
+        // 没有分区键上的条件时不用去读文件的列
+        if keys.is_empty() {
+            return Ok(true);
+        }
+        let columns = file_columns(&self.path)?;
+        Ok(keys
+            .into_iter()
+            .filter(|(key, _)| !columns.contains(key))
+            .all(|(key, value)| {
+                predicates
+                    .iter()
+                    .filter(|p| &p.column == key)
+                    .all(|p| p.matches(value.as_deref()))
+            }))
+    }
+}
+
+/// 数据文件里的列名，parquet 读 footer 里的 schema，csv 只读表头那一行
+fn file_columns(path: &Path) -> Result<Vec<String>> {
+    match extension(path).as_deref() {
+        Some("parquet") => {
+            let reader = SerializedFileReader::new(File::open(path)?)?;
+            let columns = reader
+                .metadata()
+                .file_metadata()
+                .schema_descr()
+                .root_schema()
+                .get_fields()
+                .iter()
+                .map(|f| f.name().to_owned())
+                .collect();
+            Ok(columns)
+        }
+        _ => {
+            let mut header = Vec::new();
+            BufReader::new(File::open(path)?).read_until(b'\n', &mut header)?;
+            Ok(csv_header(&header))
+        }
+    }
+}
+
+/// 递归遍历目录，`key=value` 的目录名是分区，`_` 和 `.` 开头的（_SUCCESS 之类）跳过
+fn walk(
+    dir: &Path,
+    partitions: &mut Vec<(String, Option<String>)>,
+    files: &mut Vec<DataFile>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('_') || name.starts_with('.') {
+            continue;
+        }
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            let partition = name.split_once('=').map(|(key, value)| {
+                let value = match value {
+                    DEFAULT_PARTITION => None,
+                    v => Some(v.to_owned()),
+                };
+                (key.to_owned(), value)
+            });
+            let pushed = partition.is_some();
+            partitions.extend(partition);
+            walk(&path, partitions, files)?;
+            if pushed {
+                partitions.pop();
+            }
+        } else if let Some(ext) = extension(&path) {
+            if DATA_EXTENSIONS.contains(&ext.as_str()) {
+                files.push(DataFile {
+                    path,
+                    size: meta.len(),
+                    partitions: partitions.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 分区值都是整数时分区列是 Int64，都是数字时是 Float64，否则是 Utf8
+fn partition_keys(files: &[DataFile]) -> Vec<(String, DataType)> {
+    let mut keys: Vec<(String, DataType)> = Vec::new();
+    for (key, value) in files.iter().flat_map(|f| f.partitions.iter()) {
+        let dtype = match value {
+            None => DataType::Int64,
+            Some(v) if v.parse::<i64>().is_ok() => DataType::Int64,
+            Some(v) if v.parse::<f64>().is_ok() => DataType::Float64,
+            Some(_) => DataType::Utf8,
+        };
+        match keys.iter_mut().find(|(k, _)| k == key) {
+            Some((_, current)) => *current = common_type(current, &dtype),
+            None => keys.push((key.clone(), dtype)),
+        }
+    }
+    keys
+}
+
+/// 两个文件里同一列类型不一样时统一成的类型：数字统一成 Float64，其它的都用 Utf8
+fn common_type(a: &DataType, b: &DataType) -> DataType {
+    let numeric = |t: &DataType| {
+        matches!(
+            t,
+            DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float32
+                | DataType::Float64
+        )
+    };
+    match (a, b) {
+        (a, b) if a == b => a.clone(),
+        (a, b) if numeric(a) && numeric(b) => DataType::Float64,
+        _ => DataType::Utf8,
+    }
+}
+
+impl Load for LakeLoader {
+    type Error = anyhow::Error;
+
+    fn load(self) -> Result<DataSet, Self::Error> {
+        let mut frames = Vec::with_capacity(self.files.len());
+        for file in &self.files {
+            if let Some(df) = read_file(&file.path, &self.predicates, &self.options)? {
+                frames.push(with_partitions(df, file, &self.keys)?);
+            }
+        }
+        // 所有 row group 都被跳过时，不带条件读第一个文件，只留下表结构
+        if frames.is_empty() {
+            let file = &self.files[0];
+            let df = read_file(&file.path, &[], &self.options)?
+                .ok_or_else(|| anyhow!("{} has no data", file.path.display()))?;
+            frames.push(with_partitions(df.head(Some(0)), file, &self.keys)?);
+        }
+        Ok(DataSet(concat(frames)?))
+    }
+}
+
+/// 读一个数据文件，parquet 文件所有 row group 都被跳过时返回 None
+fn read_file(
+    path: &Path,
+    predicates: &[Predicate],
+    options: &Options,
+) -> Result<Option<DataFrame>> {
+    match extension(path).as_deref() {
+        Some("parquet") => read_parquet(path, predicates),
+        _ => Ok(Some(options.csv_reader(fs::read(path)?, true).finish()?)),
+    }
+}
+
+/// 用每个 row group 的 min / max 统计信息跳过肯定没有匹配行的 row group
+fn read_parquet(path: &Path, predicates: &[Predicate]) -> Result<Option<DataFrame>> {
+    open_parquet(path, predicates, None)
+}
+
+/// 读 parquet 文件里没有被统计信息跳过的 row group，group 不为 None 时只读这一个
+/// 所有 row group 都被跳过时返回 None
+fn open_parquet(
+    path: &Path,
+    predicates: &[Predicate],
+    group: Option<usize>,
+) -> Result<Option<DataFrame>> {
+    let mut reader = SerializedFileReader::new(File::open(path)?)?;
+    let total = reader.num_row_groups();
+    reader.filter_row_groups(&|rg, i| {
+        (group.is_none() || group == Some(i)) && may_match(rg, predicates)
+    });
+    let kept = reader.num_row_groups();
+    debug!("{}: kept {} of {} row groups", path.display(), kept, total);
+    if kept == 0 {
+        return Ok(None);
+    }
+
+    let rows: i64 = reader
+        .metadata()
+        .row_groups()
+        .iter()
+        .map(|rg| rg.num_rows())
+        .sum();
+    let mut reader = ParquetFileArrowReader::new(Arc::new(reader));
+    let batches = reader
+        .get_record_reader(rows.max(1) as usize)?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(Some(DataFrame::try_from(batches)?))
+}
+
+/// parquet 文件里 row group 的个数，只读 footer
+fn row_groups(path: &Path) -> Result<usize> {
+    Ok(SerializedFileReader::new(File::open(path)?)?.num_row_groups())
+}
+
+/// parquet 文件的列名和类型，只读 footer
+fn parquet_schema(path: &Path) -> Result<Schema> {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    let schema = ParquetFileArrowReader::new(Arc::new(reader)).get_schema()?;
+    Ok((&schema).into())
+}
+
+fn may_match(rg: &RowGroupMetaData, predicates: &[Predicate]) -> bool {
+    predicates.iter().all(|p| {
+        rg.columns()
+            .iter()
+            .find(|c| c.column_path().string() == p.column)
+            .and_then(|c| match c.column_descr().converted_type() {
+                // 无符号整数、decimal 这些物理值和逻辑值对不上，不能直接比较
+                ConvertedType::NONE
+                | ConvertedType::INT_8
+                | ConvertedType::INT_16
+                | ConvertedType::INT_32
+                | ConvertedType::INT_64 => c.statistics(),
+                _ => None,
+            })
+            .and_then(min_max)
+            .is_none_or(|(min, max)| p.overlaps(min, max))
+    })
+}
+
+fn min_max(stats: &Statistics) -> Option<(Number, Number)> {
+    if !stats.has_min_max_set() {
+        return None;
+    }
+    match stats {
+        Statistics::Int32(s) => Some((Number::Int(*s.min() as i64), Number::Int(*s.max() as i64))),
+        Statistics::Int64(s) => Some((Number::Int(*s.min()), Number::Int(*s.max()))),
+        Statistics::Float(s) => Some((
+            Number::Float(*s.min() as f64),
+            Number::Float(*s.max() as f64),
+        )),
+        Statistics::Double(s) => Some((Number::Float(*s.min()), Number::Float(*s.max()))),
+        _ => None,
+    }
+}
+
+/// 把分区值作为常量列加到文件的数据后面，文件里已经有同名列时以文件为准
+fn with_partitions(
+    mut df: DataFrame,
+    file: &DataFile,
+    keys: &[(String, DataType)],
+) -> Result<DataFrame> {
+    let height = df.height();
+    for (key, dtype) in keys {
+        if df.column(key).is_ok() {
+            continue;
+        }
+        let value = file
+            .partitions
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| v.clone());
+        let s = Series::new(key, vec![value; height]).cast_with_dtype(dtype)?;
+        df.with_column(s)?;
+    }
+    Ok(df)
+}
+
+/// 按第一个文件的列顺序把所有文件的数据接起来，同一列类型不一样时先统一类型
+fn concat(frames: Vec<DataFrame>) -> Result<DataFrame> {
+    let schemas: Vec<_> = frames.iter().map(|df| df.schema()).collect();
+    let (names, dtypes) = common_schema(&schemas)?;
+
+    let mut result: Option<DataFrame> = None;
+    for df in frames {
+        let df = conform(&df, &names, &dtypes)?;
+        match result.as_mut() {
+            Some(acc) => {
+                acc.vstack_mut(&df)?;
+            }
+            None => result = Some(df),
+        }
+    }
+    result.ok_or_else(|| anyhow!("no data files to read"))
+}
+
+/// 所有文件按第一个文件的列顺序，每一列取能放下所有文件里的值的类型
+fn common_schema(schemas: &[Schema]) -> Result<(Vec<String>, Vec<DataType>)> {
+    let first = schemas
+        .first()
+        .ok_or_else(|| anyhow!("no data files to read"))?;
+    let names: Vec<String> = first.fields().iter().map(|f| f.name().clone()).collect();
+    let mut dtypes: Vec<DataType> = first
+        .fields()
+        .iter()
+        .map(|f| f.data_type().clone())
+        .collect();
+    for schema in &schemas[1..] {
+        for (name, dtype) in names.iter().zip(dtypes.iter_mut()) {
+            let other = schema
+                .field_with_name(name)
+                .map_err(|_| anyhow!("column {} is missing in some of the files", name))?
+                .data_type();
+            *dtype = common_type(dtype, other);
+        }
+    }
+    Ok((names, dtypes))
+}
+
+/// 按 common_schema 的结果调整列的顺序和类型
+fn conform(df: &DataFrame, names: &[String], dtypes: &[DataType]) -> Result<DataFrame> {
+    let mut columns = Vec::with_capacity(names.len());
+    for (name, dtype) in names.iter().zip(dtypes.iter()) {
+        columns.push(df.column(name)?.cast_with_dtype(dtype)?);
+    }
+    Ok(DataFrame::new(columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Session, TyrDialect};
+    use sqlparser::{ast::Statement, parser::Parser};
+    use std::env;
+
+    fn where_clause(sql: &str) -> SqlExpr {
+        match &Parser::parse_sql(&TyrDialect, sql).unwrap()[0] {
+            Statement::Query(q) => match &q.body {
+                sqlparser::ast::SetExpr::Select(s) => s.selection.clone().unwrap(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn predicates_works() {
+        let expr = where_clause(
+            "SELECT a FROM t WHERE year = 2023 AND 5 < month AND name IN ('a', 'b') AND (x = 1 OR y = 2)",
+        );
+        let result = predicates(&expr);
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[1].column, "month");
+        assert_eq!(result[1].op, Comparison::Gt);
+
+        assert!(result[0].matches(Some("2023")));
+        assert!(!result[0].matches(Some("2024")));
+        assert!(!result[0].matches(None));
+        assert!(result[1].matches(Some("09")));
+        assert!(!result[1].matches(Some("5")));
+        assert!(result[2].matches(Some("b")));
+        assert!(!result[2].matches(Some("c")));
+
+        assert!(result[1].overlaps(Number::Int(1), Number::Int(6)));
+        assert!(!result[1].overlaps(Number::Int(1), Number::Float(5.0)));
+
+        // 超过 2^53 的整数按 i64 比较
+        let expr = where_clause("SELECT a FROM t WHERE id > 9007199254740992");
+        let result = predicates(&expr);
+        let max = Number::Int(9007199254740993);
+        assert!(result[0].overlaps(Number::Int(0), max));
+        assert!(!result[0].overlaps(Number::Int(0), Number::Int(9007199254740992)));
+        assert!(result[0].matches(Some("9007199254740993")));
+    }
+
+    #[tokio::test]
+    async fn partitioned_directory_works() {
+        let root = env::temp_dir().join("queryer_lake_partitions");
+        let _ = fs::remove_dir_all(&root);
+        for (dir, data) in [
+            ("year=2023/month=09", "name,amount\nTyr,1\nLindsey,2\n"),
+            ("year=2024/month=01", "name,amount\nTyr,3\n"),
+            ("year=2024/month=02", "name,amount\nLindsey,4\n"),
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            fs::write(root.join(dir).join("part-0.csv"), data).unwrap();
+        }
+        fs::write(root.join("_SUCCESS"), "").unwrap();
+
+        let expr = where_clause("SELECT a FROM t WHERE year = 2024 AND month > 1");
+        let lake = LakeLoader::discover(root.clone(), predicates(&expr), Options::default())
+            .await
+            .unwrap();
+        assert_eq!(lake.files.len(), 1);
+        assert_eq!(
+            lake.keys,
+            vec![
+                ("year".into(), DataType::Int64),
+                ("month".into(), DataType::Int64)
+            ]
+        );
+
+        let sql = format!(
+            "SELECT name, amount, year, month FROM file://{} WHERE year = 2024 ORDER BY amount",
+            root.display()
+        );
+        let ds = Session::default().query(sql).await.unwrap();
+        assert_eq!(ds.height(), 2);
+        let months: Vec<i64> = ds
+            .column("month")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(months, vec![1, 2]);
+
+        // 全部被剪掉时也要有正确的列
+        let sql = format!(
+            "SELECT name, year FROM file://{} WHERE year = 2000",
+            root.display()
+        );
+        let ds = Session::default().query(sql).await.unwrap();
+        assert_eq!(ds.height(), 0);
+        assert_eq!(ds.get_column_names(), vec!["name", "year"]);
+    }
+
+    #[tokio::test]
+    async fn file_column_shadows_partition_works() {
+        let root = env::temp_dir().join("queryer_lake_shadowed");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("id=1")).unwrap();
+        fs::write(root.join("id=1/part-0.csv"), "id,name\n5,Tyr\n").unwrap();
+
+        // 文件里的 id 优先，不能按目录名里的 id=1 把文件剪掉
+        let sql = format!("SELECT name FROM file://{} WHERE id = 5", root.display());
+        let ds = Session::default().query(sql).await.unwrap();
+        assert_eq!(ds.height(), 1);
+    }
+
+    #[tokio::test]
+    async fn parquet_row_groups_works() {
+        let path = env::temp_dir().join("queryer_lake_row_groups.parquet");
+        let df = DataFrame::new(vec![
+            Series::new("id", [1i64, 2, 3]),
+            Series::new("name", ["Tyr", "Lindsey", "Tom"]),
+        ])
+        .unwrap();
+        ParquetWriter::new(File::create(&path).unwrap())
+            .finish(&df)
+            .unwrap();
+
+        assert!(read_parquet(&path, &[]).unwrap().is_some());
+        let expr = where_clause("SELECT a FROM t WHERE id > 3");
+        assert!(read_parquet(&path, &predicates(&expr)).unwrap().is_none());
+
+        let sql = format!("SELECT name FROM file://{} WHERE id >= 2", path.display());
+        let ds = Session::default().query(sql).await.unwrap();
+        assert_eq!(ds.height(), 2);
+        let sql = format!("SELECT name FROM file://{} WHERE id > 3", path.display());
+        let ds = Session::default().query(sql).await.unwrap();
+        assert_eq!(ds.height(), 0);
+
+        let path = env::temp_dir().join("queryer_lake_large_ids.parquet");
+        let df = DataFrame::new(vec![Series::new("id", [9007199254740993i64])]).unwrap();
+        ParquetWriter::new(File::create(&path).unwrap())
+            .finish(&df)
+            .unwrap();
+        let expr = where_clause("SELECT a FROM t WHERE id > 9007199254740992");
+        assert!(read_parquet(&path, &predicates(&expr)).unwrap().is_some());
+    }
+}
@@ -0,0 +1,245 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{command::Command, error::Forbidden, fetcher::split_table};
+use anyhow::Result;
+use reqwest::{redirect::Policy, Url};
+use sqlparser::ast::Statement;
+use tokio_postgres::config::{Config, Host};
+
+// 读取本机文件的 scheme，sqlite 的数据源也是本机上的文件
+const LOCAL_SCHEMES: [&str; 2] = ["file", "sqlite"];
+
+/// Session 允许执行的操作，默认全部允许
+/// 对外提供服务时（比如 queryer-server）可以用 [Permissions::read_only] 只允许读取远程数据源
+#[derive(Debug, Clone, PartialEq)]
+pub struct Permissions {
+    /// INSERT / COPY 写文件
+    pub writes: bool,
+    /// 读取 file:// / sqlite:// 等本机上的文件
+    pub local_files: bool,
+    /// SET / SHOW 选项
+    pub settings: bool,
+    /// 允许访问的远程主机，None 表示不限制，Some 时只允许列表里的主机（空列表全部拒绝）
+    /// `*.example.com` 匹配子域名，`*` 匹配所有主机
+    pub hosts: Option<Vec<String>>,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            writes: true,
+            local_files: true,
+            settings: true,
+            hosts: None,
+        }
+    }
+}
+
+impl Permissions {
+    /// 只允许 SELECT / DIFF 查询远程数据源和注册的内存表
+    pub fn read_only() -> Self {
+        Self {
+            writes: false,
+            local_files: false,
+            settings: false,
+            hosts: None,
+        }
+    }
+
+    /// 只允许访问 hosts 里的远程主机
+    pub fn with_hosts<T: Into<String>>(mut self, hosts: impl IntoIterator<Item = T>) -> Self {
+        self.hosts = Some(hosts.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub(crate) fn check_command(&self, command: &Command) -> Result<()> {
+        let (allowed, name) = match command {
+            Command::Statement(v) if matches!(**v, Statement::Insert { .. }) => {
+                (self.writes, "INSERT")
+            }
+            Command::Copy { .. } => (self.writes, "COPY"),
+            Command::Set { .. } => (self.settings, "SET"),
+            Command::Show { .. } => (self.settings, "SHOW"),
+            _ => (true, ""),
+        };
+        match allowed {
+            true => Ok(()),
+            false => Err(Forbidden(format!("{} is not allowed in this session", name)).into()),
+        }
+    }
+
+    pub(crate) fn check_source(&self, source: &str) -> Result<()> {
+        let scheme = match source.split_once("://") {
+            Some((v, _)) => v.to_lowercase(),
+            None => return Ok(()),
+        };
+        let local = LOCAL_SCHEMES.contains(&scheme.as_str());
+        if local && !self.local_files {
+            return Err(Forbidden(format!(
+                "reading local source {} is not allowed in this session",
+                source
+            ))
+            .into());
+        }
+        if local || self.hosts.is_none() {
+            return Ok(());
+        }
+        // 要连接的主机都要在 hosts 里，解析不出主机的数据源也拒绝
+        match source_hosts(&scheme, source) {
+            Some(hosts) if !hosts.is_empty() && hosts.iter().all(|h| self.allows_host(h)) => Ok(()),
+            _ => Err(Forbidden(format!(
+                "host of source {} is not allowed in this session",
+                source
+            ))
+            .into()),
+        }
+    }
+
+    /// http 数据源跟随重定向时也只能去允许的主机，否则重定向可以绕过 hosts 的限制
+    pub(crate) fn redirect_policy(&self) -> Policy {
+        let permissions = match self.hosts {
+            Some(_) => self.clone(),
+            None => return Policy::default(),
+        };
+        Policy::custom(move |attempt| {
+            let allowed = attempt
+                .url()
+                .host_str()
+                .is_some_and(|h| permissions.allows_host(h));
+            match allowed {
+                true if attempt.previous().len() < 10 => attempt.follow(),
+                true => attempt.error("too many redirects"),
+                false => {
+                    let message = format!("redirect to {} is not allowed", attempt.url());
+                    attempt.error(message)
+                }
+            }
+        })
+    }
+
+    fn allows_host(&self, host: &str) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let hosts = match &self.hosts {
+            Some(v) => v,
+            None => return true,
+        };
+        hosts.iter().any(|v| match v.strip_prefix("*.") {
+            _ if v == "*" => true,
+            Some(domain) => host
+                .to_lowercase()
+                .strip_suffix(&domain.to_lowercase())
+                .is_some_and(|v| v.ends_with('.')),
+            None => v.eq_ignore_ascii_case(host),
+        })
+    }
+}
+
+/// 数据源要连接的主机，解析不了时返回 None
+/// postgres 用 tokio-postgres 自己的解析，连接参数里的 host / hostaddr 也算，unix socket 不算远程主机
+fn source_hosts(scheme: &str, source: &str) -> Option<Vec<String>> {
+    match scheme {
+        "postgres" | "postgresql" => {
+            let (url, _) = split_table(source).ok()?;
+            let config: Config = url.parse().ok()?;
+            let mut hosts = Vec::new();
+            for host in config.get_hosts() {
+                match host {
+                    Host::Tcp(v) => hosts.push(v.clone()),
+                    _ => return None,
+                }
+            }
+            hosts.extend(config.get_hostaddrs().iter().map(|v| v.to_string()));
+            Some(hosts)
+        }
+        _ => {
+            let url = Url::parse(source).ok()?;
+            Some(vec![url.host_str()?.to_owned()])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, Session};
+
+    #[tokio::test]
+    async fn read_only_works() {
+        let path = std::env::temp_dir().join("queryer_permissions.csv");
+        std::fs::write(&path, "name,age\nTyr,18\n").unwrap();
+        let session = Session::default().with_permissions(Permissions::read_only());
+
+        let sqls = [
+            format!("SELECT name FROM file://{}", path.display()),
+            format!("SELECT name FROM sqlite://{}?table=people", path.display()),
+            format!(
+                "COPY (SELECT name FROM https://example.com/a.csv) TO 'file://{}'",
+                path.display()
+            ),
+            "SET inference_rows = 10".to_string(),
+            "SHOW ALL".to_string(),
+        ];
+        for sql in sqls {
+            let e = session.query(&sql).await.unwrap_err();
+            assert_eq!(ErrorKind::of(&e), ErrorKind::Forbidden, "{}", sql);
+        }
+
+        let sql = format!("SELECT name FROM file://{}", path.display());
+        assert!(Session::default().query(&sql).await.is_ok());
+    }
+
+    #[test]
+    fn check_hosts_works() {
+        let p = Permissions::read_only().with_hosts(["example.com", "*.internal.net"]);
+        let allowed = [
+            "https://example.com/a.csv",
+            "http://EXAMPLE.com:8080/a.csv",
+            "https://u:p@example.com/a.csv",
+            "https://data.internal.net/a.csv",
+            "postgres://u:p@example.com/db?table=t",
+            "postgresql://a.internal.net,b.internal.net:5433/db?table=t",
+        ];
+        for source in allowed {
+            assert!(p.check_source(source).is_ok(), "{}", source);
+        }
+        let denied = [
+            "https://example.com.evil.com/a.csv",
+            "https://evil.com/example.com/a.csv",
+            "https://example.com@169.254.169.254/a.csv",
+            "https://internal.net/a.csv",
+            "http://127.0.0.1/a.csv",
+            "postgres://u:p/x@10.0.0.1/db?table=t",
+            "postgres://example.com/db?table=t&host=10.0.0.1",
+            "postgres://example.com/db?table=t&hostaddr=10.0.0.1",
+            "postgres://example.com/db?table=t&h%6fst=10.0.0.1",
+            "postgres:///db?table=t&host=/var/run/postgresql",
+            "custom://dataset",
+        ];
+        for source in denied {
+            let e = p.check_source(source).unwrap_err();
+            assert_eq!(ErrorKind::of(&e), ErrorKind::Forbidden, "{}", source);
+        }
+
+        // 空列表全部拒绝，`*` 全部允许，注册的表不受影响
+        let p = Permissions::read_only().with_hosts(Vec::<String>::new());
+        assert!(p.check_source("https://example.com/a.csv").is_err());
+        assert!(p.check_source("people").is_ok());
+        let p = Permissions::read_only().with_hosts(["*"]);
+        assert!(p.check_source("https://example.com/a.csv").is_ok());
+        assert!(Permissions::read_only()
+            .check_source("https://example.com/a.csv")
+            .is_ok());
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    fetcher::FetchHandler,
+    loader::LoadHandler,
+    udf::{ScalarFn, ScalarFunction},
+    DataSet,
+};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use polars::prelude::{DataFrame, DataType, Series};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+// 全局的注册表，应用在启动时把自己的数据源、格式和函数注册进来
+lazy_static! {
+    static ref REGISTRY: RwLock<Registry> = RwLock::new(Registry::default());
+}
+
+#[derive(Default)]
+struct Registry {
+    fetchers: HashMap<String, Arc<dyn FetchHandler>>,
+    loaders: Vec<(String, Arc<dyn LoadHandler>)>,
+    functions: HashMap<String, Arc<ScalarFunction>>,
+    tables: HashMap<String, DataFrame>,
+}
+
+/// 注册一个 scheme 的数据源，比如注册 "internal" 之后就可以
+/// `SELECT * FROM internal://dataset`。同名的 scheme 会被替换，包括内置的 http/file 等
+pub fn register_fetcher(scheme: impl Into<String>, handler: impl FetchHandler + 'static) {
+    let mut registry = REGISTRY.write().unwrap();
+    registry.fetchers.insert(scheme.into(), Arc::new(handler));
+}
+
+/// 注册一种数据格式，detect_content 会按注册顺序询问每个 loader，
+/// 都不认识的内容才交给内置的 csv loader。同名的 loader 会被替换
+pub fn register_loader(name: impl Into<String>, handler: impl LoadHandler + 'static) {
+    let name = name.into();
+    let mut registry = REGISTRY.write().unwrap();
+    let handler: Arc<dyn LoadHandler> = Arc::new(handler);
+    match registry.loaders.iter_mut().find(|(n, _)| *n == name) {
+        Some((_, v)) => *v = handler,
+        None => registry.loaders.push((name, handler)),
+    }
+}
+
+/// 注册一个标量函数，之后就可以在 SQL 里 `SELECT normalize(location) FROM ...`。
+/// 函数名不区分大小写，参数在调用前会转换成 args 声明的类型，返回值转换成 returns。
+/// 内置的函数（DATE_TRUNC 等）优先，同名的函数会被替换
+pub fn register_function<F>(name: impl Into<String>, args: Vec<DataType>, returns: DataType, f: F)
+where
+    F: Fn(&[Series]) -> Result<Series> + Send + Sync + 'static,
+{
+    let name = name.into().to_lowercase();
+    let f: Arc<ScalarFn> = Arc::new(f);
+    let function = ScalarFunction {
+        name: name.clone(),
+        args,
+        returns,
+        f,
+    };
+    let mut registry = REGISTRY.write().unwrap();
+    registry.functions.insert(name, Arc::new(function));
+}
+
+/// 把内存里的数据注册成表，之后就可以 `SELECT * FROM people WHERE ...`，
+/// 也可以和其它数据源 JOIN：`SELECT p.name, o.amount FROM people p JOIN file:///orders.csv o ON p.id = o.person_id`。
+/// 表名不区分大小写，查询时直接使用注册的数据，不会复制。同名的表会被替换
+pub fn register_table(name: impl Into<String>, data: impl Into<DataSet>) {
+    let name = name.into().to_lowercase();
+    let mut registry = REGISTRY.write().unwrap();
+    registry.tables.insert(name, data.into().0);
+}
+
+/// 注销 scheme 对应的数据源
+pub fn unregister_fetcher(scheme: &str) {
+    REGISTRY.write().unwrap().fetchers.remove(scheme);
+}
+
+/// 注销数据格式
+pub fn unregister_loader(name: &str) {
+    REGISTRY.write().unwrap().loaders.retain(|(n, _)| n != name);
+}
+
+/// 注销标量函数
+pub fn unregister_function(name: &str) {
+    REGISTRY
+        .write()
+        .unwrap()
+        .functions
+        .remove(&name.to_lowercase());
+}
+
+/// 注销内存表
+pub fn unregister_table(name: &str) {
+    REGISTRY
+        .write()
+        .unwrap()
+        .tables
+        .remove(&name.to_lowercase());
+}
+
+pub(crate) fn get_fetcher(scheme: &str) -> Option<Arc<dyn FetchHandler>> {
+    REGISTRY.read().unwrap().fetchers.get(scheme).cloned()
+}
+
+pub(crate) fn get_loaders() -> Vec<Arc<dyn LoadHandler>> {
+    let registry = REGISTRY.read().unwrap();
+    registry.loaders.iter().map(|(_, v)| v.clone()).collect()
+}
+
+pub(crate) fn get_function(name: &str) -> Option<Arc<ScalarFunction>> {
+    REGISTRY.read().unwrap().functions.get(name).cloned()
+}
+
+/// DataFrame 的列是引用计数的，clone 不会复制数据
+pub(crate) fn get_table(name: &str) -> Option<DataFrame> {
+    REGISTRY
+        .read()
+        .unwrap()
+        .tables
+        .get(&name.to_lowercase())
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataSet, Session};
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use polars::prelude::*;
+
+    struct MemFetcher;
+
+    #[async_trait]
+    impl FetchHandler for MemFetcher {
+        async fn fetch(&self, source: &str, _session: &Session) -> Result<String> {
+            assert!(source.ends_with("://people"));
+            Ok("#people\nTyr,18\nLindsey,30\n".into())
+        }
+    }
+
+    // 以 #people 开头的内容
+    struct PeopleLoader;
+
+    impl LoadHandler for PeopleLoader {
+        fn detect(&self, data: &str) -> bool {
+            data.starts_with("#people")
+        }
+
+        fn load(&self, data: String) -> Result<DataSet> {
+            let (names, ages): (Vec<_>, Vec<_>) = data
+                .lines()
+                .skip(1)
+                .filter_map(|line| line.split_once(','))
+                .map(|(name, age)| (name.to_owned(), age.parse::<i64>().unwrap()))
+                .unzip();
+            let df = DataFrame::new(vec![Series::new("name", names), Series::new("age", ages)])?;
+            Ok(df.into())
+        }
+    }
+
+    #[tokio::test]
+    async fn registered_fetcher_and_loader_works() {
+        // 测试是并行跑的，注册表是全局的，每个测试用自己的 scheme 和格式名字
+        register_fetcher("mem_fetch", MemFetcher);
+        register_loader("people_fetch", PeopleLoader);
+
+        let sql = "SELECT name FROM mem_fetch://people WHERE age > 20";
+        let ds = Session::default().query(sql).await.unwrap();
+        assert_eq!(ds.to_csv().unwrap(), "name\nLindsey\n");
+
+        unregister_fetcher("mem_fetch");
+        unregister_loader("people_fetch");
+        assert!(Session::default().query(sql).await.is_err());
+    }
+
+    // 只能按字节读取的数据源，比如返回电子表格的服务
+    struct BytesFetcher;
+
+    #[async_trait]
+    impl FetchHandler for BytesFetcher {
+        async fn fetch(&self, source: &str, _session: &Session) -> Result<String> {
+            Err(anyhow::anyhow!("{} is binary", source))
+        }
+
+        async fn fetch_bytes(&self, _source: &str, _session: &Session) -> Result<Vec<u8>> {
+            Ok(b"name,age\nTyr,18\nLindsey,30\n".to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn registered_bytes_fetcher_works() {
+        register_fetcher("mem_bytes", BytesFetcher);
+        // 电子表格的扩展名按字节读取，内容不是电子表格时还是按文本处理
+        let sql = "SELECT name FROM mem_bytes://people.xlsx WHERE age > 20";
+        let ds = Session::default().query(sql).await.unwrap();
+        assert_eq!(ds.to_csv().unwrap(), "name\nLindsey\n");
+        unregister_fetcher("mem_bytes");
+    }
+
+    #[tokio::test]
+    async fn registered_function_works() {
+        register_fetcher("mem_udf", MemFetcher);
+        register_loader("people_udf", PeopleLoader);
+        register_function("SHOUT", vec![DataType::Utf8], DataType::Utf8, |s| {
+            let ca: Utf8Chunked = s[0]
+                .utf8()?
+                .into_iter()
+                .map(|v| v.map(|v| v.to_uppercase()))
+                .collect();
+            Ok(ca.into_series())
+        });
+
+        let sql = "SELECT shout(name) AS name FROM mem_udf://people WHERE age > 20";
+        let ds = Session::default().query(sql).await.unwrap();
+        assert_eq!(ds.to_csv().unwrap(), "name\nLINDSEY\n");
+
+        unregister_function("shout");
+        assert!(Session::default().query(sql).await.is_err());
+        unregister_fetcher("mem_udf");
+        unregister_loader("people_udf");
+    }
+
+    #[tokio::test]
+    async fn registered_table_works() {
+        let df = DataFrame::new(vec![
+            Series::new("name", &["Tyr", "Lindsey", "Ada"]),
+            Series::new("age", &[18i64, 30, 36]),
+        ])
+        .unwrap();
+        register_table("Staff", df);
+
+        let sql = "SELECT name FROM staff WHERE age > 20 ORDER BY age DESC LIMIT 1";
+        let ds = Session::default().query(sql).await.unwrap();
+        assert_eq!(ds.to_csv().unwrap(), "name\nAda\n");
+
+        unregister_table("STAFF");
+        assert!(Session::default().query(sql).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn join_registered_table_works() {
+        let df = DataFrame::new(vec![
+            Series::new("name", &["Tyr", "Lindsey", "Ada"]),
+            Series::new("dept_id", &[1i64, 3, 2]),
+        ])
+        .unwrap();
+        register_table("staff_join", df);
+        let path = std::env::temp_dir().join("queryer_join_depts.csv");
+        std::fs::write(&path, "id,dept\n1,eng\n2,ops\n").unwrap();
+
+        let sql = format!(
+            "SELECT s.name, d.dept FROM staff_join AS s JOIN file://{} AS d ON s.dept_id = d.id WHERE d.dept = 'eng'",
+            path.display()
+        );
+        let ds = Session::default().query(sql).await.unwrap();
+        assert_eq!(ds.to_csv().unwrap(), "name,dept\nTyr,eng\n");
+
+        // LEFT JOIN 保留没有部门的 Lindsey，连接条件两边的顺序可以反过来写
+        let sql = format!(
+            "SELECT s.name, dept FROM staff_join s LEFT JOIN file://{} d ON d.id = s.dept_id ORDER BY s.name",
+            path.display()
+        );
+        let ds = Session::default().query(sql).await.unwrap();
+        assert_eq!(
+            ds.to_csv().unwrap(),
+            "name,dept\nAda,ops\nLindsey,\nTyr,eng\n"
+        );
+
+        // 两边都有 id 列，不带表名引用时报错
+        let sql = format!(
+            "SELECT s.name, id FROM staff_join s JOIN file://{} d ON d.id = s.dept_id",
+            path.display()
+        );
+        let mut df = get_table("staff_join").unwrap();
+        df.with_column(Series::new("id", &[7i64, 8, 9])).unwrap();
+        register_table("staff_join", df);
+        let e = Session::default().query(sql).await.unwrap_err();
+        assert!(e.to_string().starts_with("column id is ambiguous"));
+
+        unregister_table("staff_join");
+    }
+}
@@ -0,0 +1,326 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::command::{is_keyword, matching_paren};
+use crate::error::unsupported;
+use anyhow::{anyhow, Result};
+use polars::prelude::*;
+use sqlparser::tokenizer::Token;
+
+/// FROM 后面的 PIVOT / UNPIVOT 子句，在 WHERE 之前作用于整个数据源
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Reshape {
+    /// UNPIVOT (value FOR metric IN (new_cases, new_deaths))
+    Unpivot {
+        value: String,
+        name: String,
+        columns: Vec<String>,
+    },
+    /// PIVOT (SUM(new_cases) FOR continent IN ('Asia', 'Europe'))
+    Pivot {
+        agg: String,
+        value: String,
+        column: String,
+        values: Vec<String>,
+    },
+}
+
+/// 从 token 里找到 PIVOT / UNPIVOT 子句，把它摘出来，剩下的 token 交给 sqlparser
+pub(crate) fn extract_reshape(mut tokens: Vec<Token>) -> Result<(Vec<Token>, Option<Reshape>)> {
+    let pos = tokens.windows(2).position(|w| {
+        (is_keyword(&w[0], "PIVOT") || is_keyword(&w[0], "UNPIVOT")) && w[1] == Token::LParen
+    });
+    let pos = match pos {
+        Some(v) => v,
+        None => return Ok((tokens, None)),
+    };
+
+    let end = pos + 1 + matching_paren(&tokens[pos + 1..])?;
+    let clause: Vec<Token> = tokens.drain(pos..=end).collect();
+    let inner = &clause[2..clause.len() - 1];
+    let reshape = match is_keyword(&clause[0], "UNPIVOT") {
+        true => parse_unpivot(inner)?,
+        false => parse_pivot(inner)?,
+    };
+
+    if tokens
+        .iter()
+        .any(|t| is_keyword(t, "PIVOT") || is_keyword(t, "UNPIVOT"))
+    {
+        return Err(unsupported!("Only one PIVOT / UNPIVOT is supported"));
+    }
+    Ok((tokens, Some(reshape)))
+}
+
+fn parse_unpivot(tokens: &[Token]) -> Result<Reshape> {
+    match tokens {
+        [value, for_, name, in_, Token::LParen, list @ .., Token::RParen]
+            if is_keyword(for_, "FOR") && is_keyword(in_, "IN") =>
+        {
+            Ok(Reshape::Unpivot {
+                value: ident(value)?,
+                name: ident(name)?,
+                columns: list_values(list)?,
+            })
+        }
+        _ => Err(anyhow!(
+            "Expected UNPIVOT (value FOR name IN (col1, col2, ...))"
+        )),
+    }
+}
+
+fn parse_pivot(tokens: &[Token]) -> Result<Reshape> {
+    match tokens {
+        [agg, Token::LParen, value, Token::RParen, for_, column, in_, Token::LParen, list @ .., Token::RParen]
+            if is_keyword(for_, "FOR") && is_keyword(in_, "IN") =>
+        {
+            Ok(Reshape::Pivot {
+                agg: ident(agg)?.to_lowercase(),
+                value: ident(value)?,
+                column: ident(column)?,
+                values: list_values(list)?,
+            })
+        }
+        _ => Err(anyhow!(
+            "Expected PIVOT (AGG(value) FOR column IN (v1, v2, ...))"
+        )),
+    }
+}
+
+fn ident(token: &Token) -> Result<String> {
+    match token {
+        Token::Word(w) => Ok(w.value.clone()),
+        t => Err(anyhow!("Expected identifier, found {}", t)),
+    }
+}
+
+/// 逗号分隔的列名或者值
+fn list_values(tokens: &[Token]) -> Result<Vec<String>> {
+    let values = tokens
+        .split(|t| *t == Token::Comma)
+        .map(|v| match v {
+            [Token::Word(w)] => Ok(w.value.clone()),
+            [Token::SingleQuotedString(s)] => Ok(s.clone()),
+            [Token::Number(n, _)] => Ok(n.clone()),
+            v => Err(anyhow!("Expected a single value in IN list, found {:?}", v)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if values.is_empty() {
+        return Err(anyhow!("IN list should not be empty"));
+    }
+    Ok(values)
+}
+
+impl Reshape {
+    pub(crate) fn apply(&self, df: DataFrame) -> Result<DataFrame> {
+        match self {
+            Self::Unpivot {
+                value,
+                name,
+                columns,
+            } => unpivot(df, value, name, columns),
+            Self::Pivot {
+                agg,
+                value,
+                column,
+                values,
+            } => pivot(df, agg, value, column, values),
+        }
+    }
+}
+
+/// 宽表转长表，对应 polars 的 melt
+fn unpivot(mut df: DataFrame, value: &str, name: &str, columns: &[String]) -> Result<DataFrame> {
+    // melt 要求所有值列的类型一致
+    let dtypes: Vec<DataType> = columns
+        .iter()
+        .map(|c| df.column(c).map(|s| s.dtype().clone()))
+        .collect::<polars::prelude::Result<_>>()?;
+    if dtypes.windows(2).any(|w| w[0] != w[1]) {
+        let dtype = match dtypes.iter().all(is_numeric) {
+            true => DataType::Float64,
+            false => DataType::Utf8,
+        };
+        for c in columns {
+            let series = df.column(c)?.cast_with_dtype(&dtype)?;
+            df.with_column(series)?;
+        }
+    }
+
+    let ids: Vec<&str> = df
+        .get_column_names()
+        .into_iter()
+        .filter(|c| !columns.iter().any(|v| v == c))
+        .collect();
+    let columns: Vec<&str> = columns.iter().map(|v| v.as_str()).collect();
+    let mut df = df.melt(ids, columns)?;
+    df.rename("variable", name)?;
+    df.rename("value", value)?;
+
+    // 和 SQL 的默认行为一致，去掉值为 null 的行
+    let mask = df.column(value)?.is_not_null();
+    Ok(df.filter(&mask)?)
+}
+
+/// 长表转宽表，其它列作为分组的 key
+fn pivot(
+    df: DataFrame,
+    agg: &str,
+    value: &str,
+    column: &str,
+    values: &[String],
+) -> Result<DataFrame> {
+    let keys: Vec<&str> = df
+        .get_column_names()
+        .into_iter()
+        .filter(|c| *c != value && *c != column)
+        .collect();
+    if keys.is_empty() {
+        return Err(anyhow!("PIVOT needs at least one other column to group by"));
+    }
+
+    // 只保留 IN 列表里的值
+    let pivot_values = df.column(column)?.cast_with_dtype(&DataType::Utf8)?;
+    let mask: BooleanChunked = pivot_values
+        .utf8()?
+        .into_iter()
+        .map(|v| v.map(|v| values.iter().any(|x| x == v)).unwrap_or(false))
+        .collect();
+    let mut df = df.filter(&mask)?;
+    df.with_column(pivot_values.filter(&mask)?)?;
+
+    let mut gb = df.groupby(keys.clone())?;
+    let pivot = gb.pivot(column, value);
+    let mut result = match agg {
+        "sum" => pivot.sum()?,
+        "min" => pivot.min()?,
+        "max" => pivot.max()?,
+        "avg" | "mean" => pivot.mean()?,
+        "median" => pivot.median()?,
+        "count" => pivot.count()?,
+        "first" => pivot.first()?,
+        v => return Err(unsupported!("PIVOT aggregate {} is not supported", v)),
+    };
+
+    // IN 列表里的值在数据里没出现时补一列 null，最后按 IN 列表的顺序输出
+    let height = result.height();
+    for v in values {
+        if result.column(v).is_err() {
+            result.with_column(Float64Chunked::full_null(v, height).into_series())?;
+        }
+    }
+    let columns: Vec<&str> = keys
+        .into_iter()
+        .chain(values.iter().map(|v| v.as_str()))
+        .collect();
+    Ok(result.select(columns)?)
+}
+
+fn is_numeric(dtype: &DataType) -> bool {
+    matches!(
+        dtype,
+        DataType::UInt32
+            | DataType::UInt64
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::Float32
+            | DataType::Float64
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TyrDialect;
+    use sqlparser::tokenizer::Tokenizer;
+
+    fn tokens(sql: &str) -> Vec<Token> {
+        Tokenizer::new(&TyrDialect, sql)
+            .tokenize()
+            .unwrap()
+            .into_iter()
+            .filter(|t| !matches!(t, Token::Whitespace(_)))
+            .collect()
+    }
+
+    fn covid() -> DataFrame {
+        DataFrame::new(vec![
+            Series::new("location", &["China", "Italy"]),
+            Series::new("new_cases", &[10i64, 20]),
+            Series::new("new_deaths", &[Some(1.0), None]),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn extract_reshape_works() {
+        let sql = "SELECT * FROM file:///tmp/a.csv UNPIVOT (value FOR metric IN (new_cases, new_deaths)) WHERE value > 1";
+        let (rest, reshape) = extract_reshape(tokens(sql)).unwrap();
+        assert_eq!(
+            reshape,
+            Some(Reshape::Unpivot {
+                value: "value".into(),
+                name: "metric".into(),
+                columns: vec!["new_cases".into(), "new_deaths".into()],
+            })
+        );
+        assert_eq!(
+            rest.iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+            "SELECT * FROM file:///tmp/a.csv WHERE value > 1"
+        );
+
+        let sql = "SELECT * FROM t PIVOT (SUM(new_cases) FOR location IN ('China', 'Italy'))";
+        let (_, reshape) = extract_reshape(tokens(sql)).unwrap();
+        assert!(matches!(reshape, Some(Reshape::Pivot { agg, .. }) if agg == "sum"));
+
+        let sql = "SELECT * FROM t PIVOT (SUM(new_cases) FOR location)";
+        assert!(extract_reshape(tokens(sql)).is_err());
+    }
+
+    #[test]
+    fn unpivot_works() {
+        let df = unpivot(
+            covid(),
+            "value",
+            "metric",
+            &["new_cases".into(), "new_deaths".into()],
+        )
+        .unwrap();
+        assert_eq!(df.get_column_names(), vec!["location", "metric", "value"]);
+        // Italy 的 new_deaths 是 null，被去掉了
+        assert_eq!(df.height(), 3);
+        assert_eq!(df.column("value").unwrap().dtype(), &DataType::Float64);
+    }
+
+    #[test]
+    fn pivot_works() {
+        let df = unpivot(
+            covid(),
+            "value",
+            "metric",
+            &["new_cases".into(), "new_deaths".into()],
+        )
+        .unwrap();
+        let values = vec!["new_cases".into(), "new_deaths".into(), "icu".into()];
+        let df = pivot(df, "sum", "value", "metric", &values).unwrap();
+        assert_eq!(
+            df.get_column_names(),
+            vec!["location", "new_cases", "new_deaths", "icu"]
+        );
+        assert_eq!(df.height(), 2);
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, Result};
+use polars::prelude::*;
+use std::collections::HashMap;
+
+/// 把查询里引用的列名对应到数据里真正的列上：
+/// - `alias.col` 去掉表别名
+/// - case_insensitive 时忽略大小写
+///
+/// 对应上的列改成查询里的写法，这样转换好的 polars 表达式不用再改。
+/// 找不到的列直接报错，polars 遇到不存在的列会 panic
+pub(crate) fn resolve_columns(
+    df: DataFrame,
+    columns: &[String],
+    alias: Option<&str>,
+    case_insensitive: bool,
+) -> Result<DataFrame> {
+    let df = rename_columns(df, columns, alias, case_insensitive)?;
+    match columns
+        .iter()
+        .find(|name| *name != "*" && df.column(name).is_err())
+    {
+        Some(name) => Err(anyhow!(
+            "column {} not found, available columns: {}",
+            name,
+            df.get_column_names().join(", ")
+        )),
+        None => Ok(df),
+    }
+}
+
+/// 和 resolve_columns 一样改列名，但是找不到的列不报错：
+/// 有 JOIN 时每个数据源只有查询里的一部分列，连接之后再用 resolve_columns 检查
+pub(crate) fn rename_columns(
+    mut df: DataFrame,
+    columns: &[String],
+    alias: Option<&str>,
+    case_insensitive: bool,
+) -> Result<DataFrame> {
+    let originals: Vec<String> = df
+        .get_column_names()
+        .iter()
+        .map(|v| v.to_string())
+        .collect();
+    // 原来的列名 -> 改过之后的列名
+    let mut renamed: HashMap<&str, &str> = HashMap::new();
+
+    for name in columns {
+        if name == "*" || originals.contains(name) {
+            continue;
+        }
+
+        let unqualified = unqualify(name, alias, case_insensitive);
+        let found = match find_column(&originals, unqualified, case_insensitive)? {
+            Some(v) => v,
+            None => continue,
+        };
+        match renamed.get(found) {
+            // 同一列被不同写法引用了多次，或者查询里也直接引用了原来的列名，就复制一份
+            Some(current) => {
+                let mut series = df.column(current)?.clone();
+                series.rename(name);
+                df.with_column(series)?;
+            }
+            None if columns.iter().any(|v| v == found) => {
+                let mut series = df.column(found)?.clone();
+                series.rename(name);
+                df.with_column(series)?;
+            }
+            None => {
+                df.rename(found, name)?;
+                renamed.insert(found, name);
+            }
+        }
+    }
+    Ok(df)
+}
+
+/// 结果里 `alias.col` 这样的列名去掉表别名，和 SQL 的习惯一致
+/// 有 JOIN 时 aliases 里有多个表名，去掉之后和别的列重名的就保留原样
+pub(crate) fn strip_alias(mut df: DataFrame, aliases: &[String]) -> Result<DataFrame> {
+    if aliases.is_empty() {
+        return Ok(df);
+    }
+
+    let names: Vec<String> = df
+        .get_column_names()
+        .iter()
+        .map(|v| v.to_string())
+        .collect();
+    for name in names {
+        let unqualified = aliases
+            .iter()
+            .map(|alias| unqualify(&name, Some(alias), false))
+            .find(|v| *v != name);
+        if let Some(unqualified) = unqualified {
+            if df.column(unqualified).is_err() {
+                df.rename(&name, unqualified)?;
+            }
+        }
+    }
+    Ok(df)
+}
+
+fn unqualify<'a>(name: &'a str, alias: Option<&str>, case_insensitive: bool) -> &'a str {
+    let alias = match alias {
+        Some(v) => v,
+        None => return name,
+    };
+    match (name.get(..alias.len()), name.get(alias.len()..)) {
+        (Some(prefix), Some(rest)) if rest.starts_with('.') => {
+            let matched = match case_insensitive {
+                true => prefix.to_lowercase() == alias.to_lowercase(),
+                false => prefix == alias,
+            };
+            if matched {
+                &rest[1..]
+            } else {
+                name
+            }
+        }
+        _ => name,
+    }
+}
+
+fn find_column<'a>(
+    names: &'a [String],
+    name: &str,
+    case_insensitive: bool,
+) -> Result<Option<&'a str>> {
+    if let Some(v) = names.iter().find(|v| *v == name) {
+        return Ok(Some(v));
+    }
+    if !case_insensitive {
+        return Ok(None);
+    }
+
+    let lower = name.to_lowercase();
+    let matched: Vec<&str> = names
+        .iter()
+        .filter(|v| v.to_lowercase() == lower)
+        .map(|v| v.as_str())
+        .collect();
+    match matched.as_slice() {
+        [] => Ok(None),
+        [v] => Ok(Some(v)),
+        _ => Err(anyhow!("column {} is ambiguous: {:?}", name, matched)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn people() -> DataFrame {
+        DataFrame::new(vec![
+            Series::new("Name", &["Tyr", "Lindsey"]),
+            Series::new("Total Cases", &[1i64, 2]),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn resolve_columns_works() {
+        let columns = vec!["t.Name".to_owned(), "total cases".to_owned()];
+        // 区分大小写时 total cases 对应不上
+        assert!(resolve_columns(people(), &columns, Some("t"), false).is_err());
+        let df = rename_columns(people(), &columns, Some("t"), false).unwrap();
+        assert!(df.column("t.Name").is_ok());
+        assert!(df.column("total cases").is_err());
+
+        let df = resolve_columns(people(), &columns, Some("T"), true).unwrap();
+        assert_eq!(df.get_column_names(), vec!["t.Name", "total cases"]);
+
+        // 同一列用两种写法引用时复制一份
+        let columns = vec!["Name".to_owned(), "t.Name".to_owned()];
+        let df = resolve_columns(people(), &columns, Some("t"), false).unwrap();
+        assert_eq!(df.get_column_names(), vec!["Name", "Total Cases", "t.Name"]);
+    }
+
+    #[test]
+    fn unknown_column_should_fail() {
+        let columns = vec!["name".to_owned()];
+        let e = resolve_columns(people(), &columns, None, false).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "column name not found, available columns: Name, Total Cases"
+        );
+        // JOIN 的一边找不到的列留到连接之后检查
+        let df = rename_columns(people(), &columns, None, false).unwrap();
+        assert_eq!(df.get_column_names(), vec!["Name", "Total Cases"]);
+    }
+
+    #[test]
+    fn ambiguous_column_should_fail() {
+        let mut df = people();
+        df.with_column(Series::new("NAME", &["a", "b"])).unwrap();
+        let columns = vec!["name".to_owned()];
+        assert!(resolve_columns(df, &columns, None, true).is_err());
+    }
+
+    #[test]
+    fn strip_alias_works() {
+        let mut df = people();
+        df.rename("Name", "t.Name").unwrap();
+        let df = strip_alias(df, &["t".to_owned()]).unwrap();
+        assert_eq!(df.get_column_names(), vec!["Name", "Total Cases"]);
+
+        // JOIN 的两边都有 name 时，第二个保留表名
+        let df = DataFrame::new(vec![
+            Series::new("p.name", &["Tyr"]),
+            Series::new("o.name", &["book"]),
+        ])
+        .unwrap();
+        let df = strip_alias(df, &["p".to_owned(), "o".to_owned()]).unwrap();
+        assert_eq!(df.get_column_names(), vec!["name", "o.name"]);
+    }
+}
@@ -0,0 +1,261 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::command::{is_keyword, matching_paren};
+use anyhow::{anyhow, Result};
+use polars::prelude::*;
+use rand::{rngs::StdRng, seq::index, Rng, SeedableRng};
+use sqlparser::tokenizer::Token;
+
+/// 对数据源抽样，在加载数据之后、其它处理之前执行
+/// - TABLESAMPLE BERNOULLI(1) [REPEATABLE(42)]: 每行按 1% 的概率保留
+/// - SAMPLE 1000 ROWS [REPEATABLE(42)]: 随机取 1000 行，数据不够时全部保留
+/// - SAMPLE 10 PERCENT [REPEATABLE(42)]: 同 BERNOULLI(10)
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Sample {
+    method: SampleMethod,
+    // 同样的 seed 抽出同样的行
+    seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SampleMethod {
+    Percent(f64),
+    Rows(usize),
+}
+
+/// 从 token 里找到 TABLESAMPLE / SAMPLE 子句，把它摘出来，剩下的 token 交给 sqlparser
+pub(crate) fn extract_sample(mut tokens: Vec<Token>) -> Result<(Vec<Token>, Option<Sample>)> {
+    let found = tokens.iter().enumerate().find_map(|(i, t)| {
+        match (t, tokens.get(i + 1), tokens.get(i + 2)) {
+            (t, Some(Token::Word(_)), Some(Token::LParen)) if is_keyword(t, "TABLESAMPLE") => {
+                Some(i)
+            }
+            (t, Some(Token::Number(..)), Some(unit))
+                if is_keyword(t, "SAMPLE")
+                    && (is_keyword(unit, "ROWS") || is_keyword(unit, "PERCENT")) =>
+            {
+                Some(i)
+            }
+            _ => None,
+        }
+    });
+    let start = match found {
+        Some(v) => v,
+        None => return Ok((tokens, None)),
+    };
+
+    let (method, mut end) = match is_keyword(&tokens[start], "TABLESAMPLE") {
+        true => {
+            let close = start + 2 + matching_paren(&tokens[start + 2..])?;
+            let percent = match (&tokens[start + 1], &tokens[start + 3..close]) {
+                (m, [Token::Number(n, _)])
+                    if is_keyword(m, "BERNOULLI") || is_keyword(m, "SYSTEM") =>
+                {
+                    number(n)?
+                }
+                (m, _) => {
+                    return Err(anyhow!(
+                        "Expected TABLESAMPLE BERNOULLI(percent), found {} method",
+                        m
+                    ))
+                }
+            };
+            (SampleMethod::Percent(percent), close + 1)
+        }
+        false => {
+            let n = match &tokens[start + 1] {
+                Token::Number(n, _) => n,
+                _ => unreachable!(),
+            };
+            let method = match is_keyword(&tokens[start + 2], "ROWS") {
+                true => SampleMethod::Rows(
+                    n.parse()
+                        .map_err(|_| anyhow!("SAMPLE rows should be an integer, got {}", n))?,
+                ),
+                false => SampleMethod::Percent(number(n)?),
+            };
+            (method, start + 3)
+        }
+    };
+    if let SampleMethod::Percent(v) = method {
+        if !(0.0..=100.0).contains(&v) {
+            return Err(anyhow!("Sample percent should be in [0, 100], got {}", v));
+        }
+    }
+
+    // 可选的 REPEATABLE (seed)
+    let seed = match tokens.get(end..end + 4) {
+        Some([r, Token::LParen, Token::Number(n, _), Token::RParen])
+            if is_keyword(r, "REPEATABLE") =>
+        {
+            let seed = n
+                .parse()
+                .map_err(|_| anyhow!("REPEATABLE seed should be an integer, got {}", n))?;
+            end += 4;
+            Some(seed)
+        }
+        _ => None,
+    };
+
+    tokens.drain(start..end);
+    Ok((tokens, Some(Sample { method, seed })))
+}
+
+fn number(n: &str) -> Result<f64> {
+    n.parse()
+        .map_err(|_| anyhow!("Expected a number, got {}", n))
+}
+
+impl Sample {
+    pub(crate) fn apply(&self, df: DataFrame) -> Result<DataFrame> {
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        let height = df.height();
+        match self.method {
+            SampleMethod::Percent(percent) => {
+                let p = percent / 100.0;
+                let mask: BooleanChunked = (0..height).map(|_| rng.gen::<f64>() < p).collect();
+                Ok(df.filter(&mask)?)
+            }
+            SampleMethod::Rows(n) if n >= height => Ok(df),
+            SampleMethod::Rows(n) => {
+                // 保持原来的行顺序
+                let mut idx = index::sample(&mut rng, height, n).into_vec();
+                idx.sort_unstable();
+                let idx: NoNull<UInt32Chunked> = idx.into_iter().map(|v| v as u32).collect();
+                Ok(df.take(&idx.into_inner())?)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TyrDialect;
+    use sqlparser::tokenizer::Tokenizer;
+
+    fn tokens(sql: &str) -> Vec<Token> {
+        Tokenizer::new(&TyrDialect, sql)
+            .tokenize()
+            .unwrap()
+            .into_iter()
+            .filter(|t| !matches!(t, Token::Whitespace(_)))
+            .collect()
+    }
+
+    fn numbers() -> DataFrame {
+        let values: Vec<i64> = (0..1000).collect();
+        DataFrame::new(vec![Series::new("n", values)]).unwrap()
+    }
+
+    #[test]
+    fn extract_sample_works() {
+        let sql = "SELECT a FROM t TABLESAMPLE BERNOULLI(1.5) REPEATABLE(42) WHERE a > 1";
+        let (rest, sample) = extract_sample(tokens(sql)).unwrap();
+        assert_eq!(
+            sample,
+            Some(Sample {
+                method: SampleMethod::Percent(1.5),
+                seed: Some(42)
+            })
+        );
+        assert_eq!(
+            rest.iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+            "SELECT a FROM t WHERE a > 1"
+        );
+
+        let sql = "SELECT sample FROM t SAMPLE 100 ROWS";
+        let (_, sample) = extract_sample(tokens(sql)).unwrap();
+        assert_eq!(
+            sample,
+            Some(Sample {
+                method: SampleMethod::Rows(100),
+                seed: None
+            })
+        );
+
+        let sql = "SELECT a FROM t TABLESAMPLE BERNOULLI(120)";
+        assert!(extract_sample(tokens(sql)).is_err());
+    }
+
+    #[test]
+    fn sample_rows_works() {
+        let sample = Sample {
+            method: SampleMethod::Rows(10),
+            seed: Some(7),
+        };
+        let a = sample.apply(numbers()).unwrap();
+        let b = sample.apply(numbers()).unwrap();
+        assert_eq!(a.height(), 10);
+        assert!(a.frame_equal(&b));
+
+        let values: Vec<i64> = a
+            .column("n")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert!(values.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn sample_percent_works() {
+        let sample = Sample {
+            method: SampleMethod::Percent(10.0),
+            seed: Some(7),
+        };
+        let height = sample.apply(numbers()).unwrap().height();
+        assert!(height > 50 && height < 150);
+    }
+
+    #[tokio::test]
+    async fn sample_before_filter_works() {
+        // 同样的数据放在 csv 文件和 sqlite 里，抽样的结果应该一样
+        let dir = std::env::temp_dir();
+        let csv = dir.join("queryer_sample_numbers.csv");
+        let db = dir.join("queryer_sample_numbers.db");
+        let values: Vec<String> = (0..1000).map(|v| v.to_string()).collect();
+        std::fs::write(&csv, format!("n\n{}\n", values.join("\n"))).unwrap();
+        let _ = std::fs::remove_file(&db);
+        let conn = rusqlite::Connection::open(&db).unwrap();
+        let rows: Vec<String> = values.iter().map(|v| format!("({})", v)).collect();
+        conn.execute_batch(&format!(
+            "CREATE TABLE numbers (n INTEGER); INSERT INTO numbers VALUES {};",
+            rows.join(", ")
+        ))
+        .unwrap();
+
+        let query = |source: String| async move {
+            let sql = format!(
+                "SELECT n FROM {} SAMPLE 100 ROWS REPEATABLE(7) WHERE n >= 500",
+                source
+            );
+            crate::Session::default().query(sql).await.unwrap()
+        };
+        let from_csv = query(format!("file://{}", csv.display())).await;
+        let from_sqlite = query(format!("sqlite://{}?table=numbers", db.display())).await;
+        // 先抽样再过滤，大约剩一半
+        assert!(from_csv.height() < 100);
+        assert!(from_csv.frame_equal(&from_sqlite));
+    }
+}
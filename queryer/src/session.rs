@@ -0,0 +1,239 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    execute, execute_incremental, settings::Settings, stream::execute_stream, DataSet, DialectMode,
+    HttpConfig, Param, Permissions, QueryStats, Watermark, DEFAULT_DATE_FORMATS,
+};
+use anyhow::Result;
+use futures::Stream;
+use tracing::{info, info_span, Instrument};
+
+/// 一次查询会话，保存查询时用到的配置
+/// 同一个 Session 可以反复执行多条 SQL
+#[derive(Debug, Clone)]
+pub struct Session {
+    // 所有 url 数据源共用的 http 配置
+    pub(crate) http: HttpConfig,
+    // 按 url 前缀匹配的 http 配置，会覆盖上面的公共配置
+    pub(crate) source_http: Vec<(String, HttpConfig)>,
+    // 识别日期列时依次尝试的格式，为空时不做识别
+    pub(crate) date_formats: Vec<String>,
+    // SQL 里 `$1` / `$name` 参数的值，位置参数的名字是 "1"、"2" ...
+    pub(crate) params: Vec<(String, Param)>,
+    // 列名是否忽略大小写，ANSI / MySQL 方言下总是忽略
+    pub(crate) case_insensitive: bool,
+    // SQL 方言
+    pub(crate) dialect: DialectMode,
+    // SET 语句修改的选项，克隆出来的 Session 共享同一份
+    pub(crate) settings: Settings,
+    // 允许执行的操作
+    pub(crate) permissions: Permissions,
+    // 顶层的 query() 每次临时创建的 Session，SET 修改的选项查询完就丢了，所以不允许 SET / SHOW
+    pub(crate) transient: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            http: HttpConfig::default(),
+            source_http: Vec::new(),
+            date_formats: DEFAULT_DATE_FORMATS.iter().map(|v| v.to_string()).collect(),
+            params: Vec::new(),
+            case_insensitive: false,
+            dialect: DialectMode::default(),
+            settings: Settings::default(),
+            permissions: Permissions::default(),
+            transient: false,
+        }
+    }
+}
+
+impl Session {
+    /// 从环境变量生成 Session，`query()` 用的就是它
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            http: HttpConfig::from_env()?,
+            ..Default::default()
+        })
+    }
+
+    /// 设置所有 url 数据源共用的 http 配置
+    pub fn with_http(mut self, config: HttpConfig) -> Self {
+        self.http = self.http.merge(&config);
+        self
+    }
+
+    /// 设置以 prefix 开头的 url 数据源的 http 配置
+    pub fn with_source_http(mut self, prefix: impl Into<String>, config: HttpConfig) -> Self {
+        self.source_http.push((prefix.into(), config));
+        self
+    }
+
+    /// 设置识别日期列时使用的格式（chrono 的格式语法），传空列表可以关闭识别
+    pub fn with_date_formats<T: Into<String>>(
+        mut self,
+        formats: impl IntoIterator<Item = T>,
+    ) -> Self {
+        self.date_formats = formats.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// 列名忽略大小写，比如 `SELECT location` 可以对应到数据里的 `Location` 列
+    pub fn with_case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// 设置 SQL 方言，默认是 [DialectMode::Tyr]
+    pub fn with_dialect(mut self, dialect: DialectMode) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// 限制这个会话能执行的操作，比如对外服务时不允许写文件和读取本机文件
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// 绑定命名参数 `$name`
+    pub fn with_param(mut self, name: impl Into<String>, value: impl Into<Param>) -> Self {
+        self.params.push((name.into(), value.into()));
+        self
+    }
+
+    /// 按顺序绑定位置参数 `$1`、`$2` ...
+    pub fn with_params<T: Into<Param>>(mut self, values: impl IntoIterator<Item = T>) -> Self {
+        for (i, v) in values.into_iter().enumerate() {
+            self.params.push(((i + 1).to_string(), v.into()));
+        }
+        self
+    }
+
+    /// 找到 url 对应的 http 配置，多个前缀匹配时按添加顺序依次覆盖，SET http.timeout 最优先
+    pub fn http_config(&self, url: &str) -> HttpConfig {
+        self.source_http
+            .iter()
+            .filter(|(prefix, _)| url.starts_with(prefix.as_str()))
+            .fold(self.http.clone(), |acc, (_, config)| acc.merge(config))
+            .merge(&HttpConfig {
+                timeout: self.settings.options().http_timeout,
+                ..Default::default()
+            })
+    }
+
+    /// 列名是否忽略大小写：SET case_sensitive 优先，其次是 Session 的配置和方言
+    pub(crate) fn ignore_case(&self) -> bool {
+        match self.settings.options().case_sensitive {
+            Some(v) => !v,
+            None => self.case_insensitive || self.dialect.case_insensitive(),
+        }
+    }
+
+    /// 在这个会话里执行 SQL
+    pub async fn query<T: AsRef<str>>(&self, sql: T) -> Result<DataSet> {
+        Ok(self.query_with_stats(sql).await?.0)
+    }
+
+    /// 执行 SQL，同时返回各个阶段的耗时和数据量
+    /// 每个阶段（parse / fetch / load / execute）也会有对应的 tracing span
+    pub async fn query_with_stats<T: AsRef<str>>(&self, sql: T) -> Result<(DataSet, QueryStats)> {
+        let sql = sql.as_ref();
+        let mut stats = QueryStats::default();
+        let ds = execute(self, sql, &mut stats)
+            .instrument(info_span!("query", sql))
+            .await?;
+        info!("query finished: {}", stats);
+        Ok((ds, stats))
+    }
+
+    /// 执行 SQL，结果按批（每批最多一万行）返回，调用方可以边收边处理
+    /// 过滤和投影是逐行的、没有 ORDER BY、JOIN、抽样和 PIVOT / UNPIVOT 时一批一批地执行，
+    /// 其它情况照常执行后再切分。没有结果时返回一个空的批次
+    /// 一批一批执行时，本地的 csv 文件每次读一万行，parquet 文件和分区目录每次读一个 row group，
+    /// 内存里只有当前这一批；http、数据库等其它数据源还是会整个读进内存
+    pub fn query_stream<T: Into<String>>(
+        &self,
+        sql: T,
+    ) -> impl Stream<Item = Result<DataSet>> + Send + 'static {
+        execute_stream(self.clone(), sql.into())
+    }
+
+    /// 在这个会话里执行增量查询，只处理数据源在 watermark 记录的位置之后追加的行
+    /// 过滤、排序、LIMIT 等都只作用在这次新读到的行上
+    pub async fn query_incremental<T: AsRef<str>>(
+        &self,
+        sql: T,
+        watermark: &mut Watermark,
+    ) -> Result<DataSet> {
+        execute_incremental(self, sql.as_ref(), watermark).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn http_config_works() {
+        let session = Session::default()
+            .with_http(HttpConfig::default().timeout(Duration::from_secs(10)))
+            .with_source_http(
+                "https://api.internal",
+                HttpConfig::default().bearer_token("token"),
+            );
+
+        let config = session.http_config("https://api.internal/data.csv");
+        assert_eq!(config.bearer_token, Some("token".into()));
+        assert_eq!(config.timeout, Some(Duration::from_secs(10)));
+
+        let config = session.http_config("https://raw.githubusercontent.com/a.csv");
+        assert_eq!(config.bearer_token, None);
+    }
+
+    #[tokio::test]
+    async fn set_and_show_works() {
+        let path = std::env::temp_dir().join("queryer_session_null_values.csv");
+        std::fs::write(&path, "Name,age\nTyr,NA\nLindsey,N/A\nTom,3\n").unwrap();
+        let sql = format!(
+            "SELECT name, age FROM file://{} WHERE age > 1",
+            path.display()
+        );
+
+        let session = Session::default();
+        assert!(session.query(&sql).await.is_err());
+
+        session.query("SET null_values = 'NA,N/A'").await.unwrap();
+        session.query("SET case_sensitive = false").await.unwrap();
+        // 克隆出来的 Session 共享选项
+        let ds = session.clone().query(&sql).await.unwrap();
+        assert_eq!(ds.height(), 1);
+
+        let ds = session.query("SHOW null_values").await.unwrap();
+        assert_eq!(ds.to_csv().unwrap(), "name,value\nnull_values,\"NA,N/A\"\n");
+        assert_eq!(session.query("SHOW ALL").await.unwrap().height(), 4);
+        assert!(session.query("SET no_such_option = 1").await.is_err());
+
+        session.query("SET http.timeout = '30s'").await.unwrap();
+        let config = session.http_config("https://raw.githubusercontent.com/a.csv");
+        assert_eq!(config.timeout, Some(Duration::from_secs(30)));
+
+        // 顶层的 query() 不保留选项，SET / SHOW 直接报错
+        let e = crate::query("SET null_values = 'NA'").await.unwrap_err();
+        assert_eq!(crate::ErrorKind::of(&e), crate::ErrorKind::Unsupported);
+        assert!(crate::query("SHOW ALL").await.is_err());
+    }
+}
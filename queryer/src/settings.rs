@@ -0,0 +1,312 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::http::parse_duration;
+use anyhow::{anyhow, Result};
+use polars::prelude::*;
+use std::{
+    io::Cursor,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+// SHOW ALL 时的顺序
+const NAMES: [&str; 4] = [
+    "inference_rows",
+    "null_values",
+    "http.timeout",
+    "case_sensitive",
+];
+
+/// 可以用 `SET name = value` 修改、用 `SHOW name` / `SHOW ALL` 查看的会话选项
+/// `SET name = DEFAULT` 恢复默认值
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Options {
+    // 推断 csv 列类型时读取的行数，0 表示读取所有行
+    pub(crate) inference_rows: usize,
+    // csv 里当作 NULL 的值，比如 NA、N/A
+    pub(crate) null_values: Vec<String>,
+    // 覆盖 http 配置里的超时时间
+    pub(crate) http_timeout: Option<Duration>,
+    // 列名是否区分大小写，没有设置时按 Session 的配置和方言决定
+    pub(crate) case_sensitive: Option<bool>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            inference_rows: 16,
+            null_values: Vec::new(),
+            http_timeout: None,
+            case_sensitive: None,
+        }
+    }
+}
+
+impl Options {
+    /// 修改一个选项，value 是 SET 语句里 `=` 后面的值（字符串字面量已经去掉了引号）
+    pub(crate) fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let name = name.to_lowercase();
+        if value.eq_ignore_ascii_case("default") {
+            let default = Self::default();
+            match name.as_str() {
+                "inference_rows" => self.inference_rows = default.inference_rows,
+                "null_values" => self.null_values = default.null_values,
+                "http.timeout" => self.http_timeout = default.http_timeout,
+                "case_sensitive" => self.case_sensitive = default.case_sensitive,
+                _ => return Err(unknown(&name)),
+            }
+            return Ok(());
+        }
+        match name.as_str() {
+            "inference_rows" => {
+                self.inference_rows = value
+                    .parse()
+                    .map_err(|_| anyhow!("inference_rows must be a non-negative integer"))?
+            }
+            "null_values" => {
+                self.null_values = value
+                    .split(',')
+                    .map(|v| v.trim().to_owned())
+                    .filter(|v| !v.is_empty())
+                    .collect()
+            }
+            "http.timeout" => self.http_timeout = Some(parse_duration(value)?),
+            "case_sensitive" => {
+                self.case_sensitive = match value.to_lowercase().as_str() {
+                    "true" | "on" | "1" => Some(true),
+                    "false" | "off" | "0" => Some(false),
+                    _ => return Err(anyhow!("case_sensitive must be true or false")),
+                }
+            }
+            _ => return Err(unknown(&name)),
+        }
+        Ok(())
+    }
+
+    /// 一个选项现在的值，没有设置的可选项显示为 default
+    pub(crate) fn get(&self, name: &str) -> Result<String> {
+        let value = match name.to_lowercase().as_str() {
+            "inference_rows" => self.inference_rows.to_string(),
+            "null_values" => self.null_values.join(","),
+            "http.timeout" => match self.http_timeout {
+                Some(v) if v.subsec_millis() == 0 => format!("{}s", v.as_secs()),
+                Some(v) => format!("{}ms", v.as_millis()),
+                None => "default".into(),
+            },
+            "case_sensitive" => match self.case_sensitive {
+                Some(v) => v.to_string(),
+                None => "default".into(),
+            },
+            name => return Err(unknown(name)),
+        };
+        Ok(value)
+    }
+
+    /// SHOW 的结果：name 和 value 两列，name 为 None 时列出所有选项
+    pub(crate) fn show(&self, name: Option<&str>) -> Result<DataFrame> {
+        let names: Vec<&str> = match name {
+            Some(name) => vec![name],
+            None => NAMES.to_vec(),
+        };
+        let values = names
+            .iter()
+            .map(|name| self.get(name))
+            .collect::<Result<Vec<_>>>()?;
+        let names: Vec<String> = names.iter().map(|v| v.to_lowercase()).collect();
+        Ok(DataFrame::new(vec![
+            Series::new("name", names),
+            Series::new("value", values),
+        ])?)
+    }
+
+    /// 按选项生成 csv 的 reader：推断类型时读取的行数，以及把空值标记换成空字段
+    /// has_header 为 false 时第一行也是数据
+    pub(crate) fn csv_reader<'a>(
+        &self,
+        data: Vec<u8>,
+        has_header: bool,
+    ) -> CsvReader<'a, Cursor<Vec<u8>>> {
+        let rows = match self.inference_rows {
+            0 => None,
+            n => Some(n),
+        };
+        let data = match self.null_values.is_empty() {
+            true => data,
+            false => strip_null_values(&data, &self.null_values, has_header),
+        };
+        CsvReader::new(Cursor::new(data))
+            .has_header(has_header)
+            .infer_schema(rows)
+    }
+}
+
+fn unknown(name: &str) -> anyhow::Error {
+    anyhow!(
+        "unknown option {}, expect one of {}",
+        name,
+        NAMES.join(", ")
+    )
+}
+
+/// polars 每一列只支持一个空值标记，这里直接把等于标记的字段（不带引号的）改成空字段，
+/// 空字段会被读成 NULL，列的类型推断也不会被这些标记干扰
+fn strip_null_values(data: &[u8], values: &[String], has_header: bool) -> Vec<u8> {
+    // 表头原样保留，列名恰好是 NA 这样的标记时不能被去掉
+    let header = match has_header {
+        true => first_record_end(data),
+        false => 0,
+    };
+    let mut result = Vec::with_capacity(data.len());
+    result.extend_from_slice(&data[..header]);
+    let mut start = header;
+    let mut quoted = false;
+    for (i, c) in data.iter().enumerate().skip(header) {
+        match c {
+            b'"' => quoted = !quoted,
+            b',' | b'\n' | b'\r' if !quoted => {
+                push_field(&mut result, &data[start..i], values);
+                result.push(*c);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    push_field(&mut result, &data[start..], values);
+    result
+}
+
+/// 第一个记录结束的位置，引号里的换行属于字段内容
+pub(crate) fn first_record_end(data: &[u8]) -> usize {
+    let mut quoted = false;
+    for (i, c) in data.iter().enumerate() {
+        match c {
+            b'"' => quoted = !quoted,
+            b'\n' if !quoted => return i + 1,
+            _ => {}
+        }
+    }
+    data.len()
+}
+
+/// csv 表头里的列名，按逗号切分，引号里的逗号属于列名，`""` 是转义的引号
+/// 只有表头没有数据时 polars 的 csv reader 会 panic，所以表头自己解析
+pub(crate) fn csv_header(data: &[u8]) -> Vec<String> {
+    let line = &data[..first_record_end(data)];
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if line.is_empty() {
+        return vec![];
+    }
+    let mut names = vec![];
+    let mut name = Vec::new();
+    let mut quoted = false;
+    let mut iter = line.iter().peekable();
+    while let Some(c) = iter.next() {
+        match c {
+            b'"' if quoted && iter.peek() == Some(&&b'"') => {
+                name.push(b'"');
+                iter.next();
+            }
+            b'"' => quoted = !quoted,
+            b',' if !quoted => {
+                names.push(String::from_utf8_lossy(&std::mem::take(&mut name)).into_owned())
+            }
+            _ => name.push(*c),
+        }
+    }
+    names.push(String::from_utf8_lossy(&name).into_owned());
+    names
+}
+
+fn push_field(result: &mut Vec<u8>, field: &[u8], values: &[String]) {
+    if !values.iter().any(|v| v.as_bytes() == field) {
+        result.extend_from_slice(field);
+    }
+}
+
+/// Session 里的选项，SET 语句通过 &Session 修改它，所以放在锁里
+/// Session 的克隆共享同一份选项，query_stream 里的 SET 也会生效
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Settings(Arc<RwLock<Options>>);
+
+impl Settings {
+    /// 当前选项的快照
+    pub(crate) fn options(&self) -> Options {
+        self.0.read().unwrap().clone()
+    }
+
+    pub(crate) fn set(&self, name: &str, value: &str) -> Result<()> {
+        self.0.write().unwrap().set(name, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_options_works() {
+        let mut options = Options::default();
+        options.set("inference_rows", "1000").unwrap();
+        options.set("null_values", "NA, N/A").unwrap();
+        options.set("HTTP.TIMEOUT", "30s").unwrap();
+        options.set("case_sensitive", "false").unwrap();
+        assert_eq!(options.inference_rows, 1000);
+        assert_eq!(options.null_values, vec!["NA", "N/A"]);
+        assert_eq!(options.http_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(options.case_sensitive, Some(false));
+        assert_eq!(options.get("http.timeout").unwrap(), "30s");
+
+        options.set("http.timeout", "DEFAULT").unwrap();
+        assert_eq!(options.get("http.timeout").unwrap(), "default");
+
+        assert!(options.set("inference_rows", "-1").is_err());
+        assert!(options.set("no_such_option", "1").is_err());
+        assert_eq!(options.show(None).unwrap().height(), NAMES.len());
+    }
+
+    #[test]
+    fn csv_reader_works() {
+        let options = Options {
+            null_values: vec!["NA".into(), "N/A".into()],
+            ..Default::default()
+        };
+        let data = b"name,age\nTyr,NA\nLindsey,N/A\n\"NA\",3\n".to_vec();
+        let df = options.csv_reader(data, true).finish().unwrap();
+        assert_eq!(df.column("age").unwrap().dtype(), &DataType::Int64);
+        assert_eq!(df.column("age").unwrap().null_count(), 2);
+        assert_eq!(df.column("name").unwrap().null_count(), 0);
+
+        // 表头里的标记是列名，不是空值
+        let data = b"name,NA\nTyr,NA\nLindsey,1\n".to_vec();
+        let df = options.csv_reader(data, true).finish().unwrap();
+        assert_eq!(df.get_column_names(), vec!["name", "NA"]);
+        assert_eq!(df.column("NA").unwrap().null_count(), 1);
+
+        // 没有表头时第一行也是数据
+        let stripped = strip_null_values(b"Tyr,NA\n", &options.null_values, false);
+        assert_eq!(stripped, b"Tyr,\n");
+    }
+
+    #[test]
+    fn csv_header_works() {
+        assert_eq!(csv_header(b"name,age\r\nTyr,1\n"), vec!["name", "age"]);
+        assert_eq!(
+            csv_header(b"\"a,b\",\"say \"\"hi\"\"\",c"),
+            vec!["a,b", "say \"hi\"", "c"]
+        );
+        assert!(csv_header(b"").is_empty());
+    }
+}
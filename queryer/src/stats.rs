@@ -0,0 +1,82 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{fmt, time::Duration};
+
+/// 一次查询各个阶段的耗时和数据量，通过 [Session::query_with_stats](crate::Session::query_with_stats) 拿到
+/// DIFF 这种包含多个查询的语句，各项是所有查询加起来的值
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryStats {
+    // 解析 SQL
+    pub parse: Duration,
+    // 从数据源获取数据
+    pub fetch: Duration,
+    // 把获取的数据加载成 DataFrame
+    pub load: Duration,
+    // 执行过滤、排序、投影等
+    pub execute: Duration,
+    // 从数据源获取的字节数
+    pub bytes_downloaded: usize,
+    // 加载进来的行数
+    pub rows_scanned: usize,
+    // 返回的行数
+    pub rows_returned: usize,
+}
+
+impl QueryStats {
+    /// 所有阶段的总耗时
+    pub fn total(&self) -> Duration {
+        self.parse + self.fetch + self.load + self.execute
+    }
+}
+
+impl fmt::Display for QueryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "total {:?} (parse {:?}, fetch {:?}, load {:?}, execute {:?}), {} bytes downloaded, {} rows scanned, {} rows returned",
+            self.total(),
+            self.parse,
+            self.fetch,
+            self.load,
+            self.execute,
+            self.bytes_downloaded,
+            self.rows_scanned,
+            self.rows_returned
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Session;
+
+    #[tokio::test]
+    async fn query_with_stats_works() {
+        let path = std::env::temp_dir().join("queryer_stats.csv");
+        let data = "name,age\nTyr,18\nLindsey,30\nJoe,40\n";
+        std::fs::write(&path, data).unwrap();
+        let sql = format!(
+            "SELECT name FROM file://{} WHERE age > 20 LIMIT 1",
+            path.display()
+        );
+
+        let (ds, stats) = Session::default().query_with_stats(sql).await.unwrap();
+        assert_eq!(ds.height(), 1);
+        assert_eq!(stats.bytes_downloaded, data.len());
+        assert_eq!(stats.rows_scanned, 3);
+        assert_eq!(stats.rows_returned, 1);
+        assert!(stats.total() >= stats.fetch);
+    }
+}
@@ -0,0 +1,565 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    command::{parse_command, Clauses},
+    convert::Sql,
+    error::FetchError,
+    execute, load_source,
+    loader::split_sheet,
+    partition::{lake_path, LakeBatches, LakeLoader},
+    registry,
+    resolve::{resolve_columns, strip_alias},
+    settings::{csv_header, first_record_end, Options},
+    temporal::{convert_dates, detect_dates},
+    transform, DataSet, QueryStats, Session,
+};
+use anyhow::{Context, Result};
+use futures::{
+    future::Either,
+    stream::{self, Stream, StreamExt},
+};
+use polars::prelude::*;
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::{BufRead, BufReader},
+};
+use tracing::{info, info_span, Instrument};
+
+/// query_stream 返回的每一批最多的行数，也是按批读取数据源时每次读的行数
+pub(crate) const BATCH_ROWS: usize = 10_000;
+
+/// 执行 SQL，结果按批返回
+pub(crate) fn execute_stream(
+    session: Session,
+    sql: String,
+) -> impl Stream<Item = Result<DataSet>> + Send + 'static {
+    let span = info_span!("query", sql = sql.as_str());
+    stream::once(async move { batches(&session, &sql).await }.instrument(span))
+        .map(|result| match result {
+            Ok(batches) => Either::Left(stream::iter(batches)),
+            Err(e) => Either::Right(stream::once(async move { Err(e) })),
+        })
+        .flatten()
+}
+
+async fn batches(session: &Session, sql: &str) -> Result<Batches> {
+    let (command, clauses) = parse_command(session.dialect, sql, &session.params)?;
+    let q = match command.into_query() {
+        Some(q) => q,
+        // INSERT / COPY / DIFF 照常执行，结果再按批返回
+        None => {
+            let ds = execute(session, sql, &mut QueryStats::default()).await?;
+            return Ok(Batches::new(Source::frame(ds.0), None));
+        }
+    };
+
+    let sql: Sql = q.as_ref().try_into()?;
+    if !streamable(&sql, &clauses) {
+        let df = load_source(session, &sql, &clauses, &mut QueryStats::default()).await?;
+        let ds = info_span!("execute").in_scope(|| transform(session, sql, &clauses, df))?;
+        return Ok(Batches::new(Source::frame(ds.0), None));
+    }
+
+    // 本地文件边读边执行，其它数据源整个读进来，按批执行的只是后面的子句
+    let source = match open_source(session, &sql).await? {
+        Some(source) => source,
+        None => {
+            let df = load_source(session, &sql, &clauses, &mut QueryStats::default()).await?;
+            Source::frame(df)
+        }
+    };
+    let plan = Plan {
+        columns: sql.columns(),
+        qualifier: sql.qualifier().map(String::from),
+        ignore_case: session.ignore_case(),
+        formats: session.date_formats.clone(),
+        dates: None,
+        qualifiers: sql.qualifiers(),
+        condition: sql.condition,
+        selection: sql.selection,
+        skip: sql.offset.unwrap_or(0) as usize,
+        remaining: sql.limit.unwrap_or(usize::MAX),
+    };
+    Ok(Batches::new(source, Some(plan)))
+}
+
+/// 过滤和投影都是逐行的、没有 ORDER BY、JOIN、抽样和 PIVOT / UNPIVOT 时，才能一批一批地执行
+fn streamable(sql: &Sql, clauses: &Clauses) -> bool {
+    clauses.sample.is_none()
+        && clauses.reshape.is_none()
+        && sql.joins.is_empty()
+        && sql.order_by.is_empty()
+        && !matches!(sql.offset, Some(v) if v < 0)
+        && sql
+            .selection
+            .iter()
+            .chain(sql.condition.iter())
+            .all(row_wise)
+}
+
+fn row_wise(expr: &Expr) -> bool {
+    expr.into_iter().all(|e| {
+        matches!(
+            e,
+            Expr::Alias(..)
+                | Expr::Column(_)
+                | Expr::Literal(_)
+                | Expr::BinaryExpr { .. }
+                | Expr::Not(_)
+                | Expr::IsNull(_)
+                | Expr::IsNotNull(_)
+                | Expr::Cast { .. }
+                | Expr::Ternary { .. }
+                | Expr::Function { .. }
+                | Expr::BinaryFunction { .. }
+                | Expr::Wildcard
+                | Expr::Exclude(..)
+        )
+    })
+}
+
+/// 可以按批读取的数据源：本地的 csv 文件、parquet 文件和只有 parquet 文件的分区目录。
+/// 其它数据源（http、数据库、电子表格、注册的表和格式等）返回 None，整个读进来
+async fn open_source(session: &Session, sql: &Sql<'_>) -> Result<Option<Source>> {
+    let source = sql.source;
+    if registry::get_table(source).is_some() {
+        return Ok(None);
+    }
+    session.permissions.check_source(source)?;
+    let options = session.settings.options();
+
+    if let Some(root) = lake_path(source) {
+        let lake = LakeLoader::discover(root, sql.predicates.clone(), options)
+            .await
+            .context(FetchError(source.to_owned()))?;
+        let batches = lake
+            .batches(BATCH_ROWS)
+            .context(FetchError(source.to_owned()))?;
+        return Ok(batches.map(Source::Lake));
+    }
+
+    // 用户注册的格式要看到整个内容才能判断，电子表格是二进制格式
+    let path = match source.strip_prefix("file://") {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    if registry::get_fetcher("file").is_some()
+        || !registry::get_loaders().is_empty()
+        || split_sheet(source).is_some()
+    {
+        return Ok(None);
+    }
+    info!("streaming data from source: {}", source);
+    let file = File::open(path).context(FetchError(source.to_owned()))?;
+    Ok(Some(Source::Csv(CsvChunks::new(file, options))))
+}
+
+/// 按批读出来的数据源，每次最多 BATCH_ROWS 行，至少返回一批（可能是空的），这样才知道有哪些列
+enum Source {
+    // 已经整个读进来的数据，position 是下一批开始的行
+    Frame(DataFrame, usize),
+    Csv(CsvChunks),
+    Lake(LakeBatches),
+}
+
+impl Source {
+    fn frame(df: DataFrame) -> Self {
+        Self::Frame(df, 0)
+    }
+}
+
+impl Iterator for Source {
+    type Item = Result<DataFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Source::Frame(df, position) => {
+                if *position > 0 && *position >= df.height() {
+                    return None;
+                }
+                let batch = df.slice(*position as i64, BATCH_ROWS);
+                *position += batch.height().max(1);
+                Some(Ok(batch))
+            }
+            Source::Csv(csv) => csv.next(),
+            Source::Lake(lake) => lake.next(),
+        }
+    }
+}
+
+/// 一次读 BATCH_ROWS 个记录的 csv 文件。类型按第一批推断，后面的批次按同样的类型解析，
+/// 后面出现第一批推断不了的值（比如整数列里的小数）时这一批会报错
+struct CsvChunks {
+    reader: BufReader<File>,
+    options: Options,
+    // 第一批推断出来的表结构
+    empty: Option<DataFrame>,
+    finished: bool,
+}
+
+impl CsvChunks {
+    fn new(file: File, options: Options) -> Self {
+        Self {
+            reader: BufReader::new(file),
+            options,
+            empty: None,
+            finished: false,
+        }
+    }
+
+    /// 读 records 个完整的记录，引号里的换行属于字段内容
+    fn read_records(&mut self, records: usize) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut quoted = false;
+        let mut count = 0;
+        while count < records {
+            let start = data.len();
+            if self.reader.read_until(b'\n', &mut data)? == 0 {
+                self.finished = true;
+                break;
+            }
+            quoted = data[start..]
+                .iter()
+                .fold(quoted, |q, c| if *c == b'"' { !q } else { q });
+            if !quoted {
+                count += 1;
+            }
+        }
+        Ok(data)
+    }
+
+    fn next_chunk(&mut self) -> Result<Option<DataFrame>> {
+        let empty = match &self.empty {
+            Some(empty) => empty.clone(),
+            None => {
+                // 第一批带上表头
+                let data = self.read_records(BATCH_ROWS + 1)?;
+                // 只有表头的数据交给 polars 会 panic，直接按表头生成空的 data frame
+                let df = match first_record_end(&data) == data.len() {
+                    true => {
+                        let columns = csv_header(&data)
+                            .iter()
+                            .map(|name| Series::new(name, Vec::<&str>::new()))
+                            .collect();
+                        DataFrame::new(columns)?
+                    }
+                    false => self.options.csv_reader(data, true).finish()?,
+                };
+                self.empty = Some(df.head(Some(0)));
+                return Ok(Some(df));
+            }
+        };
+        if self.finished {
+            return Ok(None);
+        }
+        let data = self.read_records(BATCH_ROWS)?;
+        if data.iter().all(|c| c.is_ascii_whitespace()) {
+            return Ok(None);
+        }
+        let df = self
+            .options
+            .csv_reader(data, false)
+            .with_schema(&empty.schema())
+            .finish()?;
+        Ok(Some(df))
+    }
+}
+
+impl Iterator for CsvChunks {
+    type Item = Result<DataFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_chunk() {
+            Ok(Some(df)) => Some(Ok(df)),
+            Ok(None) => None,
+            Err(e) => {
+                // 出错之后不再继续读
+                self.finished = true;
+                self.empty.get_or_insert_with(DataFrame::default);
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// 按批执行时还没有执行的子句
+struct Plan {
+    // 把每批数据的列名对应到查询里的写法，再识别日期列，和 prepare 一样
+    columns: Vec<String>,
+    qualifier: Option<String>,
+    ignore_case: bool,
+    formats: Vec<String>,
+    // 第一批识别出来的日期列和格式，后面的批次按同样的格式转换
+    dates: Option<Vec<(String, String)>>,
+    condition: Option<Expr>,
+    selection: Vec<Expr>,
+    // 结果的列名里要去掉的表名
+    qualifiers: Vec<String>,
+    // OFFSET 还要跳过的行数
+    skip: usize,
+    // LIMIT 还能返回的行数
+    remaining: usize,
+}
+
+impl Plan {
+    fn prepare(&mut self, df: DataFrame) -> Result<DataFrame> {
+        let df = resolve_columns(
+            df,
+            &self.columns,
+            self.qualifier.as_deref(),
+            self.ignore_case,
+        )?;
+        match &self.dates {
+            Some(dates) => convert_dates(df, dates),
+            None => {
+                let (df, dates) = detect_dates(df, &self.formats, &self.columns)?;
+                self.dates = Some(dates);
+                Ok(df)
+            }
+        }
+    }
+
+    fn execute(&mut self, df: DataFrame) -> Result<DataSet> {
+        let df = match &self.condition {
+            Some(expr) => df.lazy().filter(expr.clone()).collect()?,
+            None => df,
+        };
+        // OFFSET / LIMIT 针对的是过滤之后的行
+        let skip = self.skip.min(df.height());
+        let take = self.remaining.min(df.height() - skip);
+        self.skip -= skip;
+        self.remaining -= take;
+        let df = df
+            .slice(skip as i64, take)
+            .lazy()
+            .select(self.selection.clone())
+            .collect()?;
+        Ok(DataSet(strip_alias(df, &self.qualifiers)?))
+    }
+}
+
+/// 每次从 source 读一批，执行 plan 之后返回
+/// plan 为 None 时 source 里已经是最终结果，只需要切分
+struct Batches {
+    source: Source,
+    plan: Option<Plan>,
+    // 第一批执行 plan 之前的表结构，没有结果时用它返回一个带列的空批次
+    empty: Option<DataFrame>,
+    emitted: bool,
+    finished: bool,
+}
+
+impl Batches {
+    fn new(source: Source, plan: Option<Plan>) -> Self {
+        Self {
+            source,
+            plan,
+            empty: None,
+            emitted: false,
+            finished: false,
+        }
+    }
+
+    /// LIMIT 已经满了，知道了表结构之后就不用再读了
+    fn limited(&self) -> bool {
+        self.empty.is_some() && matches!(&self.plan, Some(p) if p.remaining == 0)
+    }
+
+    fn apply(&mut self, df: DataFrame) -> Result<DataSet> {
+        let plan = match self.plan.as_mut() {
+            Some(plan) => plan,
+            None => {
+                self.empty.get_or_insert_with(|| df.head(Some(0)));
+                return Ok(DataSet(df));
+            }
+        };
+        let df = plan.prepare(df)?;
+        self.empty.get_or_insert_with(|| df.head(Some(0)));
+        plan.execute(df)
+    }
+}
+
+impl Iterator for Batches {
+    type Item = Result<DataSet>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let batch = match self.finished || self.limited() {
+                true => None,
+                false => self.source.next(),
+            };
+            let result = match batch {
+                Some(Ok(batch)) => self.apply(batch),
+                Some(Err(e)) => Err(e),
+                None => {
+                    self.finished = true;
+                    if self.emitted {
+                        return None;
+                    }
+                    // 没有结果时也返回一个空的批次，调用方才能知道有哪些列
+                    self.emitted = true;
+                    let empty = self.empty.take()?;
+                    match self.plan.as_mut() {
+                        Some(plan) => plan.execute(empty),
+                        None => Ok(DataSet(empty)),
+                    }
+                }
+            };
+            match result {
+                // 整批都被过滤掉了，接着处理下一批
+                Ok(ds) if ds.height() == 0 && !self.finished => continue,
+                Err(e) => {
+                    self.finished = true;
+                    self.emitted = true;
+                    return Some(Err(e));
+                }
+                result => {
+                    self.emitted = true;
+                    return Some(result);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::{
+        arrow::ArrowWriter,
+        file::{
+            properties::WriterProperties,
+            reader::{FileReader, SerializedFileReader},
+        },
+    };
+    use std::env;
+
+    async fn collect(sql: String) -> Vec<DataSet> {
+        Session::default()
+            .query_stream(sql)
+            .map(|ds| ds.unwrap())
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn query_stream_works() {
+        let path = env::temp_dir().join("queryer_stream_numbers.csv");
+        let rows: Vec<String> = (0..25_000).map(|i| format!("{},{}", i, i % 3)).collect();
+        std::fs::write(&path, format!("n,m\n{}\n", rows.join("\n"))).unwrap();
+
+        let sql = format!("SELECT n FROM file://{} WHERE m = 0", path.display());
+        let batches = collect(sql).await;
+        assert_eq!(batches.len(), 3);
+        assert_eq!(batches.iter().map(|ds| ds.height()).sum::<usize>(), 8334);
+
+        // OFFSET / LIMIT 跨批次
+        let sql = format!(
+            "SELECT n FROM file://{} WHERE m = 0 LIMIT 5000 OFFSET 3000",
+            path.display()
+        );
+        let batches = collect(sql).await;
+        assert_eq!(batches.iter().map(|ds| ds.height()).sum::<usize>(), 5000);
+        let first = batches[0].column("n").unwrap().i64().unwrap().get(0);
+        assert_eq!(first, Some(9000));
+
+        // 没有结果时返回一个带列的空批次
+        let sql = format!("SELECT n, m FROM file://{} WHERE m = 5", path.display());
+        let batches = collect(sql).await;
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].get_column_names(), vec!["n", "m"]);
+
+        // ORDER BY 不能按批执行，整体算完后再切分
+        let sql = format!("SELECT n FROM file://{} ORDER BY n DESC", path.display());
+        let batches = collect(sql).await;
+        assert_eq!(batches.len(), 3);
+        let first = batches[0].column("n").unwrap().i64().unwrap().get(0);
+        assert_eq!(first, Some(24_999));
+    }
+
+    #[tokio::test]
+    async fn query_stream_reads_csv_in_batches() {
+        // 第 20000 行的值不是整数，按批读取时前面的批次已经返回了，读到这一批才报错
+        let path = env::temp_dir().join("queryer_stream_bad_row.csv");
+        let rows: Vec<String> = (0..25_000)
+            .map(|i| match i {
+                20_000 => "oops,0".to_string(),
+                _ => format!("{},{}", i, i % 3),
+            })
+            .collect();
+        std::fs::write(&path, format!("n,m\n{}\n", rows.join("\n"))).unwrap();
+
+        let sql = format!("SELECT n FROM file://{} WHERE m = 0", path.display());
+        let result: Vec<_> = Session::default().query_stream(sql).collect().await;
+        assert_eq!(result.len(), 3);
+        assert!(result[0].is_ok() && result[1].is_ok());
+        assert!(result[2].is_err());
+
+        // 只有表头的文件返回一个带列的空批次
+        let path = env::temp_dir().join("queryer_stream_header_only.csv");
+        std::fs::write(&path, "n,m\n").unwrap();
+        let batches = collect(format!("SELECT n, m FROM file://{}", path.display())).await;
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].get_column_names(), vec!["n", "m"]);
+    }
+
+    #[tokio::test]
+    async fn query_stream_reads_parquet_row_groups() {
+        let path = env::temp_dir().join("queryer_stream_row_groups.parquet");
+        // 每个 chunk 写成一个 row group
+        let mut df =
+            DataFrame::new(vec![Series::new("id", (0..15_000i64).collect::<Vec<_>>())]).unwrap();
+        for i in 1..3i64 {
+            let chunk = (i * 15_000..(i + 1) * 15_000).collect::<Vec<_>>();
+            df.vstack_mut(&DataFrame::new(vec![Series::new("id", chunk)]).unwrap())
+                .unwrap();
+        }
+        // parquet 5 解码 dictionary 编码的大文件时在 debug 构建下会因为指针没有对齐 panic，这里不用 dictionary
+        let batches = df.as_record_batches().unwrap();
+        let props = WriterProperties::builder()
+            .set_dictionary_enabled(false)
+            .build();
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = ArrowWriter::try_new(file, batches[0].schema(), Some(props)).unwrap();
+        for batch in &batches {
+            writer.write(batch).unwrap();
+        }
+        writer.close().unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.num_row_groups(), 3);
+
+        let sql = format!("SELECT id FROM file://{} WHERE id >= 5000", path.display());
+        let batches = collect(sql).await;
+        assert!(batches.iter().all(|ds| ds.height() <= BATCH_ROWS));
+        assert_eq!(batches.iter().map(|ds| ds.height()).sum::<usize>(), 40_000);
+        let first = batches[0].column("id").unwrap().i64().unwrap().get(0);
+        assert_eq!(first, Some(5000));
+
+        // row group 都被跳过时返回一个带列的空批次
+        let sql = format!("SELECT id FROM file://{} WHERE id > 50000", path.display());
+        let batches = collect(sql).await;
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].get_column_names(), vec!["id"]);
+    }
+
+    #[tokio::test]
+    async fn query_stream_error_works() {
+        let sql = "SELECT a FROM file:///queryer/no/such/file.csv".to_string();
+        let result: Vec<_> = Session::default().query_stream(sql).collect().await;
+        assert_eq!(result.len(), 1);
+        assert!(result[0].is_err());
+    }
+}
@@ -0,0 +1,361 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::error::unsupported;
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use polars::prelude::*;
+use sqlparser::ast::DateTimeField;
+
+/// 自动识别日期时用的默认格式
+pub const DEFAULT_DATE_FORMATS: [&str; 4] = [
+    "%Y-%m-%d",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S%.f",
+];
+
+// 判断一列字符串是不是日期时只看前面这么多个值
+const SNIFF_ROWS: usize = 64;
+
+/// 把 columns 里能按 formats 中某个格式完整解析的字符串列转换成日期列
+/// 只有日期的格式转换成 Date32，带时间的转换成 Date64，查询里没有引用的列不去尝试
+pub(crate) fn parse_dates(
+    df: DataFrame,
+    formats: &[String],
+    columns: &[String],
+) -> Result<DataFrame> {
+    Ok(detect_dates(df, formats, columns)?.0)
+}
+
+/// 和 parse_dates 一样，同时返回转换了的列和用的格式，
+/// 流式查询后面的批次用 convert_dates 按同样的格式转换，每批的类型才一致
+pub(crate) fn detect_dates(
+    mut df: DataFrame,
+    formats: &[String],
+    columns: &[String],
+) -> Result<(DataFrame, Vec<(String, String)>)> {
+    let mut dates = Vec::new();
+    if formats.is_empty() {
+        return Ok((df, dates));
+    }
+
+    let names: Vec<String> = df
+        .get_columns()
+        .iter()
+        .filter(|s| s.dtype() == &DataType::Utf8)
+        .map(|s| s.name().to_owned())
+        .filter(|name| columns.contains(name))
+        .collect();
+
+    for name in names {
+        let ca = df.column(&name)?.utf8()?.clone();
+        let sample: Vec<&str> = ca.into_iter().flatten().take(SNIFF_ROWS).collect();
+        if sample.is_empty() {
+            continue;
+        }
+
+        let matched = formats.iter().find(|fmt| match is_date_only(fmt) {
+            true => sample
+                .iter()
+                .all(|v| NaiveDate::parse_from_str(v, fmt).is_ok()),
+            false => sample
+                .iter()
+                .all(|v| NaiveDateTime::parse_from_str(v, fmt).is_ok()),
+        });
+        if let Some(fmt) = matched {
+            let series = to_date(&ca, fmt)?;
+            // 后面的值有解析不了的，就还是保留成字符串
+            if series.null_count() == ca.null_count() {
+                df.with_column(series)?;
+                dates.push((name, fmt.clone()));
+            }
+        }
+    }
+    Ok((df, dates))
+}
+
+/// 按 detect_dates 找到的格式把字符串列转换成日期，解析不了的值是 NULL
+pub(crate) fn convert_dates(mut df: DataFrame, dates: &[(String, String)]) -> Result<DataFrame> {
+    for (name, fmt) in dates {
+        let s = df.column(name)?;
+        if s.dtype() == &DataType::Utf8 {
+            let series = to_date(s.utf8()?, fmt)?;
+            df.with_column(series)?;
+        }
+    }
+    Ok(df)
+}
+
+fn to_date(ca: &Utf8Chunked, fmt: &str) -> Result<Series> {
+    let series = match is_date_only(fmt) {
+        true => ca.as_date32(Some(fmt))?.into_series(),
+        false => ca.as_date64(Some(fmt))?.into_series(),
+    };
+    Ok(series)
+}
+
+fn is_date_only(fmt: &str) -> bool {
+    !["%H", "%M", "%S", "%T", "%R", "%s"]
+        .iter()
+        .any(|v| fmt.contains(v))
+}
+
+/// 解析 DATE '2023-09-01' 这样的字面量
+pub(crate) fn parse_date(s: &str) -> Result<NaiveDateTime> {
+    let date = NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d")
+        .map_err(|_| anyhow!("invalid date {}, expect YYYY-MM-DD", s))?;
+    date.and_hms_opt(0, 0, 0)
+        .ok_or_else(|| anyhow!("invalid date {}", s))
+}
+
+/// 解析 TIMESTAMP '2023-09-01 12:00:00' 这样的字面量，也接受只有日期的写法
+pub(crate) fn parse_timestamp(s: &str) -> Result<NaiveDateTime> {
+    let s = s.trim();
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(s, fmt).ok())
+        .map(Ok)
+        .unwrap_or_else(|| parse_date(s))
+        .map_err(|_| anyhow!("invalid timestamp {}, expect YYYY-MM-DD HH:MM:SS", s))
+}
+
+/// EXTRACT(field FROM expr)
+pub(crate) fn extract(field: &DateTimeField, expr: Expr) -> Expr {
+    match field {
+        DateTimeField::Year => expr.year(),
+        DateTimeField::Month => expr.month(),
+        DateTimeField::Day => expr.day(),
+        DateTimeField::Hour => expr.hour(),
+        DateTimeField::Minute => expr.minute(),
+        DateTimeField::Second => expr.second(),
+    }
+}
+
+/// DATE_TRUNC('month', expr)，结果统一是 Date64
+pub(crate) fn date_trunc(unit: &str, expr: Expr) -> Result<Expr> {
+    let unit = unit.to_lowercase();
+    if ![
+        "year", "quarter", "month", "week", "day", "hour", "minute", "second",
+    ]
+    .contains(&unit.as_str())
+    {
+        return Err(unsupported!("DATE_TRUNC unit {} is not supported", unit));
+    }
+
+    Ok(expr.map(
+        move |s| map_datetime(&s, |dt| truncate(dt, &unit)),
+        Some(DataType::Date64),
+    ))
+}
+
+/// 截断之后的时间超出 chrono 的范围时返回 None，结果是 null
+fn truncate(dt: NaiveDateTime, unit: &str) -> Option<NaiveDateTime> {
+    let date = dt.date();
+    let date = match unit {
+        "year" => NaiveDate::from_ymd_opt(date.year(), 1, 1)?,
+        "quarter" => NaiveDate::from_ymd_opt(date.year(), (date.month() - 1) / 3 * 3 + 1, 1)?,
+        "month" => NaiveDate::from_ymd_opt(date.year(), date.month(), 1)?,
+        "week" => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+        _ => date,
+    };
+    let time = match unit {
+        "hour" => NaiveTime::from_hms_opt(dt.hour(), 0, 0)?,
+        "minute" => NaiveTime::from_hms_opt(dt.hour(), dt.minute(), 0)?,
+        "second" => NaiveTime::from_hms_opt(dt.hour(), dt.minute(), dt.second())?,
+        _ => NaiveTime::from_hms_opt(0, 0, 0)?,
+    };
+    Some(date.and_time(time))
+}
+
+/// INTERVAL 字面量，月和毫秒分开存，因为每个月的天数不一样
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct Interval {
+    months: i32,
+    millis: i64,
+}
+
+impl Interval {
+    /// 支持 INTERVAL '1' DAY 和 INTERVAL '1 day 2 hours' 两种写法
+    pub(crate) fn parse(value: &str, field: Option<&DateTimeField>) -> Result<Self> {
+        let mut interval = Interval::default();
+        match field {
+            Some(field) => {
+                let n: i64 = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("invalid interval {}", value))?;
+                interval.add(n, &field.to_string())?;
+            }
+            None => {
+                let parts: Vec<&str> = value.split_whitespace().collect();
+                if parts.is_empty() || !parts.len().is_multiple_of(2) {
+                    return Err(anyhow!("invalid interval {}", value));
+                }
+                for pair in parts.chunks(2) {
+                    let n: i64 = pair[0]
+                        .parse()
+                        .map_err(|_| anyhow!("invalid interval {}", value))?;
+                    interval.add(n, pair[1])?;
+                }
+            }
+        }
+        Ok(interval)
+    }
+
+    fn add(&mut self, n: i64, unit: &str) -> Result<()> {
+        let unit = unit.to_lowercase();
+        let overflow = || anyhow!("interval {} {} is out of range", n, unit);
+        let (months, millis) = match unit.trim_end_matches('s') {
+            "year" => (n.checked_mul(12), Some(0)),
+            "month" => (Some(n), Some(0)),
+            "week" => (Some(0), n.checked_mul(7 * 86_400_000)),
+            "day" => (Some(0), n.checked_mul(86_400_000)),
+            "hour" => (Some(0), n.checked_mul(3_600_000)),
+            "minute" => (Some(0), n.checked_mul(60_000)),
+            "second" => (Some(0), n.checked_mul(1000)),
+            _ => return Err(unsupported!("interval unit {} is not supported", unit)),
+        };
+        let months = months
+            .and_then(|v| i32::try_from(v).ok())
+            .and_then(|v| self.months.checked_add(v))
+            .ok_or_else(overflow)?;
+        let millis = millis
+            .and_then(|v| self.millis.checked_add(v))
+            .ok_or_else(overflow)?;
+        self.months = months;
+        self.millis = millis;
+        Ok(())
+    }
+
+    pub(crate) fn negate(self) -> Result<Self> {
+        match (self.months.checked_neg(), self.millis.checked_neg()) {
+            (Some(months), Some(millis)) => Ok(Self { months, millis }),
+            _ => Err(anyhow!("interval is out of range")),
+        }
+    }
+
+    /// 结果超出 chrono 能表示的范围时返回 None
+    fn add_to(&self, dt: NaiveDateTime) -> Option<NaiveDateTime> {
+        let date = dt.date();
+        let months = (date.year() * 12 + date.month0() as i32).checked_add(self.months)?;
+        let (year, month) = (months.div_euclid(12), months.rem_euclid(12) as u32 + 1);
+        // 1 月 31 日加一个月是 2 月的最后一天
+        let day = (1..=date.day())
+            .rev()
+            .find_map(|d| NaiveDate::from_ymd_opt(year, month, d))?;
+        day.and_time(dt.time())
+            .checked_add_signed(Duration::milliseconds(self.millis))
+    }
+
+    /// expr + interval，结果统一是 Date64，超出范围的结果是 null
+    pub(crate) fn apply(self, expr: Expr) -> Expr {
+        expr.map(
+            move |s| map_datetime(&s, |dt| self.add_to(dt)),
+            Some(DataType::Date64),
+        )
+    }
+}
+
+/// 把日期列转成 Date64 后对每个值做变换，f 返回 None 时结果是 null
+fn map_datetime(
+    s: &Series,
+    f: impl Fn(NaiveDateTime) -> Option<NaiveDateTime>,
+) -> polars::prelude::Result<Series> {
+    let s = s.cast_with_dtype(&DataType::Date64)?;
+    let mut ca: Date64Chunked = s
+        .date64()?
+        .into_iter()
+        .map(|v| {
+            v.and_then(from_millis)
+                .and_then(&f)
+                .map(|dt| dt.timestamp_millis())
+        })
+        .collect();
+    ca.rename(s.name());
+    Ok(ca.into_series())
+}
+
+fn from_millis(ms: i64) -> Option<NaiveDateTime> {
+    NaiveDateTime::from_timestamp_opt(
+        ms.div_euclid(1000),
+        (ms.rem_euclid(1000) * 1_000_000) as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dt(s: &str) -> NaiveDateTime {
+        parse_timestamp(s).unwrap()
+    }
+
+    #[test]
+    fn parse_dates_works() {
+        let df = DataFrame::new(vec![
+            Series::new("day", &[Some("2023-09-01"), None, Some("2023-09-03")]),
+            Series::new(
+                "at",
+                &[
+                    "2023-09-01 10:00:00",
+                    "2023-09-02 11:00:00",
+                    "2023-09-03 12:00:00",
+                ],
+            ),
+            Series::new("name", &["2023-09-01", "Tyr", "Lindsey"]),
+        ])
+        .unwrap();
+        let formats: Vec<String> = DEFAULT_DATE_FORMATS.iter().map(|v| v.to_string()).collect();
+        let columns: Vec<String> = vec!["day".into(), "name".into()];
+        let df = parse_dates(df, &formats, &columns).unwrap();
+        assert_eq!(df.column("day").unwrap().dtype(), &DataType::Date32);
+        assert_eq!(df.column("name").unwrap().dtype(), &DataType::Utf8);
+        // 查询里没有引用的列保持原样
+        assert_eq!(df.column("at").unwrap().dtype(), &DataType::Utf8);
+    }
+
+    #[test]
+    fn truncate_works() {
+        let v = dt("2023-08-31 10:20:30");
+        assert_eq!(truncate(v, "year"), Some(dt("2023-01-01")));
+        assert_eq!(truncate(v, "quarter"), Some(dt("2023-07-01")));
+        assert_eq!(truncate(v, "month"), Some(dt("2023-08-01")));
+        assert_eq!(truncate(v, "week"), Some(dt("2023-08-28")));
+        assert_eq!(truncate(v, "hour"), Some(dt("2023-08-31 10:00:00")));
+    }
+
+    #[test]
+    fn interval_works() {
+        let interval = Interval::parse("1", Some(&DateTimeField::Month)).unwrap();
+        assert_eq!(interval.add_to(dt("2023-01-31")), Some(dt("2023-02-28")));
+        let negated = interval.negate().unwrap();
+        assert_eq!(negated.add_to(dt("2023-01-31")), Some(dt("2022-12-31")));
+
+        let interval = Interval::parse("1 day 2 hours", None).unwrap();
+        assert_eq!(
+            interval.add_to(dt("2023-01-31")),
+            Some(dt("2023-02-01 02:00:00"))
+        );
+        assert!(Interval::parse("1 fortnight", None).is_err());
+
+        // 溢出时返回错误，超出 chrono 范围的结果是 None
+        assert!(Interval::parse("300000000 years", None).is_err());
+        assert!(Interval::parse("9223372036854775807 days", None).is_err());
+        let interval = Interval::parse("10000000 years", None).unwrap();
+        assert_eq!(interval.add_to(dt("2023-01-31")), None);
+        let interval = Interval::parse("100000000000 days", None).unwrap();
+        assert_eq!(interval.add_to(dt("2023-01-31")), None);
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::error::unsupported;
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use polars::prelude::*;
+use regex::Regex;
+use sqlparser::ast::{Expr as SqlExpr, Value as SqlValue};
+use std::{collections::HashMap, sync::RwLock};
+
+// 编译好的正则按 pattern 缓存，同一个 pattern 在每一行、每一次查询里都只编译一次
+lazy_static! {
+    static ref REGEX_CACHE: RwLock<HashMap<String, Regex>> = RwLock::new(HashMap::new());
+}
+
+// 缓存的正则个数上限，超过后清空重来，避免参数化的 pattern 让缓存无限增长
+const CACHE_LIMIT: usize = 256;
+
+/// 从缓存里取编译好的正则，没有的话编译后放进缓存
+pub(crate) fn regex(pattern: &str) -> Result<Regex> {
+    if let Some(re) = REGEX_CACHE.read().unwrap().get(pattern) {
+        return Ok(re.clone());
+    }
+
+    let re = Regex::new(pattern).map_err(|e| anyhow!("invalid regex {}: {}", pattern, e))?;
+    let mut cache = REGEX_CACHE.write().unwrap();
+    if cache.len() >= CACHE_LIMIT {
+        cache.clear();
+    }
+    cache.insert(pattern.to_owned(), re.clone());
+    Ok(re)
+}
+
+/// 字符串匹配函数，第一个参数是要匹配的表达式，pattern 等其它参数必须是字面量
+pub(crate) fn call(name: &str, expr: Expr, args: &[SqlExpr]) -> Result<Expr> {
+    let args = args
+        .iter()
+        .map(|v| match v {
+            SqlExpr::Value(SqlValue::SingleQuotedString(v) | SqlValue::Number(v, _)) => {
+                Ok(v.as_str())
+            }
+            v => Err(anyhow!("{} expects literal arguments, got {}", name, v)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    match (name, args.as_slice()) {
+        ("regexp_like", [pattern]) => regexp_like(expr, pattern, None),
+        ("regexp_like", [pattern, flags]) => regexp_like(expr, pattern, Some(flags)),
+        ("regexp_extract", [pattern]) => regexp_extract(expr, pattern, None),
+        ("regexp_extract", [pattern, group]) => regexp_extract(expr, pattern, Some(group.parse()?)),
+        ("regexp_replace", [pattern, replacement]) => regexp_replace(expr, pattern, replacement),
+        ("contains", [text]) => Ok(contains(expr, text)),
+        _ => Err(anyhow!("wrong number of arguments for {}", name)),
+    }
+}
+
+/// a || b 和 CONCAT(a, b, ...)，参数先转成字符串再拼接，有一个是 null 结果就是 null
+pub(crate) fn concat(exprs: Vec<Expr>) -> Result<Expr> {
+    exprs
+        .into_iter()
+        .map(|v| v.cast(DataType::Utf8))
+        .reduce(|acc, v| acc + v)
+        .ok_or_else(|| anyhow!("CONCAT needs at least one argument"))
+}
+
+/// REGEXP_LIKE(expr, pattern [, flags])
+/// flags 支持 i（忽略大小写）、c（区分大小写，默认）、m（多行）、s（. 匹配换行）
+fn regexp_like(expr: Expr, pattern: &str, flags: Option<&str>) -> Result<Expr> {
+    let re = regex(&with_flags(pattern, flags.unwrap_or_default())?)?;
+    Ok(expr.map(
+        move |s| {
+            let mut ca: BooleanChunked = utf8(&s)?
+                .into_iter()
+                .map(|v| v.map(|v| re.is_match(v)))
+                .collect();
+            ca.rename(s.name());
+            Ok(ca.into_series())
+        },
+        Some(DataType::Boolean),
+    ))
+}
+
+/// REGEXP_EXTRACT(expr, pattern [, group])，没有匹配时返回 null
+/// 不指定 group 时，pattern 里有捕获分组就取第一个分组，否则取整个匹配
+fn regexp_extract(expr: Expr, pattern: &str, group: Option<usize>) -> Result<Expr> {
+    let re = regex(pattern)?;
+    let group = group.unwrap_or_else(|| if re.captures_len() > 1 { 1 } else { 0 });
+    if group >= re.captures_len() {
+        return Err(anyhow!(
+            "REGEXP_EXTRACT group {} does not exist in {}",
+            group,
+            pattern
+        ));
+    }
+
+    Ok(expr.map(
+        move |s| {
+            let ca = utf8(&s)?;
+            let mut ca: Utf8Chunked = ca
+                .into_iter()
+                .map(|v| {
+                    v.and_then(|v| re.captures(v))
+                        .and_then(|c| c.get(group))
+                        .map(|m| m.as_str())
+                })
+                .collect();
+            ca.rename(s.name());
+            Ok(ca.into_series())
+        },
+        Some(DataType::Utf8),
+    ))
+}
+
+/// REGEXP_REPLACE(expr, pattern, replacement)，替换所有匹配，replacement 里可以用 $1 引用分组
+fn regexp_replace(expr: Expr, pattern: &str, replacement: &str) -> Result<Expr> {
+    let re = regex(pattern)?;
+    let replacement = replacement.to_owned();
+    Ok(expr.map(
+        move |s| {
+            let ca = utf8(&s)?;
+            let mut ca: Utf8Chunked = ca
+                .into_iter()
+                .map(|v| v.map(|v| re.replace_all(v, replacement.as_str())))
+                .collect();
+            ca.rename(s.name());
+            Ok(ca.into_series())
+        },
+        Some(DataType::Utf8),
+    ))
+}
+
+/// CONTAINS(expr, text)，普通的子串匹配，text 里的字符没有特殊含义
+fn contains(expr: Expr, text: &str) -> Expr {
+    let text = text.to_owned();
+    expr.map(
+        move |s| {
+            let mut ca: BooleanChunked = utf8(&s)?
+                .into_iter()
+                .map(|v| v.map(|v| v.contains(text.as_str())))
+                .collect();
+            ca.rename(s.name());
+            Ok(ca.into_series())
+        },
+        Some(DataType::Boolean),
+    )
+}
+
+/// 把 flags 转换成正则的内联标记，比如 "i" 变成 (?i)
+fn with_flags(pattern: &str, flags: &str) -> Result<String> {
+    let mut inline = String::new();
+    for flag in flags.chars() {
+        match flag {
+            'i' | 'm' | 's' => inline.push(flag),
+            'c' => {}
+            v => return Err(unsupported!("regex flag {} is not supported", v)),
+        }
+    }
+    match inline.is_empty() {
+        true => Ok(pattern.to_owned()),
+        false => Ok(format!("(?{}){}", inline, pattern)),
+    }
+}
+
+/// 数字等其它类型的列先转成字符串再匹配
+fn utf8(s: &Series) -> polars::prelude::Result<Utf8Chunked> {
+    Ok(s.cast_with_dtype(&DataType::Utf8)?.utf8()?.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regex_functions_work() {
+        let df = DataFrame::new(vec![Series::new(
+            "name",
+            &[Some("Tyr Chen"), Some("lindsey 2021"), None],
+        )])
+        .unwrap();
+        let df = df
+            .lazy()
+            .select(vec![
+                regexp_like(col("name"), "^t", Some("i"))
+                    .unwrap()
+                    .alias("like"),
+                regexp_extract(col("name"), r"(\d+)", None)
+                    .unwrap()
+                    .alias("year"),
+                regexp_replace(col("name"), r"\s+", "_")
+                    .unwrap()
+                    .alias("replaced"),
+                contains(col("name"), "y ").alias("contains"),
+            ])
+            .collect()
+            .unwrap();
+
+        let like: Vec<_> = df
+            .column("like")
+            .unwrap()
+            .bool()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(like, vec![Some(true), Some(false), None]);
+        let year: Vec<_> = df
+            .column("year")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(year, vec![None, Some("2021"), None]);
+        let replaced: Vec<_> = df
+            .column("replaced")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(replaced, vec![Some("Tyr_Chen"), Some("lindsey_2021"), None]);
+        let contains: Vec<_> = df
+            .column("contains")
+            .unwrap()
+            .bool()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(contains, vec![Some(false), Some(true), None]);
+    }
+
+    #[test]
+    fn concat_works() {
+        let df = DataFrame::new(vec![
+            Series::new("name", &[Some("Tyr"), None]),
+            Series::new("age", &[18i64, 30]),
+        ])
+        .unwrap();
+        let df = df
+            .lazy()
+            .select(vec![concat(vec![col("name"), lit("-"), col("age")])
+                .unwrap()
+                .alias("v")])
+            .collect()
+            .unwrap();
+        let v: Vec<_> = df
+            .column("v")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(v, vec![Some("Tyr-18"), None]);
+        assert!(concat(vec![]).is_err());
+    }
+
+    #[test]
+    fn call_works() {
+        let args = vec![SqlExpr::Value(SqlValue::SingleQuotedString("^t".into()))];
+        assert!(call("regexp_like", col("name"), &args).is_ok());
+        assert!(call("regexp_replace", col("name"), &args).is_err());
+        let args = vec![SqlExpr::Identifier("pattern".into())];
+        assert!(call("regexp_like", col("name"), &args).is_err());
+    }
+
+    #[test]
+    fn invalid_regex_should_fail() {
+        assert!(regexp_like(col("name"), "(", None).is_err());
+        assert!(regexp_like(col("name"), "a", Some("q")).is_err());
+        assert!(regexp_extract(col("name"), "a(b)", Some(2)).is_err());
+        assert!(regex("a+").unwrap().is_match("caab"));
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, Result};
+use polars::prelude::*;
+
+/// 用户自定义的标量函数，输入和输出都是整列的 Series
+pub type ScalarFn = dyn Fn(&[Series]) -> Result<Series> + Send + Sync;
+
+/// 注册到全局的标量函数，参数和返回值的类型在注册时声明
+pub(crate) struct ScalarFunction {
+    pub(crate) name: String,
+    pub(crate) args: Vec<DataType>,
+    pub(crate) returns: DataType,
+    pub(crate) f: Arc<ScalarFn>,
+}
+
+impl ScalarFunction {
+    /// 转换成 polars 的 Function 表达式，调用前把参数转换成声明的类型
+    pub(crate) fn to_expr(&self, input: Vec<Expr>) -> Result<Expr> {
+        if input.len() != self.args.len() {
+            return Err(anyhow!(
+                "function {} expects {} arguments, got {}",
+                self.name,
+                self.args.len(),
+                input.len()
+            ));
+        }
+
+        let name = self.name.clone();
+        let args = self.args.clone();
+        let returns = self.returns.clone();
+        let f = self.f.clone();
+        let function = move |s: &mut [Series]| {
+            let series = s
+                .iter()
+                .zip(args.iter())
+                .map(|(s, dtype)| s.cast_with_dtype(dtype))
+                .collect::<polars::prelude::Result<Vec<_>>>()?;
+            let result = f(&series).map_err(|e| {
+                PolarsError::Other(format!("function {} failed: {}", name, e).into())
+            })?;
+            result.cast_with_dtype(&returns)
+        };
+
+        Ok(Expr::Function {
+            input,
+            function: NoEq::new(Arc::new(function)),
+            output_type: Some(self.returns.clone()),
+            options: function_options(),
+        })
+    }
+}
+
+// FunctionOptions 的字段在 polars 里是私有的，从 map 生成的表达式里拿一份默认值
+fn function_options() -> FunctionOptions {
+    match lit(0i64).map(Ok, None) {
+        Expr::Function { options, .. } => options,
+        _ => unreachable!("map always creates a function expression"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scalar_function_works() {
+        let double = ScalarFunction {
+            name: "double".into(),
+            args: vec![DataType::Float64],
+            returns: DataType::Float64,
+            f: Arc::new(|s: &[Series]| Ok(&s[0] * 2)),
+        };
+
+        let df = DataFrame::new(vec![Series::new("a", &[1i64, 2, 3])]).unwrap();
+        let expr = double.to_expr(vec![col("a")]).unwrap();
+        let result = df.lazy().select(vec![expr]).collect().unwrap();
+        let values: Vec<f64> = result[0].f64().unwrap().into_no_null_iter().collect();
+        assert_eq!(values, vec![2.0, 4.0, 6.0]);
+
+        assert!(double.to_expr(vec![col("a"), col("a")]).is_err());
+    }
+}
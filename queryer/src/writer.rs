@@ -0,0 +1,177 @@
+// Copyright 2023 lzd
+// 
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+// 
+//     http://www.apache.org/licenses/LICENSE-2.0
+// 
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{error::unsupported, DataSet};
+use anyhow::{anyhow, Result};
+use polars::prelude::*;
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+/// INSERT / COPY 支持的输出格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Csv,
+    Parquet,
+    // arrow ipc 文件
+    Ipc,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "parquet" => Ok(Self::Parquet),
+            "ipc" | "arrow" | "feather" => Ok(Self::Ipc),
+            v => Err(unsupported!("Output format {} is not supported", v)),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// 根据文件扩展名推断输出格式
+    pub fn from_path(path: &str) -> Option<Self> {
+        Path::new(path).extension()?.to_str()?.parse().ok()
+    }
+}
+
+/// 把 DataSet 写到 target，返回写入的行数
+/// append 为 true 时追加到已有的文件后面，否则覆盖
+pub(crate) async fn write_data(
+    ds: DataSet,
+    target: &str,
+    format: Option<&str>,
+    append: bool,
+) -> Result<usize> {
+    let path = target
+        .strip_prefix("file://")
+        .ok_or_else(|| unsupported!("We only support writing to file:// at the moment"))?
+        .to_owned();
+    let format = match format {
+        Some(v) => v.parse()?,
+        None => OutputFormat::from_path(&path)
+            .ok_or_else(|| anyhow!("Cannot infer output format of {}, use FORMAT", target))?,
+    };
+
+    // polars 的 writer 都是同步的，放到 blocking 线程池里跑
+    tokio::task::spawn_blocking(move || -> Result<usize> {
+        let rows = ds.height();
+        let exists = Path::new(&path)
+            .metadata()
+            .map(|m| m.len() > 0)
+            .unwrap_or(false);
+        match (format, append && exists) {
+            (OutputFormat::Csv, true) => append_csv(&ds, &path)?,
+            (OutputFormat::Csv, false) => CsvWriter::new(File::create(&path)?).finish(&ds)?,
+            // parquet / ipc 不能直接在文件后面追加，只能读出来合并后重写
+            (OutputFormat::Parquet, true) => {
+                let old = ParquetReader::new(File::open(&path)?).finish()?;
+                ParquetWriter::new(File::create(&path)?).finish(&old.vstack(&ds)?)?
+            }
+            (OutputFormat::Parquet, false) => {
+                ParquetWriter::new(File::create(&path)?).finish(&ds)?
+            }
+            (OutputFormat::Ipc, true) => {
+                let old = IpcReader::new(File::open(&path)?).finish()?;
+                IpcWriter::new(File::create(&path)?).finish(&old.vstack(&ds)?)?
+            }
+            (OutputFormat::Ipc, false) => IpcWriter::new(File::create(&path)?).finish(&ds)?,
+        }
+        Ok(rows)
+    })
+    .await?
+}
+
+/// 追加写 csv，已有文件的表头要和 DataSet 的列一致
+fn append_csv(ds: &DataSet, path: &str) -> Result<()> {
+    let mut header = String::new();
+    BufReader::new(File::open(path)?).read_line(&mut header)?;
+    let columns = ds.get_column_names().join(",");
+    if header.trim_end() != columns {
+        return Err(anyhow!(
+            "Cannot append columns [{}] to {} with columns [{}]",
+            columns,
+            path,
+            header.trim_end()
+        ));
+    }
+
+    let file = OpenOptions::new().append(true).open(path)?;
+    CsvWriter::new(file).has_headers(false).finish(ds)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn people() -> DataSet {
+        let df = DataFrame::new(vec![
+            Series::new("name", &["Tyr", "Lindsey"]),
+            Series::new("age", &[18i64, 30]),
+        ])
+        .unwrap();
+        df.into()
+    }
+
+    #[test]
+    fn output_format_works() {
+        assert_eq!(
+            OutputFormat::from_path("/tmp/a.csv"),
+            Some(OutputFormat::Csv)
+        );
+        assert_eq!(
+            OutputFormat::from_path("/tmp/a.parquet"),
+            Some(OutputFormat::Parquet)
+        );
+        assert_eq!(OutputFormat::from_path("/tmp/a"), None);
+        assert!("xlsx".parse::<OutputFormat>().is_err());
+    }
+
+    #[tokio::test]
+    async fn write_csv_works() {
+        let path = std::env::temp_dir().join("queryer_write.csv");
+        let target = format!("file://{}", path.display());
+
+        assert_eq!(write_data(people(), &target, None, false).await.unwrap(), 2);
+        assert_eq!(write_data(people(), &target, None, true).await.unwrap(), 2);
+        let data = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(data, "name,age\nTyr,18\nLindsey,30\nTyr,18\nLindsey,30\n");
+
+        // 覆盖写
+        write_data(people(), &target, Some("csv"), false)
+            .await
+            .unwrap();
+        let data = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(data, "name,age\nTyr,18\nLindsey,30\n");
+    }
+
+    #[tokio::test]
+    async fn write_parquet_works() {
+        let path = std::env::temp_dir().join("queryer_write.parquet");
+        let target = format!("file://{}", path.display());
+
+        write_data(people(), &target, None, false).await.unwrap();
+        write_data(people(), &target, None, true).await.unwrap();
+        let df = ParquetReader::new(File::open(&path).unwrap())
+            .finish()
+            .unwrap();
+        assert_eq!(df.height(), 4);
+    }
+}
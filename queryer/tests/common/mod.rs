@@ -0,0 +1,59 @@
+// 集成测试共用的工具函数
+
+use polars::prelude::*;
+use queryer::DataSet;
+use serde_json::{Number, Value};
+use std::path::PathBuf;
+
+/// tests/fixtures 目录，测试用的数据文件都在这里
+pub fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+}
+
+/// 把 DataSet 转换成一行行的 json 值，方便和期望的结果以及 SQLite 的结果比较
+pub fn to_rows(ds: &DataSet) -> Vec<Vec<Value>> {
+    (0..ds.height())
+        .map(|i| ds.get_columns().iter().map(|s| to_json(s.get(i))).collect())
+        .collect()
+}
+
+fn to_json(value: AnyValue) -> Value {
+    match value {
+        AnyValue::Null => Value::Null,
+        AnyValue::Boolean(v) => Value::Bool(v),
+        AnyValue::Utf8(v) => Value::String(v.to_owned()),
+        AnyValue::Int8(v) => v.into(),
+        AnyValue::Int16(v) => v.into(),
+        AnyValue::Int32(v) => v.into(),
+        AnyValue::Int64(v) => v.into(),
+        AnyValue::UInt8(v) => v.into(),
+        AnyValue::UInt16(v) => v.into(),
+        AnyValue::UInt32(v) => v.into(),
+        AnyValue::UInt64(v) => v.into(),
+        AnyValue::Float32(v) => float(v as f64),
+        AnyValue::Float64(v) => float(v),
+        // 日期等其它类型按显示的文本比较
+        v => Value::String(v.to_string()),
+    }
+}
+
+pub fn float(v: f64) -> Value {
+    Number::from_f64(v).map_or(Value::Null, Value::Number)
+}
+
+/// 两组行是否一样，数字都按 f64 比较，允许浮点运算带来的很小的误差
+pub fn same_rows(a: &[Vec<Value>], b: &[Vec<Value>]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(x, y)| x.len() == y.len() && x.iter().zip(y).all(|(x, y)| same_value(x, y)))
+}
+
+fn same_value(a: &Value, b: &Value) -> bool {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0),
+        _ => a == b,
+    }
+}
@@ -0,0 +1,183 @@
+// 差分测试：随机生成查询，分别交给 queryer（读 tests/fixtures/orders.csv）和 SQLite（导入同样的数据）执行，
+// 比较两边的结果。种子是固定的，失败时可以复现；生成的查询数可以用 QUERYER_DIFF_CASES 修改
+
+mod common;
+
+use common::{fixtures, float, same_rows, to_rows};
+use queryer::Session;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rusqlite::{
+    params_from_iter,
+    types::{Value as SqlValue, ValueRef},
+    Connection,
+};
+use serde_json::Value;
+use std::{env, fs};
+
+// orders.csv 的列和导入 SQLite 时的类型，discount 有空值
+const COLUMNS: [(&str, &str); 7] = [
+    ("order_id", "INTEGER"),
+    ("customer", "TEXT"),
+    ("product", "TEXT"),
+    ("quantity", "INTEGER"),
+    ("price", "REAL"),
+    ("discount", "REAL"),
+    ("region", "TEXT"),
+];
+const CUSTOMERS: [&str; 7] = [
+    "acme", "globex", "initech", "umbrella", "hooli", "stark", "wayne",
+];
+const PRODUCTS: [&str; 7] = ["anvil", "bolt", "cable", "drill", "easel", "funnel", "gear"];
+const REGIONS: [&str; 4] = ["north", "south", "east", "west"];
+const OPERATORS: [&str; 6] = ["=", "!=", "<", "<=", ">", ">="];
+// 除了原始的列，投影里也会出现简单的算术
+const PROJECTIONS: [&str; 9] = [
+    "order_id",
+    "customer",
+    "product",
+    "quantity",
+    "price",
+    "discount",
+    "region",
+    "quantity * 2 AS twice",
+    "price + 1.5 AS bumped",
+];
+
+#[tokio::test]
+async fn queryer_matches_sqlite() {
+    let conn = load_sqlite();
+    let cases = env::var("QUERYER_DIFF_CASES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(200);
+    let source = format!("file://{}", fixtures().join("orders.csv").display());
+    let session = Session::default();
+    let mut rng = StdRng::seed_from_u64(20230901);
+
+    for i in 0..cases {
+        let sql = generate(&mut rng);
+        let expected = sqlite_rows(&conn, &sql.replace("{source}", "orders"));
+        let actual = session
+            .query(sql.replace("{source}", &source))
+            .await
+            .unwrap_or_else(|e| panic!("case {} failed: {}\n{:#}", i, sql, e));
+        let actual = to_rows(&actual);
+        assert!(
+            same_rows(&actual, &expected),
+            "case {}: {}\n  queryer: {:?}\n  sqlite:  {:?}",
+            i,
+            sql,
+            actual,
+            expected
+        );
+    }
+}
+
+/// 把 orders.csv 导入内存里的 SQLite，空字段是 NULL
+fn load_sqlite() -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    let columns: Vec<String> = COLUMNS
+        .iter()
+        .map(|(name, ty)| format!("{} {}", name, ty))
+        .collect();
+    conn.execute(&format!("CREATE TABLE orders ({})", columns.join(", ")), [])
+        .unwrap();
+
+    let data = fs::read_to_string(fixtures().join("orders.csv")).unwrap();
+    {
+        let placeholders = vec!["?"; COLUMNS.len()].join(", ");
+        let mut stmt = conn
+            .prepare(&format!("INSERT INTO orders VALUES ({})", placeholders))
+            .unwrap();
+        for line in data.lines().skip(1) {
+            let values = line
+                .split(',')
+                .zip(COLUMNS.iter())
+                .map(|(v, (_, ty))| match (v, *ty) {
+                    ("", _) => SqlValue::Null,
+                    (v, "INTEGER") => SqlValue::Integer(v.parse().unwrap()),
+                    (v, "REAL") => SqlValue::Real(v.parse().unwrap()),
+                    (v, _) => SqlValue::Text(v.to_owned()),
+                });
+            stmt.execute(params_from_iter(values)).unwrap();
+        }
+    }
+    conn
+}
+
+fn sqlite_rows(conn: &Connection, sql: &str) -> Vec<Vec<Value>> {
+    let mut stmt = conn.prepare(sql).unwrap();
+    let count = stmt.column_count();
+    let mut rows = stmt.query([]).unwrap();
+    let mut result = Vec::new();
+    while let Some(row) = rows.next().unwrap() {
+        let values = (0..count)
+            .map(|i| match row.get_ref(i).unwrap() {
+                ValueRef::Null => Value::Null,
+                ValueRef::Integer(v) => v.into(),
+                ValueRef::Real(v) => float(v),
+                ValueRef::Text(v) => Value::String(String::from_utf8_lossy(v).into_owned()),
+                ValueRef::Blob(_) => unreachable!("orders has no blob column"),
+            })
+            .collect();
+        result.push(values);
+    }
+    result
+}
+
+/// 生成一条查询，数据源写成 {source}，按 order_id 排序，结果的顺序是确定的
+fn generate(rng: &mut StdRng) -> String {
+    let count = rng.gen_range(1..=4);
+    let projection: Vec<&str> = PROJECTIONS.choose_multiple(rng, count).copied().collect();
+    let mut sql = format!("SELECT {} FROM {{source}}", projection.join(", "));
+    if rng.gen_bool(0.8) {
+        sql.push_str(&format!(" WHERE {}", predicate(rng, 2)));
+    }
+    let desc = if rng.gen_bool(0.3) { " DESC" } else { "" };
+    sql.push_str(&format!(" ORDER BY order_id{}", desc));
+    if rng.gen_bool(0.3) {
+        sql.push_str(&format!(" LIMIT {}", rng.gen_range(1..20)));
+        if rng.gen_bool(0.5) {
+            sql.push_str(&format!(" OFFSET {}", rng.gen_range(0..20)));
+        }
+    }
+    sql
+}
+
+/// 生成 AND / OR 组合的条件，discount 上的比较结果可能是 NULL，
+/// polars 的 AND / OR 和 SQL 一样是三值逻辑，NULL OR TRUE 是 TRUE
+fn predicate(rng: &mut StdRng, depth: usize) -> String {
+    if depth == 0 || rng.gen_bool(0.4) {
+        return atom(rng);
+    }
+    let left = predicate(rng, depth - 1);
+    let right = predicate(rng, depth - 1);
+    let op = if rng.gen_bool(0.5) { "OR" } else { "AND" };
+    format!("({}) {} ({})", left, op, right)
+}
+
+fn atom(rng: &mut StdRng) -> String {
+    let op = OPERATORS.choose(rng).unwrap();
+    match rng.gen_range(0..6) {
+        0 => format!("order_id {} {}", op, rng.gen_range(0..=61)),
+        1 => format!("quantity {} {}", op, rng.gen_range(0..=21)),
+        2 => format!("price {} {:.2}", op, rng.gen_range(1.0..100.0)),
+        3 => {
+            let (column, values): (&str, &[&str]) = match rng.gen_range(0..3) {
+                0 => ("customer", &CUSTOMERS),
+                1 => ("product", &PRODUCTS),
+                _ => ("region", &REGIONS),
+            };
+            let value = values.choose(rng).unwrap();
+            format!("{} {} '{}'", column, op, value)
+        }
+        4 => match rng.gen_bool(0.5) {
+            true => "discount IS NULL".to_string(),
+            false => "discount IS NOT NULL".to_string(),
+        },
+        _ => {
+            let value = [0.05, 0.1, 0.15, 0.2, 0.25].choose(rng).unwrap();
+            format!("discount {} {}", op, value)
+        }
+    }
+}
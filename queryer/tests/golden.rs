@@ -0,0 +1,73 @@
+// golden 测试：tests/golden 下的每个 .sql 文件是一条查询，同名的 .json 文件是期望的结果，
+// 格式是 {"columns": [...], "rows": [[...], ...]}，SQL 里的 {fixtures} 会换成 tests/fixtures 目录
+// 行为有意改变、需要更新期望结果时运行：QUERYER_BLESS=1 cargo test --test golden
+
+mod common;
+
+use common::{fixtures, same_rows, to_rows};
+use queryer::Session;
+use serde_json::{json, Value};
+use std::{env, fs, path::PathBuf};
+
+#[tokio::test]
+async fn golden_queries_work() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden");
+    let bless = env::var("QUERYER_BLESS").is_ok();
+    let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "sql"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let fixtures = fixtures().display().to_string();
+    let mut failures = Vec::new();
+    for path in paths {
+        let sql = fs::read_to_string(&path)
+            .unwrap()
+            .trim()
+            .replace("{fixtures}", &fixtures);
+        let ds = match Session::default().query(&sql).await {
+            Ok(ds) => ds,
+            Err(e) => {
+                failures.push(format!("{}: {:#}", path.display(), e));
+                continue;
+            }
+        };
+        let columns = ds.get_column_names();
+        let rows = to_rows(&ds);
+
+        let expected_path = path.with_extension("json");
+        if bless {
+            let actual = json!({ "columns": columns, "rows": rows });
+            let data = serde_json::to_string_pretty(&actual).unwrap() + "\n";
+            fs::write(&expected_path, data).unwrap();
+            continue;
+        }
+
+        let expected: Value =
+            serde_json::from_str(&fs::read_to_string(&expected_path).unwrap()).unwrap();
+        let expected_columns: Vec<String> =
+            serde_json::from_value(expected["columns"].clone()).unwrap();
+        let expected_rows: Vec<Vec<Value>> =
+            serde_json::from_value(expected["rows"].clone()).unwrap();
+        if columns != expected_columns || !same_rows(&rows, &expected_rows) {
+            failures.push(format!(
+                "{}:\n  expected: {:?} {:?}\n  actual:   {:?} {:?}",
+                path.display(),
+                expected_columns,
+                expected_rows,
+                columns,
+                rows
+            ));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "golden queries failed:\n{}",
+        failures.join("\n")
+    );
+}
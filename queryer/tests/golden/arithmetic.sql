@@ -0,0 +1 @@
+SELECT order_id, quantity * 2 AS twice, price + 1.5 AS bumped FROM file://{fixtures}/orders.csv WHERE order_id < 4 ORDER BY order_id
@@ -0,0 +1 @@
+SELECT name, age FROM file://{fixtures}/people.csv WHERE age > 30 ORDER BY age
@@ -0,0 +1 @@
+SELECT name FROM file://{fixtures}/people.csv WHERE city = 'Paris' AND (score > 90 OR age < 30) ORDER BY name
@@ -0,0 +1 @@
+SELECT name || ' from ' || city AS label FROM file://{fixtures}/people.csv WHERE id <= 2 ORDER BY id
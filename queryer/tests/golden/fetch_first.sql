@@ -0,0 +1 @@
+SELECT id, age FROM file://{fixtures}/people.csv ORDER BY age FETCH FIRST 2 ROWS ONLY
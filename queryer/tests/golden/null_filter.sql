@@ -0,0 +1 @@
+SELECT id, name, score FROM file://{fixtures}/people.csv WHERE score IS NULL ORDER BY id
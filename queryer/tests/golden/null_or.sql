@@ -0,0 +1 @@
+SELECT id, name FROM file://{fixtures}/people.csv WHERE score > 90 OR age < 28 ORDER BY id
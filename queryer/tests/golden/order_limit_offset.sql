@@ -0,0 +1 @@
+SELECT name, city FROM file://{fixtures}/people.csv ORDER BY age DESC LIMIT 3 OFFSET 1
@@ -0,0 +1 @@
+SELECT name, REGEXP_EXTRACT(city, '^(.)') AS initial FROM file://{fixtures}/people.csv WHERE REGEXP_LIKE(name, '^[A-D]') ORDER BY id
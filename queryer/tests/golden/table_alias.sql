@@ -0,0 +1 @@
+SELECT p.name AS who, p.age FROM file://{fixtures}/people.csv AS p WHERE p.city = 'London' ORDER BY p.age DESC